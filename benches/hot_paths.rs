@@ -0,0 +1,275 @@
+// Benchmarks of the distance fields, ray marching and rendering, for
+// comparing the speed of changes to them. Run with `cargo bench`.
+
+#[path = "../src/scene1.rs"]
+mod scene1;
+#[path = "../src/scene10.rs"]
+mod scene10;
+#[path = "../src/scene2.rs"]
+mod scene2;
+#[path = "../src/scene3.rs"]
+mod scene3;
+#[path = "../src/scene4.rs"]
+mod scene4;
+#[path = "../src/scene5.rs"]
+mod scene5;
+#[path = "../src/scene6.rs"]
+mod scene6;
+#[path = "../src/scene7.rs"]
+mod scene7;
+#[path = "../src/scene8.rs"]
+mod scene8;
+#[path = "../src/scene9.rs"]
+mod scene9;
+
+use raymarching_pathtracer::{background, camera, export, renderer, sdf, texture, union_all};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use glam::{vec3, Vec3};
+use sdf::{Bounds, DistInfo, Material, RayMask, Sdf, SdfMap};
+
+type CreateScene = fn(f32) -> renderer::Scene;
+
+const SCENES: [(&str, CreateScene); 10] = [
+    ("scene1", scene1::create_scene),
+    ("scene2", scene2::create_scene),
+    ("scene3", scene3::create_scene),
+    ("scene4", scene4::create_scene),
+    ("scene5", scene5::create_scene),
+    ("scene6", scene6::create_scene),
+    ("scene7", scene7::create_scene),
+    ("scene8", scene8::create_scene),
+    ("scene9", scene9::create_scene),
+    ("scene10", scene10::create_scene),
+];
+
+// A fixed grid of points around the origin, where the scenes are.
+fn grid_points() -> Vec<Vec3> {
+    let n = 8;
+    let coordinate = |i: i32| -4.0 + 8.0 * (i as f32 + 0.5) / n as f32;
+    (0..n)
+        .flat_map(|i| (0..n).flat_map(move |j| (0..n).map(move |k| (i, j, k))))
+        .map(|(i, j, k)| vec3(coordinate(i), coordinate(j), coordinate(k)))
+        .collect()
+}
+
+fn bench_dist(c: &mut Criterion) {
+    let points = grid_points();
+    let mut group = c.benchmark_group("dist");
+
+    for (name, create_scene) in SCENES {
+        let scene = create_scene(1.0);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                points
+                    .iter()
+                    .map(|p| scene.map.dist(black_box(*p), RayMask::ALL))
+                    .sum::<f32>()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_ray_intersection(c: &mut Criterion) {
+    let material = Material::Lambertian {
+        color: Vec3::splat(0.5),
+    };
+    let maps: [(&str, Box<dyn SdfMap>); 3] = [
+        (
+            "sphere",
+            Box::new(sdf::sphere(1.0).material(material.clone())),
+        ),
+        (
+            "plane",
+            Box::new(sdf::plane(Vec3::Z).material(material.clone())),
+        ),
+        (
+            "mandelbulb",
+            Box::new(
+                sdf::Mandelbulb
+                    .clip((Vec3::splat(-1.2), Vec3::splat(1.2)))
+                    .material(material),
+            ),
+        ),
+    ];
+
+    // A fan of rays from a fixed point towards the origin, some of which miss
+    // the sphere and the Mandelbulb.
+    let origin = vec3(0.0, -3.0, 2.0);
+    let directions: Vec<Vec3> = (0..64)
+        .map(|i| {
+            let x = -0.6 + 1.2 * (i % 8) as f32 / 7.0;
+            let y = -0.6 + 1.2 * (i / 8) as f32 / 7.0;
+            (vec3(x, 0.0, y) - origin).normalize()
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("ray_intersection");
+
+    for (name, map) in &maps {
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                directions
+                    .iter()
+                    .filter(|direction| {
+                        map.ray_intersection(origin, black_box(**direction), RayMask::CAMERA, 1.0)
+                            .is_some()
+                    })
+                    .count()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// Normals at points on the Mandelbulb, which has no gradient in closed form.
+// Central differences take six evaluations of the distance, and forward
+// differences from the distance known at a hit take three.
+fn bench_normal(c: &mut Criterion) {
+    let mandelbulb = sdf::Mandelbulb
+        .clip((Vec3::splat(-1.2), Vec3::splat(1.2)))
+        .material(Material::Lambertian {
+            color: Vec3::splat(0.5),
+        });
+    let origin = vec3(0.0, -3.0, 0.5);
+    let hits: Vec<sdf::HitInfo> = (0..64)
+        .filter_map(|i| {
+            let x = -0.6 + 1.2 * (i % 8) as f32 / 7.0;
+            let y = -0.6 + 1.2 * (i / 8) as f32 / 7.0;
+            let direction = (vec3(x, 0.0, y) - origin).normalize();
+            mandelbulb.ray_intersection(origin, direction, RayMask::CAMERA, 1.0)
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("normal");
+    group.bench_function("central", |b| {
+        b.iter(|| {
+            hits.iter()
+                .map(|hit| mandelbulb.normal(black_box(hit.position), RayMask::CAMERA))
+                .fold(Vec3::ZERO, |sum, normal| sum + normal)
+        })
+    });
+    group.bench_function("forward", |b| {
+        b.iter(|| {
+            hits.iter()
+                .map(|hit| {
+                    mandelbulb.normal_cached(black_box(hit.position), hit.distance, RayMask::CAMERA)
+                })
+                .fold(Vec3::ZERO, |sum, normal| sum + normal)
+        })
+    });
+    group.finish();
+}
+
+// A map that marches every ray through its bounds, without first testing
+// the ray against the bounds of each object in it.
+struct Unculled<M>(M);
+
+impl<M: SdfMap> SdfMap for Unculled<M> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.0.dist(p, mask)
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.0.bounds()
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        self.0.distinfo(p, mask)
+    }
+}
+
+// Rays through a scene of a few small objects far apart, most of which miss
+// all of them, with and without culling the objects a ray can't hit.
+fn bench_sparse(c: &mut Criterion) {
+    let material = Material::Lambertian {
+        color: Vec3::splat(0.5),
+    };
+    let objects = || {
+        union_all![
+            sdf::sphere(0.3)
+                .position(vec3(-2.0, 0.0, -2.0))
+                .material(material.clone()),
+            sdf::sphere(0.2)
+                .position(vec3(1.5, 0.5, -2.0))
+                .material(material.clone()),
+            sdf::cuboid(Vec3::splat(0.3))
+                .position(vec3(2.0, 0.0, 2.0))
+                .material(material.clone()),
+            sdf::torus(0.4, 0.1)
+                .position(vec3(0.0, 0.0, 2.2))
+                .material(material.clone()),
+            sdf::sphere(0.4).material(material.clone())
+        ]
+    };
+    let maps: [(&str, Box<dyn SdfMap>); 2] = [
+        ("culled", Box::new(objects())),
+        ("unculled", Box::new(Unculled(objects()))),
+    ];
+
+    let origin = vec3(0.3, -6.0, 0.4);
+    let directions: Vec<Vec3> = (0..1024)
+        .map(|i| {
+            let x = -3.0 + 6.0 * (i % 32) as f32 / 31.0;
+            let y = -3.0 + 6.0 * (i / 32) as f32 / 31.0;
+            (vec3(x, 0.0, y) - origin).normalize()
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("sparse");
+
+    for (name, map) in &maps {
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                directions
+                    .iter()
+                    .filter(|direction| {
+                        map.ray_intersection(origin, black_box(**direction), RayMask::CAMERA, 1.0)
+                            .is_some()
+                    })
+                    .count()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// The samples within each pixel come from a Sobol sequence seeded by the
+// index of the pixel, so every run traces the same camera rays and takes its
+// first bounces in the same directions. Later decisions along the paths use
+// the thread's random number generator and vary from run to run.
+fn bench_render(c: &mut Criterion) {
+    let settings = renderer::RenderSettings {
+        width: 32,
+        height: 24,
+        sample_count: 4,
+        threads: Some(1),
+        ..renderer::RenderSettings::default()
+    };
+    let mut scene = scene1::create_scene(settings.aspect_ratio());
+    scene.camera.set_aspect_ratio(settings.aspect_ratio());
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    // The pool is built outside the timed loop. Without it, the render would
+    // spread over the global pool whatever `threads` says.
+    let pool = settings.thread_pool().unwrap().unwrap();
+    group.bench_function("scene1", |b| {
+        b.iter(|| pool.install(|| renderer::render(&settings, &scene)))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_dist,
+    bench_ray_intersection,
+    bench_normal,
+    bench_sparse,
+    bench_render
+);
+criterion_main!(benches);
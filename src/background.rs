@@ -0,0 +1,269 @@
+use super::camera::Ray;
+use super::error::RenderError;
+use super::light::Light;
+use super::sampling::{self, Sampler};
+use super::texture::{Image, Projection, Wrap};
+use glam::{const_vec3, vec3, Vec3};
+use std::f32::consts::PI;
+
+const SKY_SCALE: f32 = 0.08;
+const SUN_COS_RADIUS: f32 = 0.9995;
+const SUN_RADIANCE: f32 = 40.0;
+const STUDIO_GRAY: Vec3 = const_vec3!([0.5, 0.5, 0.5]);
+const GRADIENT_GROUND: Vec3 = const_vec3!([0.05, 0.045, 0.04]);
+const GRADIENT_HORIZON: Vec3 = const_vec3!([0.8, 0.85, 0.9]);
+const GRADIENT_ZENITH: Vec3 = const_vec3!([0.25, 0.45, 0.85]);
+
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        (1.0 + self.a * (self.b / cos_theta).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    let cx = x * luminance / y;
+    let cz = (1.0 - x - y) * luminance / y;
+    vec3(
+        3.2406 * cx - 1.5372 * luminance - 0.4986 * cz,
+        -0.9689 * cx + 1.8758 * luminance + 0.0415 * cz,
+        0.0557 * cx - 0.2040 * luminance + 1.0570 * cz,
+    )
+    .max(Vec3::ZERO)
+}
+
+/// The radiance of the background along a ray escaping the scene. The origin
+/// allows for effects like haze that thickens towards the ground:
+///
+/// ```
+/// use glam::{vec3, Vec3};
+/// use raymarching_pathtracer::{camera::{Camera, Ray}, renderer::*, sdf::*};
+///
+/// let scene = |z: f32| {
+///     let camera = Camera::new(vec3(0.0, 0.0, z), vec3(0.0, 1.0, z), Vec3::Z, 0.5, 1.0, 0.0);
+///     let white = Material::Lambertian { color: Vec3::ONE };
+///     let mut scene = Scene::new(camera, sphere(1.0).material(white));
+///     scene.background_color = Box::new(|ray: &Ray| Vec3::splat((0.1 * ray.origin.z).min(1.0)));
+///     scene
+/// };
+/// let settings = RenderSettings { width: 4, height: 4, sample_count: 16, ..Default::default() };
+/// let mean = |z| render(&settings, &scene(z)).unwrap().iter().flatten().sum::<Vec3>() / 16.0;
+/// assert!((mean(2.0).dot(Vec3::ONE) / 3.0 - 0.2).abs() < 0.01);
+/// assert!((mean(8.0).dot(Vec3::ONE) / 3.0 - 0.8).abs() < 0.04);
+/// ```
+pub type Background = dyn Fn(&Ray) -> Vec3 + Sync;
+
+// Makes a background out of a function of the direction only, like `sky`.
+pub fn directional(background: impl Fn(Vec3) -> Vec3 + Sync + 'static) -> Box<Background> {
+    Box::new(move |ray: &Ray| background(ray.direction))
+}
+
+// Preetham et al., "A Practical Analytic Model for Daylight" (1999). The
+// z-axis is up, as in the sample scenes.
+pub fn sky(sun_dir: Vec3, turbidity: f32) -> Box<dyn Fn(Vec3) -> Vec3 + Sync> {
+    let t = turbidity;
+    let sun_dir = sun_dir.normalize();
+    let theta_s = sun_dir.z.clamp(0.0, 1.0).acos();
+
+    let perez_y = Perez {
+        a: 0.1787 * t - 1.4630,
+        b: -0.3554 * t + 0.4275,
+        c: -0.0227 * t + 5.3251,
+        d: 0.1206 * t - 2.5771,
+        e: -0.0670 * t + 0.3703,
+    };
+    let perez_x = Perez {
+        a: -0.0193 * t - 0.2592,
+        b: -0.0665 * t + 0.0008,
+        c: -0.0004 * t + 0.2125,
+        d: -0.0641 * t - 0.8989,
+        e: -0.0033 * t + 0.0452,
+    };
+    let perez_yy = Perez {
+        a: -0.0167 * t - 0.2608,
+        b: -0.0950 * t + 0.0092,
+        c: -0.0079 * t + 0.2102,
+        d: -0.0441 * t - 1.6537,
+        e: -0.0109 * t + 0.0529,
+    };
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+    let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+    let poly = |c: [f32; 4]| ((c[0] * theta_s + c[1]) * theta_s + c[2]) * theta_s + c[3];
+    let zenith_x = t * t * poly([0.00166, -0.00375, 0.00209, 0.0])
+        + t * poly([-0.02903, 0.06377, -0.03202, 0.00394])
+        + poly([0.11693, -0.21196, 0.06052, 0.25886]);
+    let zenith_yy = t * t * poly([0.00275, -0.00610, 0.00317, 0.0])
+        + t * poly([-0.04214, 0.08970, -0.04153, 0.00516])
+        + poly([0.15346, -0.26756, 0.06670, 0.26688]);
+
+    let norm_y = zenith_y / perez_y.eval(1.0, theta_s);
+    let norm_x = zenith_x / perez_x.eval(1.0, theta_s);
+    let norm_yy = zenith_yy / perez_yy.eval(1.0, theta_s);
+
+    Box::new(move |direction: Vec3| {
+        let cos_gamma = direction.dot(sun_dir);
+
+        if cos_gamma > SUN_COS_RADIUS {
+            return SUN_RADIANCE * vec3(1.0, 0.85, 0.75);
+        }
+
+        let cos_theta = direction.z.max(0.01);
+        let gamma = cos_gamma.clamp(-1.0, 1.0).acos();
+
+        let luminance = norm_y * perez_y.eval(cos_theta, gamma);
+        let x = norm_x * perez_x.eval(cos_theta, gamma);
+        let y = norm_yy * perez_yy.eval(cos_theta, gamma);
+
+        SKY_SCALE * xyy_to_rgb(x, y, luminance)
+    })
+}
+
+// A sun disk on top of the given sky. The disk is also returned as a light, so
+// that the renderer can sample it directly instead of finding it by chance.
+pub fn sun_light(
+    direction: Vec3,
+    cos_radius: f32,
+    radiance: Vec3,
+    sky: impl Fn(Vec3) -> Vec3 + Sync + 'static,
+) -> (Box<Background>, Light) {
+    let direction = direction.normalize();
+
+    let light = Light::Directional {
+        direction,
+        cos_radius,
+        radiance,
+    };
+
+    let background = directional(
+        move |d: Vec3| {
+            if light.covers(d) {
+                radiance
+            } else {
+                sky(d)
+            }
+        },
+    );
+
+    (background, light)
+}
+
+// The sun low in the direction of the x-axis over a flat blue sky, as in most
+// of the sample scenes.
+pub fn sky_sun() -> (Box<Background>, Light) {
+    sun_light(
+        vec3(1.0, 0.0, 0.5),
+        0.95,
+        15.0 * vec3(1.0, 0.85, 0.75),
+        |_| 0.5 * vec3(0.4, 0.7, 1.0),
+    )
+}
+
+// Backgrounds that can be chosen by name, to light a scene differently
+// without changing it, like when testing materials.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackgroundPreset {
+    // Uniform gray light from every direction.
+    StudioGray,
+    // See `sky_sun`.
+    SkySun,
+    // No light at all, so that only emitters light the scene.
+    Black,
+    // A dark ground below the horizon and a sky that deepens from the
+    // horizon up.
+    Gradient,
+    // An equirectangular image loaded from a PNG file, with the poles on the
+    // z-axis like in `Projection::Spherical`.
+    EnvMap(String),
+}
+
+impl BackgroundPreset {
+    // The preset with the given name, or an environment map if the name is
+    // the path of a PNG file.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "studio-gray" => Some(BackgroundPreset::StudioGray),
+            "sky-sun" => Some(BackgroundPreset::SkySun),
+            "black" => Some(BackgroundPreset::Black),
+            "gradient" => Some(BackgroundPreset::Gradient),
+            _ if name.ends_with(".png") => Some(BackgroundPreset::EnvMap(name.to_string())),
+            _ => None,
+        }
+    }
+
+    // The background along with the lights that are part of it.
+    pub fn build(&self) -> Result<(Box<Background>, Vec<Light>), RenderError> {
+        Ok(match self {
+            BackgroundPreset::StudioGray => (directional(|_| STUDIO_GRAY), Vec::new()),
+            BackgroundPreset::SkySun => {
+                let (background, sun) = sky_sun();
+                (background, vec![sun])
+            }
+            BackgroundPreset::Black => (directional(|_| Vec3::ZERO), Vec::new()),
+            BackgroundPreset::Gradient => {
+                let background = directional(|d: Vec3| {
+                    if d.z < 0.0 {
+                        GRADIENT_GROUND
+                    } else {
+                        GRADIENT_HORIZON.lerp(GRADIENT_ZENITH, d.z)
+                    }
+                });
+                (background, Vec::new())
+            }
+            BackgroundPreset::EnvMap(path) => {
+                // Clamping keeps the poles from blending with each other.
+                let image = Image::load_png(path)?.wrap(Wrap::Clamp);
+                let projection = Projection::Spherical { center: Vec3::ZERO };
+                let background = directional(move |d: Vec3| projection.sample(&image, d, d));
+                (background, Vec::new())
+            }
+        })
+    }
+}
+
+pub type SampleDirection = dyn Fn(&mut dyn Sampler) -> (Vec3, f32) + Sync;
+
+// Importance sampling of the background: `sample` returns a direction and its
+// probability density, which `pdf` must also return for that direction.
+pub struct BackgroundSampler {
+    pub sample: Box<SampleDirection>,
+    pub pdf: Box<dyn Fn(Vec3) -> f32 + Sync>,
+}
+
+// Samples a cone around a bright region of the background with the given
+// probability, and the whole sphere of directions otherwise.
+pub fn cone_sampler(direction: Vec3, cos_radius: f32, probability: f32) -> BackgroundSampler {
+    let direction = direction.normalize();
+    let cone_pdf = 1.0 / (2.0 * PI * (1.0 - cos_radius));
+    let sphere_pdf = 1.0 / (4.0 * PI);
+
+    let pdf = move |d: Vec3| {
+        let cone = if d.dot(direction) > cos_radius {
+            cone_pdf
+        } else {
+            0.0
+        };
+        probability * cone + (1.0 - probability) * sphere_pdf
+    };
+
+    BackgroundSampler {
+        sample: Box::new(move |sampler: &mut dyn Sampler| {
+            let d = if sampler.next_f32() < probability {
+                sampling::uniform_cone(direction, cos_radius, sampler)
+            } else {
+                sampling::uniform_sphere(sampler)
+            };
+            (d, pdf(d))
+        }),
+        pdf: Box::new(pdf),
+    }
+}
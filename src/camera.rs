@@ -1,4 +1,5 @@
-use super::sampling;
+use super::error::RenderError;
+
 use glam::Vec3;
 
 pub struct Ray {
@@ -6,15 +7,19 @@ pub struct Ray {
     pub direction: Vec3,
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct Camera {
     position: Vec3,
-    left: Vec3,
+    right: Vec3,
     forward: Vec3,
     up: Vec3,
     focal_length: f32,
     aspect_ratio: f32,
     focus_dist: f32,
     aperture: f32,
+    // Counts the changes made to the camera, so that progressive renders
+    // can tell when what they have accumulated is stale.
+    generation: u64,
 }
 
 impl Camera {
@@ -28,29 +33,89 @@ impl Camera {
     ) -> Self {
         let focal_length = 0.5 / (0.5 * angle_of_view).tan();
         let forward = (look_at - position).normalize();
-        let left = forward.cross(up).normalize();
-        let up = left.cross(forward);
+        let right = forward.cross(up).normalize();
+        let up = right.cross(forward);
         let focus_dist = (look_at - position).length();
         Self {
             position,
-            left,
+            right,
             forward,
             up,
             focal_length,
             aspect_ratio,
             focus_dist,
             aperture,
+            generation: 0,
         }
     }
 
-    pub fn get_ray(&self, x: f32, y: f32) -> Ray {
-        let (dx, dy) = sampling::uniform_disk();
-        let offset = 0.5 * self.aperture * (dx * self.left + dy * self.up);
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
+        self.generation += 1;
+    }
+
+    // Focuses on the plane through the point facing the camera, without
+    // turning the camera towards it. A point that isn't in front of the
+    // camera would turn the depth of field inside out, and is rejected,
+    // leaving the focus as it was.
+    pub fn focus_on(&mut self, point: Vec3) -> Result<(), RenderError> {
+        let focus_dist = (point - self.position).dot(self.forward);
+        if focus_dist.is_nan() || focus_dist <= 0.0 {
+            return Err(RenderError::InvalidSettings(format!(
+                "Can't focus on {}, which isn't in front of the camera.",
+                point
+            )));
+        }
+        self.focus_dist = focus_dist;
+        self.generation += 1;
+        Ok(())
+    }
+
+    // Moves the camera along its own right, up and forward directions,
+    // keeping the direction it looks in.
+    pub fn translate(&mut self, right: f32, up: f32, forward: f32) {
+        self.position += up * self.up + forward * self.forward + right * self.right;
+        self.generation += 1;
+    }
+
+    // Angle between the rays through the centers of two adjacent pixels.
+    pub fn pixel_spread(&self, width: i32) -> f32 {
+        1.0 / (self.focal_length * width as f32)
+    }
+
+    /// The ray through the screen point (x, y), where both run from -0.5 to
+    /// 0.5 across the image, x to the right and y up. The angle of view is
+    /// horizontal and y is divided by the aspect ratio, so pixels come out
+    /// square when the aspect ratio matches the image:
+    ///
+    /// ```
+    /// use glam::{vec3, Vec3};
+    /// use raymarching_pathtracer::{camera::Camera, renderer::*, sdf::*};
+    ///
+    /// let (width, height) = (80, 60);
+    /// let camera = Camera::new(vec3(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y, 0.5, 4.0 / 3.0, 0.0);
+    /// let white = Material::Lambertian { color: Vec3::ONE };
+    /// let scene = Scene::new(camera, sphere(0.5).material(white));
+    /// let image = render_preview(width, height, Vec3::ONE, &scene).unwrap();
+    ///
+    /// let covered = |i: usize, j: usize| image[i][j].length() > 0.0;
+    /// let rows = (0..height as usize).filter(|&i| (0..width as usize).any(|j| covered(i, j)));
+    /// let columns = (0..width as usize).filter(|&j| (0..height as usize).any(|i| covered(i, j)));
+    /// let (rows, columns) = (rows.count(), columns.count());
+    /// assert_eq!((rows, columns), (32, 32));
+    /// ```
+    pub fn get_ray(&self, x: f32, y: f32, lens: (f32, f32)) -> Ray {
+        let (dx, dy) = lens;
+        let offset = 0.5 * self.aperture * (dx * self.right + dy * self.up);
 
         let origin = self.position + offset;
 
         let direction = (self.focus_dist
-            * (x / self.focal_length * self.left
+            * (x / self.focal_length * self.right
                 + y / (self.focal_length * self.aspect_ratio) * self.up
                 + self.forward)
             - offset)
@@ -58,4 +123,20 @@ impl Camera {
 
         Ray { origin, direction }
     }
+
+    // Screen coordinates of a world point, as taken by get_ray, or None if
+    // the point is behind the camera.
+    pub fn project(&self, point: Vec3) -> Option<(f32, f32)> {
+        let d = point - self.position;
+        let depth = d.dot(self.forward);
+
+        if depth <= 0.0 {
+            return None;
+        }
+
+        let x = self.focal_length * d.dot(self.right) / depth;
+        let y = self.focal_length * self.aspect_ratio * d.dot(self.up) / depth;
+
+        Some((x, y))
+    }
 }
@@ -0,0 +1,28 @@
+use super::texture::srgb_to_linear;
+use glam::{vec3, Vec3};
+
+/// The linear RGB color of a hue in degrees, with saturation and value from
+/// zero to one, as picked in the HSV model of a color picker. Picked colors
+/// are sRGB encoded, so the result is linearized like with
+/// `texture::srgb_to_linear`:
+///
+/// ```
+/// use glam::Vec3;
+/// use raymarching_pathtracer::color::from_hsv;
+///
+/// assert_eq!(from_hsv(0.0, 1.0, 1.0), Vec3::X);
+/// assert_eq!(from_hsv(120.0, 1.0, 1.0), Vec3::Y);
+/// assert_eq!(from_hsv(-120.0, 1.0, 1.0), Vec3::Z);
+/// assert_eq!(from_hsv(60.0, 0.0, 1.0), Vec3::ONE);
+/// assert_eq!(from_hsv(200.0, 1.0, 0.0), Vec3::ZERO);
+///
+/// let gray = from_hsv(300.0, 0.0, 128.0 / 255.0);
+/// assert!((gray - Vec3::splat(0.216)).abs().max_element() < 1e-3);
+/// ```
+pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Vec3 {
+    let channel = |n: f32| {
+        let k = (n + hue / 60.0).rem_euclid(6.0);
+        value - value * saturation * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    srgb_to_linear(vec3(channel(5.0), channel(3.0), channel(1.0)))
+}
@@ -0,0 +1,101 @@
+use super::error::RenderError;
+use glam::{Vec3, Vec4};
+
+// Layers are RGBA images with premultiplied alpha, stored row by row like
+// rendered images. A rendered image is an opaque layer.
+pub type Layer = Vec<Vec<Vec4>>;
+
+pub fn opaque(pixels: &[Vec<Vec3>]) -> Layer {
+    pixels
+        .iter()
+        .map(|row| row.iter().map(|pixel| pixel.extend(1.0)).collect())
+        .collect()
+}
+
+// The color of the layer composited over black, for exporting.
+pub fn flatten(layer: &[Vec<Vec4>]) -> Vec<Vec<Vec3>> {
+    layer
+        .iter()
+        .map(|row| row.iter().map(|pixel| pixel.truncate()).collect())
+        .collect()
+}
+
+fn same_size<T, U>(a: &[Vec<T>], b: &[Vec<U>], message: &str) -> Result<(), RenderError> {
+    if a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.len() == b.len()) {
+        Ok(())
+    } else {
+        Err(RenderError::InvalidSettings(message.to_string()))
+    }
+}
+
+fn combine(
+    a: &[Vec<Vec4>],
+    b: &[Vec<Vec4>],
+    f: impl Fn(Vec4, Vec4) -> Vec4,
+) -> Result<Layer, RenderError> {
+    same_size(a, b, "Layers to composite must have the same size.")?;
+    Ok(a.iter()
+        .zip(b)
+        .map(|(a, b)| a.iter().zip(b).map(|(&a, &b)| f(a, b)).collect())
+        .collect())
+}
+
+/// Porter-Duff "over": the back layer shows through where the front layer
+/// is transparent. Layers of different sizes are rejected.
+///
+/// ```
+/// use glam::vec4;
+/// use raymarching_pathtracer::{composite, error::RenderError};
+///
+/// // Red at half coverage, premultiplied, over solid blue.
+/// let front = vec![vec![vec4(0.5, 0.0, 0.0, 0.5)]];
+/// let back = vec![vec![vec4(0.0, 0.0, 1.0, 1.0)]];
+/// assert_eq!(composite::over(&front, &back).unwrap()[0][0], vec4(0.5, 0.0, 0.5, 1.0));
+///
+/// let wider = vec![vec![vec4(0.0, 0.0, 1.0, 1.0); 2]];
+/// let result = composite::over(&front, &wider);
+/// assert!(matches!(result, Err(RenderError::InvalidSettings(_))));
+/// ```
+pub fn over(front: &[Vec<Vec4>], back: &[Vec<Vec4>]) -> Result<Layer, RenderError> {
+    combine(front, back, |front, back| front + (1.0 - front.w) * back)
+}
+
+// Sums the light of both layers, as when they were rendered with separate
+// lights. The coverage saturates at one.
+pub fn add(a: &[Vec<Vec4>], b: &[Vec<Vec4>]) -> Result<Layer, RenderError> {
+    combine(a, b, |a, b| {
+        let sum = a + b;
+        sum.truncate().extend(sum.w.min(1.0))
+    })
+}
+
+/// A red-cyan anaglyph of a stereo pair, for viewing with red-cyan glasses:
+/// red comes from the left eye's image and green and blue from the right
+/// eye's. The two images must have the same size.
+///
+/// ```
+/// use glam::vec3;
+/// use raymarching_pathtracer::composite;
+///
+/// let left = vec![vec![vec3(0.1, 0.2, 0.3)]];
+/// let right = vec![vec![vec3(0.4, 0.5, 0.6)]];
+/// assert_eq!(composite::anaglyph(&left, &right).unwrap()[0][0], vec3(0.1, 0.5, 0.6));
+/// assert!(composite::anaglyph(&left, &[]).is_err());
+/// ```
+pub fn anaglyph(left: &[Vec<Vec3>], right: &[Vec<Vec3>]) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    same_size(
+        left,
+        right,
+        "The images of a stereo pair must have the same size.",
+    )?;
+    Ok(left
+        .iter()
+        .zip(right)
+        .map(|(left, right)| {
+            left.iter()
+                .zip(right)
+                .map(|(left, right)| Vec3::new(left.x, right.y, right.z))
+                .collect()
+        })
+        .collect())
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+
+/// Errors from rendering a scene and writing out the result.
+///
+/// Exporting into a directory that doesn't exist fails with `Io`:
+///
+/// ```
+/// use glam::Vec3;
+/// use raymarching_pathtracer::{error::RenderError, export, ppm};
+///
+/// let pixels = vec![vec![Vec3::ONE; 4]; 3];
+/// let settings = export::ExportSettings::default();
+/// let result = ppm::export_ppm("no/such/directory/image.ppm", &pixels, &settings);
+/// assert!(matches!(result, Err(RenderError::Io(_))));
+///
+/// // An empty image is rejected before the path is even tried.
+/// let result = ppm::export_ppm("no/such/directory/image.ppm", &[], &settings);
+/// assert!(matches!(result, Err(RenderError::InvalidSettings(_))));
+/// ```
+///
+/// Each error describes itself for printing:
+///
+/// ```
+/// use raymarching_pathtracer::error::RenderError;
+///
+/// let error = RenderError::SceneNotFound("scene0".to_string());
+/// assert_eq!(error.to_string(), "Scene \"scene0\" not found.");
+/// ```
+#[derive(Debug)]
+pub enum RenderError {
+    SceneNotFound(String),
+    Io(std::io::Error),
+    InvalidSettings(String),
+    MalformedScene(String),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::SceneNotFound(name) => write!(f, "Scene \"{}\" not found.", name),
+            RenderError::Io(error) => write!(f, "{}", error),
+            RenderError::InvalidSettings(message) | RenderError::MalformedScene(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RenderError {
+    fn from(error: std::io::Error) -> Self {
+        RenderError::Io(error)
+    }
+}
+
+// PNG errors are mostly failures to read or write the file, and otherwise
+// malformed files, which the standard library also reports as IO errors.
+impl From<png::EncodingError> for RenderError {
+    fn from(error: png::EncodingError) -> Self {
+        RenderError::Io(error.into())
+    }
+}
+
+impl From<png::DecodingError> for RenderError {
+    fn from(error: png::DecodingError) -> Self {
+        RenderError::Io(error.into())
+    }
+}
+
+// The thread pool fails to build when the system can't start its threads.
+impl From<rayon::ThreadPoolBuildError> for RenderError {
+    fn from(error: rayon::ThreadPoolBuildError) -> Self {
+        RenderError::Io(std::io::Error::other(error))
+    }
+}
@@ -0,0 +1,97 @@
+const GAMMA: f32 = 2.2;
+// The color temperature at which white balance leaves colors unchanged.
+pub(crate) const NEUTRAL_TEMPERATURE: f32 = 6500.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    Linear,
+    Srgb,
+    Gamma(f32),
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Gamma(GAMMA)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClampMode {
+    #[default]
+    PerChannel,
+    Luminance,
+}
+
+/// Bits per channel of exported images. Sixteen bits keep smooth gradients,
+/// like skies, from banding:
+///
+/// ```
+/// use glam::Vec3;
+/// use raymarching_pathtracer::export::{ColorSpace, ExportSettings};
+/// use raymarching_pathtracer::ppm;
+/// use std::collections::HashSet;
+///
+/// let gradient = vec![(0..1000).map(|i| Vec3::splat(0.5 + 0.01 * i as f32 / 1000.0)).collect()];
+/// let settings = ExportSettings {
+///     color_space: ColorSpace::Linear,
+///     ..ExportSettings::default()
+/// };
+///
+/// let eight: HashSet<_> = ppm::to_rgb8(&gradient, &settings).into_iter().collect();
+/// let sixteen: HashSet<_> = ppm::to_rgb16(&gradient, &settings).into_iter().collect();
+/// assert!(sixteen.len() > 100 * eight.len());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    pub(crate) fn max_value(self) -> f32 {
+        match self {
+            BitDepth::Eight => 255.0,
+            BitDepth::Sixteen => 65535.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMap {
+    #[default]
+    None,
+    Reinhard,
+    Aces,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExportSettings {
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    pub color_space: ColorSpace,
+    pub clamp_mode: ClampMode,
+    // Color temperature in kelvins. Lower values warm the image and higher
+    // values cool it.
+    pub white_balance: f32,
+    // Scales the exposure by `auto_exposure` of the image being exported.
+    pub auto_exposure: bool,
+    pub bit_depth: BitDepth,
+    // Dithers 8-bit output, so that smooth gradients don't band.
+    pub dither: bool,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            tone_map: ToneMap::default(),
+            color_space: ColorSpace::default(),
+            clamp_mode: ClampMode::default(),
+            white_balance: NEUTRAL_TEMPERATURE,
+            auto_exposure: false,
+            bit_depth: BitDepth::default(),
+            dither: true,
+        }
+    }
+}
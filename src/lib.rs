@@ -0,0 +1,21 @@
+pub mod background;
+pub mod camera;
+pub mod color;
+pub mod composite;
+pub mod error;
+pub mod export;
+mod font;
+pub mod light;
+pub mod mesh;
+pub mod noise;
+pub mod ppm;
+pub mod profile;
+pub mod renderer;
+pub mod sampling;
+pub mod sdf;
+pub mod sobol;
+pub mod spectral;
+pub mod texture;
+pub mod validation;
+#[cfg(feature = "window")]
+pub mod window;
@@ -0,0 +1,103 @@
+use super::sampling::{self, Sampler};
+use glam::Vec3;
+use std::f32::consts::PI;
+
+// Lower bound for the distance to a point light, so that surfaces touching it
+// don't receive unbounded irradiance.
+const MIN_POINT_DIST: f32 = 0.01;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    // A distant light covering a cone of directions, such as the sun.
+    Directional {
+        direction: Vec3,
+        cos_radius: f32,
+        radiance: Vec3,
+    },
+    // An infinitesimal light radiating the given intensity in all directions.
+    // It can't be hit by rays, only sampled.
+    Point {
+        position: Vec3,
+        intensity: Vec3,
+    },
+}
+
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    // Radiance divided by the probability density of the sampled direction.
+    pub weight: Vec3,
+}
+
+impl Light {
+    // Samples the light as seen from the given position.
+    pub fn sample(&self, position: Vec3, sampler: &mut (impl Sampler + ?Sized)) -> LightSample {
+        match *self {
+            Light::Directional {
+                direction,
+                cos_radius,
+                radiance,
+            } => {
+                let solid_angle = 2.0 * PI * (1.0 - cos_radius);
+                LightSample {
+                    direction: sampling::uniform_cone(direction, cos_radius, sampler),
+                    distance: f32::INFINITY,
+                    weight: solid_angle * radiance,
+                }
+            }
+            Light::Point {
+                position: light_position,
+                intensity,
+            } => {
+                let offset = light_position - position;
+                let distance = offset.length();
+                let clamped = distance.max(MIN_POINT_DIST);
+                // At the light itself there is no direction to it, and it
+                // gives no light.
+                let direction = offset.normalize_or_zero();
+                if direction == Vec3::ZERO {
+                    return LightSample {
+                        direction: Vec3::Z,
+                        distance: 0.0,
+                        weight: Vec3::ZERO,
+                    };
+                }
+                LightSample {
+                    direction,
+                    distance,
+                    weight: intensity / (clamped * clamped),
+                }
+            }
+        }
+    }
+
+    // Emitted power, averaged over the color channels, by which lights are
+    // chosen for sampling. A distant light has no finite power, so the
+    // power falling on a unit area facing it is used instead. That is in
+    // different units from the power of a point light, so how often one is
+    // chosen over the other depends on the scale of the scene. The choice
+    // only changes the noise, not what the image converges to.
+    pub fn power(&self) -> f32 {
+        let mean = |color: Vec3| (color.x + color.y + color.z) / 3.0;
+        match *self {
+            Light::Directional {
+                cos_radius,
+                radiance,
+                ..
+            } => 2.0 * PI * (1.0 - cos_radius) * mean(radiance),
+            Light::Point { intensity, .. } => 4.0 * PI * mean(intensity),
+        }
+    }
+
+    // Whether a ray escaping in the given direction would see this light.
+    pub fn covers(&self, direction: Vec3) -> bool {
+        match *self {
+            Light::Directional {
+                direction: light_direction,
+                cos_radius,
+                ..
+            } => direction.dot(light_direction) > cos_radius,
+            Light::Point { .. } => false,
+        }
+    }
+}
@@ -1,45 +1,337 @@
-mod camera;
-mod ppm;
-mod renderer;
-mod sampling;
 mod scene1;
+mod scene10;
 mod scene2;
-mod sdf;
+mod scene3;
+mod scene4;
+mod scene5;
+mod scene6;
+mod scene7;
+mod scene8;
+mod scene9;
+
+use raymarching_pathtracer::error::RenderError;
+use raymarching_pathtracer::{background, camera, export, renderer, sdf, texture};
+
+use glam::{vec2, Vec2, Vec3};
 
 use std::env;
-use std::time::Instant;
+use std::process;
+use std::time::{Duration, Instant};
 
-const WIDTH: i32 = 800;
-const HEIGHT: i32 = 600;
-const SAMPLE_COUNT: i32 = 100;
-const ASPECT_RATIO: f32 = WIDTH as f32 / HEIGHT as f32;
+#[derive(Default)]
+struct Options {
+    preview: bool,
+    window: bool,
+    png: bool,
+    auto_exposure: bool,
+    bit_depth: export::BitDepth,
+    no_dither: bool,
+    slice: Option<f32>,
+    shadow_catcher: Option<f32>,
+    time_budget: Option<f32>,
+    ambient: f32,
+    env_intensity: Option<f32>,
+    background: Option<background::BackgroundPreset>,
+    profile: bool,
+}
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+fn invalid_flag(flag: &str, value: &str) -> RenderError {
+    RenderError::InvalidSettings(format!("Flag \"{}\" needs {} value.", flag, value))
+}
 
-    let now = Instant::now();
+fn positive_int(flag: &str, value: Option<&String>) -> Result<i32, RenderError> {
+    match value.map(|value| value.parse::<i32>()) {
+        Some(Ok(value)) if value > 0 => Ok(value),
+        _ => Err(invalid_flag(flag, "a positive integer")),
+    }
+}
+
+fn non_negative_int(flag: &str, value: Option<&String>) -> Result<i32, RenderError> {
+    match value.map(|value| value.parse::<i32>()) {
+        Some(Ok(value)) if value >= 0 => Ok(value),
+        _ => Err(invalid_flag(flag, "a non-negative integer")),
+    }
+}
+
+fn float(flag: &str, value: Option<&String>) -> Result<f32, RenderError> {
+    match value.map(|value| value.parse::<f32>()) {
+        Some(Ok(value)) if value.is_finite() => Ok(value),
+        _ => Err(invalid_flag(flag, "a number")),
+    }
+}
+
+fn positive_float(flag: &str, value: Option<&String>) -> Result<f32, RenderError> {
+    match value.map(|value| value.parse::<f32>()) {
+        Some(Ok(value)) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err(invalid_flag(flag, "a positive number")),
+    }
+}
+
+fn parse_flags(
+    flags: &[String],
+    settings: &mut renderer::RenderSettings,
+) -> Result<Options, RenderError> {
+    let mut options = Options::default();
+
+    // A quality preset is applied before any other flag, so that the flags
+    // for individual settings override it wherever they are given.
+    if let Some(i) = flags.iter().position(|flag| flag == "--quality") {
+        let preset = match flags.get(i + 1).map(|value| value.as_str()) {
+            Some("preview") => renderer::RenderSettings::preview(),
+            Some("medium") => renderer::RenderSettings::medium(),
+            Some("high") => renderer::RenderSettings::high(),
+            _ => return Err(invalid_flag("--quality", "a preview, medium or high")),
+        };
+        settings.sample_count = preset.sample_count;
+        settings.max_bounces = preset.max_bounces;
+        settings.step_scale = preset.step_scale;
+    }
+
+    let mut flags = flags.iter();
+
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--width" => settings.width = positive_int(flag, flags.next())?,
+            "--height" => settings.height = positive_int(flag, flags.next())?,
+            "--samples" => settings.sample_count = positive_int(flag, flags.next())?,
+            "--paths" => settings.paths_per_sample = positive_int(flag, flags.next())?,
+            "--threads" => settings.threads = Some(positive_int(flag, flags.next())? as usize),
+            "--bounces" => settings.max_bounces = non_negative_int(flag, flags.next())?,
+            "--clay" => settings.clay = true,
+            "--show-march-failures" => settings.show_march_failures = true,
+            "--step-scale" => settings.step_scale = positive_float(flag, flags.next())?,
+            "--ssaa" => settings.ssaa = positive_int(flag, flags.next())? as u32,
+            "--preview" => options.preview = true,
+            "--window" => options.window = true,
+            "--png" => options.png = true,
+            "--profile" => options.profile = true,
+            "--quality" => {
+                flags.next();
+            }
+            "--auto-exposure" => options.auto_exposure = true,
+            "--bit-depth" => {
+                options.bit_depth = match flags.next().map(|value| value.as_str()) {
+                    Some("8") => export::BitDepth::Eight,
+                    Some("16") => export::BitDepth::Sixteen,
+                    _ => return Err(invalid_flag(flag, "an 8 or 16")),
+                }
+            }
+            "--no-dither" => options.no_dither = true,
+            "--slice" => options.slice = Some(float(flag, flags.next())?),
+            "--shadow-catcher" => options.shadow_catcher = Some(float(flag, flags.next())?),
+            "--time-budget" => options.time_budget = Some(positive_float(flag, flags.next())?),
+            "--ambient" => options.ambient = positive_float(flag, flags.next())?,
+            "--env-intensity" => options.env_intensity = Some(float(flag, flags.next())?),
+            "--background" => {
+                options.background = match flags.next() {
+                    Some(name) => background::BackgroundPreset::from_name(name),
+                    None => None,
+                };
+                if options.background.is_none() {
+                    return Err(invalid_flag(
+                        flag,
+                        "a studio-gray, sky-sun, black, gradient or PNG file",
+                    ));
+                }
+            }
+            _ => {
+                return Err(RenderError::InvalidSettings(format!(
+                    "Unknown flag \"{}\".",
+                    flag
+                )))
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+// Slices the scene horizontally at the given height, over the extent of the
+// map if it is bounded, or else around the origin.
+fn render_slice(
+    z: f32,
+    settings: &renderer::RenderSettings,
+    scene: &renderer::Scene,
+) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    const UNBOUNDED_HALF_HEIGHT: f32 = 3.0;
+    const MARGIN: f32 = 1.1;
+
+    let aspect_ratio = settings.aspect_ratio();
+    let (center, half_height) = match scene.map.bounds() {
+        Some((min, max)) => {
+            let half_size = 0.5 * (max - min);
+            let half_height = half_size.y.max(half_size.x / aspect_ratio);
+            (0.5 * (min + max).truncate(), MARGIN * half_height)
+        }
+        None => (Vec2::ZERO, UNBOUNDED_HALF_HEIGHT),
+    };
+    let half_size = vec2(aspect_ratio * half_height, half_height);
+    let (min, max) = (center - half_size, center + half_size);
 
-    let scene_name = &(args[1])[..];
+    renderer::render_slice(
+        scene.map.as_ref(),
+        renderer::SlicePlane::Xy { z },
+        ((min.x, min.y), (max.x, max.y)),
+        settings.width,
+        settings.height,
+    )
+}
+
+// The shadow density on a ground plane at the given height, from white where
+// the plane is fully shadowed to black where it isn't shadowed or is hidden.
+fn render_shadow_catcher(
+    z: f32,
+    settings: &renderer::RenderSettings,
+    scene: &renderer::Scene,
+) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    use sdf::Sdf;
+
+    let catcher = sdf::plane(Vec3::Z)
+        .position(z * Vec3::Z)
+        .material(sdf::Material::Lambertian { color: Vec3::ONE });
+
+    Ok(renderer::render_shadow_catcher(settings, scene, &catcher)?
+        .iter()
+        .map(|row| row.iter().map(|pixel| Vec3::splat(pixel.w)).collect())
+        .collect())
+}
+
+#[cfg(feature = "window")]
+fn show_window(
+    settings: &renderer::RenderSettings,
+    scene: &mut renderer::Scene,
+    export_settings: &export::ExportSettings,
+) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    raymarching_pathtracer::window::show(settings, scene, export_settings)
+}
+
+#[cfg(not(feature = "window"))]
+fn show_window(
+    _settings: &renderer::RenderSettings,
+    _scene: &mut renderer::Scene,
+    _export_settings: &export::ExportSettings,
+) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    Err(RenderError::InvalidSettings(
+        "Flag \"--window\" needs the crate to be built with the \"window\" feature.".to_string(),
+    ))
+}
+
+fn create_scene(name: &str, aspect_ratio: f32) -> Result<renderer::Scene, RenderError> {
+    match name {
+        "scene1" => Ok(scene1::create_scene(aspect_ratio)),
+        "scene2" => Ok(scene2::create_scene(aspect_ratio)),
+        "scene3" => Ok(scene3::create_scene(aspect_ratio)),
+        "scene4" => Ok(scene4::create_scene(aspect_ratio)),
+        "scene5" => Ok(scene5::create_scene(aspect_ratio)),
+        "scene6" => Ok(scene6::create_scene(aspect_ratio)),
+        "scene7" => Ok(scene7::create_scene(aspect_ratio)),
+        "scene8" => Ok(scene8::create_scene(aspect_ratio)),
+        "scene9" => Ok(scene9::create_scene(aspect_ratio)),
+        "scene10" => Ok(scene10::create_scene(aspect_ratio)),
+        _ => Err(RenderError::SceneNotFound(name.to_string())),
+    }
+}
 
-    let scene = match scene_name {
-        "scene1" => scene1::create_scene(ASPECT_RATIO),
-        "scene2" => scene2::create_scene(ASPECT_RATIO),
-        _ => panic!("Scene \"{}\" not found.", scene_name),
+fn run(args: &[String]) -> Result<(), RenderError> {
+    let now = Instant::now();
+
+    let scene_name = match args.get(1) {
+        Some(name) => name.as_str(),
+        None => {
+            return Err(RenderError::InvalidSettings(
+                "Give the name of the scene to render.".to_string(),
+            ))
+        }
     };
 
-    let pixels = renderer::render(WIDTH, HEIGHT, SAMPLE_COUNT, &scene);
+    let aspect_ratio = renderer::RenderSettings::default().aspect_ratio();
 
-    let output_path = format!("{}.ppm", scene_name);
+    let mut scene = create_scene(scene_name, aspect_ratio)?;
 
-    match ppm::export_ppm(output_path.as_str(), &pixels) {
-        Ok(()) => {}
-        Err(error) => {
-            println!("{}", error)
+    // Command line flags take precedence over the settings suggested by the
+    // scene, which in turn take precedence over the defaults.
+    let mut settings = scene.suggested_settings.unwrap_or_default();
+    let options = parse_flags(&args[2..], &mut settings)?;
+    if let Some(env_intensity) = options.env_intensity {
+        scene.env_intensity = env_intensity;
+    }
+    if let Some(preset) = &options.background {
+        scene.set_background(preset)?;
+    }
+    scene.validate()?;
+    settings.validate()?;
+    scene.camera.set_aspect_ratio(settings.aspect_ratio());
+
+    // Slices are colored and shadow densities written for display as they
+    // are.
+    let export_settings = if options.slice.is_some() || options.shadow_catcher.is_some() {
+        export::ExportSettings {
+            color_space: export::ColorSpace::Linear,
+            bit_depth: options.bit_depth,
+            dither: !options.no_dither,
+            ..export::ExportSettings::default()
         }
+    } else {
+        export::ExportSettings {
+            auto_exposure: options.auto_exposure,
+            bit_depth: options.bit_depth,
+            dither: !options.no_dither,
+            ..scene.export_settings()
+        }
+    };
+
+    // The window has to stay on the main thread, so it only renders in the
+    // thread pool.
+    let pixels = if options.window {
+        show_window(&settings, &mut scene, &export_settings)?
+    } else {
+        settings.install(|| {
+            if let Some(z) = options.slice {
+                render_slice(z, &settings, &scene)
+            } else if let Some(z) = options.shadow_catcher {
+                render_shadow_catcher(z, &settings, &scene)
+            } else if options.preview {
+                renderer::render_preview(
+                    settings.width,
+                    settings.height,
+                    Vec3::splat(options.ambient),
+                    &scene,
+                )
+            } else if let Some(seconds) = options.time_budget {
+                let budget = Duration::from_secs_f32(seconds);
+                let (pixels, passes) = renderer::render_timed(&settings, &scene, budget)?;
+                println!("Passes: {}", passes);
+                Ok(pixels)
+            } else {
+                renderer::render(&settings, &scene)
+            }
+        })?
+    };
+
+    let result = renderer::RenderResult::from(pixels);
+    if options.png {
+        result.to_png(&format!("{}.png", scene_name), &export_settings)?;
+    } else {
+        result.to_ppm(&format!("{}.ppm", scene_name), &export_settings)?;
     }
 
     println!(
         "Rendering time: {:.1} s",
         now.elapsed().as_micros() as f32 / 1_000_000.0
     );
+    if options.profile {
+        let report = settings.install(|| Ok(renderer::profile_camera_rays(&settings, &scene)))?;
+        print!("{}", report);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if let Err(error) = run(&args) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,180 @@
+use super::error::RenderError;
+use super::sdf::{RayMask, SdfMap};
+
+use glam::{vec3, Vec3};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Corners of a grid cell are numbered by their offsets, x + 2y + 4z. Each
+// face lists its corners counterclockwise as seen from outside the cell.
+const FACES: [[usize; 4]; 6] = [
+    [0, 4, 6, 2],
+    [1, 3, 7, 5],
+    [0, 1, 5, 4],
+    [2, 6, 7, 3],
+    [0, 2, 3, 1],
+    [4, 5, 7, 6],
+];
+
+fn corner_offset(corner: usize) -> [usize; 3] {
+    [corner & 1, (corner >> 1) & 1, corner >> 2]
+}
+
+// Cell edges are identified by their lower corner and axis.
+fn edge_id(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), (a ^ b).trailing_zeros() as usize)
+}
+
+// Triangulates the zero level set of the distance field within the box by
+// marching cubes over a grid of `resolution` points per axis. Triangles wind
+// counterclockwise when seen from outside, and vertices shared between
+// neighbouring cells are merged, so a closed surface that doesn't cross the
+// box gives a watertight mesh.
+//
+// Instead of a lookup table, the surface within each cell is built face by
+// face. A face whose corners alternate in sign is ambiguous, and is resolved
+// by the sign of the average of its corners. Both cells sharing the face
+// see the same values, so they connect the surface the same way.
+pub fn extract(
+    map: &dyn SdfMap,
+    (min, max): (Vec3, Vec3),
+    resolution: [usize; 3],
+) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let [nx, ny, nz] = resolution;
+    let steps = vec3(nx as f32, ny as f32, nz as f32) - Vec3::ONE;
+    let spacing = (max - min) / steps.max(Vec3::ONE);
+    let point = |[i, j, k]: [usize; 3]| min + spacing * vec3(i as f32, j as f32, k as f32);
+    let index = |[i, j, k]: [usize; 3]| i + nx * (j + ny * k);
+
+    let values: Vec<f32> = (0..nx * ny * nz)
+        .into_par_iter()
+        .map(|n| point([n % nx, (n / nx) % ny, n / (nx * ny)]))
+        .map(|p| map.dist(p, RayMask::CAMERA))
+        .collect();
+
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut edge_vertices: HashMap<(usize, usize), u32> = HashMap::new();
+
+    for k in 0..nz.saturating_sub(1) {
+        for j in 0..ny.saturating_sub(1) {
+            for i in 0..nx.saturating_sub(1) {
+                let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|c| {
+                    let [di, dj, dk] = corner_offset(c);
+                    [i + di, j + dj, k + dk]
+                });
+                let value = corners.map(|c| values[index(c)]);
+                let inside = value.map(|v| v < 0.0);
+                if inside.iter().all(|&b| b) || !inside.iter().any(|&b| b) {
+                    continue;
+                }
+
+                // Where the surface continues from each crossed edge, going
+                // counterclockwise around the outside of the surface.
+                let mut next = [None; 24];
+                for face in FACES {
+                    let mut crossings = Vec::with_capacity(4);
+                    for n in 0..4 {
+                        let (a, b) = (face[n], face[(n + 1) % 4]);
+                        if inside[a] != inside[b] {
+                            crossings.push((edge_id(a, b), inside[a]));
+                        }
+                    }
+                    let center_inside = face.iter().map(|&c| value[c]).sum::<f32>() < 0.0;
+                    let m = crossings.len();
+                    for n in 0..m {
+                        let (from, leaving) = crossings[n];
+                        if leaving {
+                            continue;
+                        }
+                        // Connect to the following crossing to cut off the
+                        // inside corner in between, or to the preceding one
+                        // to cut off the outside corner and join the insides.
+                        let partner = if m == 4 && center_inside {
+                            (n + m - 1) % m
+                        } else {
+                            (n + 1) % m
+                        };
+                        let (to, _) = crossings[partner];
+                        next[from.0 * 3 + from.1] = Some(to);
+                    }
+                }
+
+                let mut vertex = |(corner, axis): (usize, usize)| {
+                    let a = corners[corner];
+                    let b = corners[corner | (1 << axis)];
+                    let key = (index(a), axis);
+                    *edge_vertices.entry(key).or_insert_with(|| {
+                        let (va, vb) = (values[index(a)], values[index(b)]);
+                        let t = va / (va - vb);
+                        vertices.push(point(a).lerp(point(b), t));
+                        (vertices.len() - 1) as u32
+                    })
+                };
+
+                for start in 0..24 {
+                    let mut polygon = Vec::new();
+                    let mut edge = (start / 3, start % 3);
+                    while let Some(to) = next[edge.0 * 3 + edge.1].take() {
+                        polygon.push(vertex(edge));
+                        edge = to;
+                    }
+                    for n in 1..polygon.len().saturating_sub(1) {
+                        triangles.push([polygon[0], polygon[n], polygon[n + 1]]);
+                    }
+                }
+            }
+        }
+    }
+
+    (vertices, triangles)
+}
+
+// Per-vertex normals from the gradient of the distance field, for smooth
+// shading of the extracted mesh.
+pub fn vertex_normals(map: &dyn SdfMap, vertices: &[Vec3]) -> Vec<Vec3> {
+    vertices
+        .par_iter()
+        .map(|&v| map.normal(v, RayMask::CAMERA))
+        .collect()
+}
+
+// Writes the mesh as a Wavefront OBJ file, with one normal per vertex if
+// `normals` is given, which must then have one for each vertex.
+pub fn export_obj(
+    path: &str,
+    vertices: &[Vec3],
+    faces: &[[u32; 3]],
+    normals: Option<&[Vec3]>,
+) -> Result<(), RenderError> {
+    if let Some(normals) = normals {
+        if normals.len() != vertices.len() {
+            return Err(RenderError::InvalidSettings(format!(
+                "The mesh has {} normals for {} vertices.",
+                normals.len(),
+                vertices.len()
+            )));
+        }
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    for v in vertices {
+        writeln!(file, "v {} {} {}", v.x, v.y, v.z)?;
+    }
+    for n in normals.unwrap_or_default() {
+        writeln!(file, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    // OBJ indices start from one.
+    for face in faces {
+        let [a, b, c] = face.map(|i| i + 1);
+        if normals.is_some() {
+            writeln!(file, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        } else {
+            writeln!(file, "f {a} {b} {c}")?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use super::sdf::cell_hash;
+
+use glam::{vec3, Vec3};
+
+// Bound on the gradient of `value_noise`, measured over many cells and
+// rounded up.
+const VALUE_NOISE_LIPSCHITZ: f32 = 4.0;
+
+// Quintic smoothstep, whose first and second derivatives vanish at the ends.
+fn fade(t: Vec3) -> Vec3 {
+    t * t * t * (t * (6.0 * t - 15.0) + 10.0)
+}
+
+// Smoothly interpolated random values at the integer lattice, in [-1, 1].
+// The same point and seed always give the same value.
+pub fn value_noise(p: Vec3, seed: u32) -> f32 {
+    let cell = p.floor();
+    let t = fade(p - cell);
+    let corner = |x: f32, y: f32, z: f32| 2.0 * cell_hash(cell + vec3(x, y, z), seed) - 1.0;
+    let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+
+    let x00 = lerp(corner(0.0, 0.0, 0.0), corner(1.0, 0.0, 0.0), t.x);
+    let x10 = lerp(corner(0.0, 1.0, 0.0), corner(1.0, 1.0, 0.0), t.x);
+    let x01 = lerp(corner(0.0, 0.0, 1.0), corner(1.0, 0.0, 1.0), t.x);
+    let x11 = lerp(corner(0.0, 1.0, 1.0), corner(1.0, 1.0, 1.0), t.x);
+    lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+}
+
+// Fractal sum of octaves of value noise, each at twice the frequency and half
+// the amplitude of the previous one, normalized back to [-1, 1].
+pub fn fbm(p: Vec3, octaves: u32, seed: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut p = p;
+
+    for octave in 0..octaves {
+        sum += amplitude * value_noise(p, seed.wrapping_add(octave));
+        total += amplitude;
+        amplitude *= 0.5;
+        p *= 2.0;
+    }
+
+    if total > 0.0 {
+        sum / total
+    } else {
+        0.0
+    }
+}
+
+// Every octave contributes equally to the gradient of `fbm`, since doubling
+// the frequency makes up for halving the amplitude.
+pub fn fbm_lipschitz(octaves: u32) -> f32 {
+    let total = (0..octaves)
+        .map(|octave| 0.5f32.powi(octave as i32))
+        .sum::<f32>();
+    if total > 0.0 {
+        VALUE_NOISE_LIPSCHITZ * octaves as f32 / total
+    } else {
+        0.0
+    }
+}
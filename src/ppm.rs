@@ -1,20 +1,228 @@
-use glam::Vec3;
+use super::error::RenderError;
+use super::export::NEUTRAL_TEMPERATURE;
+use glam::{vec3, Vec3};
 use std::fs::File;
 use std::io::{prelude::*, BufWriter};
+use std::path::Path;
 
-const GAMMA_INV: f32 = 1.0 / 2.2;
+// The export settings live in their own module, which the renderer uses too.
+pub use super::export::{BitDepth, ClampMode, ColorSpace, ExportSettings, ToneMap};
 
-fn gamma_encode(pixel: Vec3) -> Vec3 {
-    pixel.clamp(Vec3::ZERO, Vec3::ONE).powf(GAMMA_INV)
+// Log-average luminance that auto exposure brings images to, the middle gray
+// of photography.
+const AUTO_EXPOSURE_KEY: f32 = 0.18;
+// Keeps black pixels from sending the log-average to zero.
+const AUTO_EXPOSURE_EPSILON: f32 = 1e-4;
+// Bayer's ordered dither matrix, the order in which the pixels of a 4x4 block
+// round up as a value rises through one step.
+const BAYER: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+fn log_average_exposure<'a>(pixels: impl Iterator<Item = &'a Vec3>) -> f32 {
+    let (sum, count) = pixels
+        .map(|pixel| luminance(*pixel))
+        .filter(|l| l.is_finite() && *l >= 0.0)
+        .fold((0.0, 0), |(sum, count), l| {
+            (sum + (AUTO_EXPOSURE_EPSILON + l).ln() as f64, count + 1)
+        });
+
+    if count == 0 {
+        1.0
+    } else {
+        AUTO_EXPOSURE_KEY / (sum / count as f64).exp() as f32
+    }
+}
+
+// Exposure that brings the log-average luminance of the pixels to the key
+// value, after Reinhard et al., "Photographic Tone Reproduction for Digital
+// Images" (2002). Pixels that aren't finite are left out.
+pub fn auto_exposure(pixels: &[Vec<Vec3>]) -> f32 {
+    log_average_exposure(pixels.iter().flatten())
+}
+
+impl ExportSettings {
+    // Resolves auto exposure into a fixed exposure for the given pixels. An
+    // animation is resolved once for all of its frames, so that the exposure
+    // doesn't flicker.
+    fn resolve_exposure<'a>(&self, pixels: impl Iterator<Item = &'a Vec3>) -> Self {
+        if !self.auto_exposure {
+            return *self;
+        }
+        Self {
+            exposure: self.exposure * log_average_exposure(pixels),
+            auto_exposure: false,
+            ..*self
+        }
+    }
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: Vec3) -> Vec3 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn tone_map(pixel: Vec3, tone_map: ToneMap) -> Vec3 {
+    match tone_map {
+        ToneMap::None => pixel,
+        ToneMap::Reinhard => pixel / (1.0 + luminance(pixel)),
+        ToneMap::Aces => aces(pixel.max(Vec3::ZERO)),
+    }
+}
+
+// Tanner Helland's fit of the color of a blackbody at the given temperature,
+// with the brightest channel at one.
+fn blackbody(kelvin: f32) -> Vec3 {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (t - 60.0).powf(-0.13320476)
+    };
+    let g = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12216 * (t - 60.0).powf(-0.07551485)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    (vec3(r, g, b) / 255.0).clamp(Vec3::ZERO, Vec3::ONE)
 }
 
-pub fn export_ppm(path: &str, pixels: &Vec<Vec<Vec3>>) -> Result<(), std::io::Error> {
-    const MAX_PIXEL_VALUE: f32 = 255.0;
+fn white_balance(kelvin: f32) -> Vec3 {
+    blackbody(kelvin) / blackbody(NEUTRAL_TEMPERATURE)
+}
+
+fn luminance(pixel: Vec3) -> f32 {
+    pixel.dot(vec3(0.2126, 0.7152, 0.0722))
+}
 
-    let width = pixels[0].len();
-    let height = pixels.len();
+// Desaturates towards gray of equal luminance until the brightest channel
+// fits, so that bright saturated colors keep their hue instead of clipping.
+fn clamp_luminance(pixel: Vec3) -> Vec3 {
+    let pixel = pixel.max(Vec3::ZERO);
+    let l = luminance(pixel);
+    let max = pixel.max_element();
+
+    if max <= 1.0 {
+        pixel
+    } else if l >= 1.0 {
+        Vec3::ONE
+    } else {
+        let s = (1.0 - l) / (max - l);
+        Vec3::splat(l) + s * (pixel - Vec3::splat(l))
+    }
+}
+
+fn srgb_encode(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn encode(pixel: Vec3, settings: &ExportSettings) -> Vec3 {
+    let pixel = settings.exposure * white_balance(settings.white_balance) * pixel;
+    let pixel = tone_map(pixel, settings.tone_map);
+    let pixel = match settings.clamp_mode {
+        ClampMode::PerChannel => pixel.clamp(Vec3::ZERO, Vec3::ONE),
+        ClampMode::Luminance => clamp_luminance(pixel).min(Vec3::ONE),
+    };
+    match settings.color_space {
+        ColorSpace::Linear => pixel,
+        ColorSpace::Srgb => vec3(
+            srgb_encode(pixel.x),
+            srgb_encode(pixel.y),
+            srgb_encode(pixel.z),
+        ),
+        ColorSpace::Gamma(gamma) => pixel.powf(1.0 / gamma),
+    }
+}
+
+/// Scales an encoded pixel to the bit depth and rounds it. At 8 bits the
+/// rounding threshold is dithered from pixel to pixel, so a gradient too
+/// shallow for 8 bits fades smoothly between steps instead of banding, as
+/// the averages of 4x4 blocks show:
+///
+/// ```
+/// use glam::Vec3;
+/// use raymarching_pathtracer::ppm::{self, ColorSpace, ExportSettings};
+///
+/// let (width, height) = (1000, 4);
+/// let value = |j: usize| 0.5 + 0.01 * j as f32 / width as f32;
+/// let gradient = vec![(0..width).map(|j| Vec3::splat(value(j))).collect(); height];
+///
+/// let block_steps = |dither| {
+///     let settings = ExportSettings {
+///         color_space: ColorSpace::Linear,
+///         dither,
+///         ..ExportSettings::default()
+///     };
+///     let rgb = ppm::to_rgb8(&gradient, &settings);
+///     let blocks: Vec<f32> = (0..width / 4)
+///         .map(|block| {
+///             let mut sum = 0.0;
+///             for i in 0..height {
+///                 for j in 4 * block..4 * block + 4 {
+///                     sum += rgb[3 * (i * width + j)] as f32;
+///                 }
+///             }
+///             sum / 16.0
+///         })
+///         .collect();
+///     let error = (0..width / 4)
+///         .map(|block| (blocks[block] - 255.0 * value(4 * block + 2)).abs())
+///         .fold(0.0, f32::max);
+///     let step = blocks.windows(2).map(|pair| pair[1] - pair[0]).fold(0.0, f32::max);
+///     (error, step)
+/// };
+///
+/// let (error, step) = block_steps(false);
+/// assert!(error > 0.4 && step > 0.5);
+/// let (error, step) = block_steps(true);
+/// assert!(error < 0.1 && step < 0.1);
+/// ```
+fn quantize(pixel: Vec3, row: usize, column: usize, settings: &ExportSettings) -> Vec3 {
+    let max_value = settings.bit_depth.max_value();
+    let offset = if settings.dither && settings.bit_depth == BitDepth::Eight {
+        (BAYER[row % 4][column % 4] + 0.5) / 16.0 - 0.5
+    } else {
+        0.0
+    };
+    (max_value * encode(pixel, settings) + Vec3::splat(offset))
+        .round()
+        .clamp(Vec3::ZERO, Vec3::splat(max_value))
+}
+
+// The width and height of the image, which mustn't be empty.
+fn image_size(pixels: &[Vec<Vec3>]) -> Result<(usize, usize), RenderError> {
+    match pixels.first().map(Vec::len) {
+        Some(width) if width > 0 => Ok((width, pixels.len())),
+        _ => Err(RenderError::InvalidSettings(
+            "Can't export an empty image.".to_string(),
+        )),
+    }
+}
 
-    let file = File::create(path).unwrap();
+pub fn export_ppm(
+    path: &str,
+    pixels: &[Vec<Vec3>],
+    settings: &ExportSettings,
+) -> Result<(), RenderError> {
+    let max_pixel_value = settings.bit_depth.max_value();
+    let (width, height) = image_size(pixels)?;
+    let settings = &settings.resolve_exposure(pixels.iter().flatten());
+
+    let file = File::create(path)?;
     let mut writer = BufWriter::new(file);
 
     writeln!(
@@ -22,15 +230,156 @@ pub fn export_ppm(path: &str, pixels: &Vec<Vec<Vec3>>) -> Result<(), std::io::Er
         "P3\n{width} {height}\n{max_pixel_value}\n",
         width = width,
         height = height,
-        max_pixel_value = MAX_PIXEL_VALUE
+        max_pixel_value = max_pixel_value
     )?;
 
-    for row in pixels {
-        for pixel in row {
-            let pixel = MAX_PIXEL_VALUE * gamma_encode(*pixel);
+    for (i, row) in pixels.iter().enumerate() {
+        for (j, pixel) in row.iter().enumerate() {
+            let pixel = quantize(*pixel, i, j, settings);
             writeln!(writer, "{:.0} {:.0} {:.0}", pixel.x, pixel.y, pixel.z)?;
         }
     }
 
-    writer.flush()
+    writer.flush()?;
+    Ok(())
+}
+
+fn quantize_all<'a>(
+    pixels: &'a [Vec<Vec3>],
+    settings: &'a ExportSettings,
+) -> impl Iterator<Item = Vec3> + 'a {
+    pixels.iter().enumerate().flat_map(move |(i, row)| {
+        row.iter()
+            .enumerate()
+            .map(move |(j, pixel)| quantize(*pixel, i, j, settings))
+    })
+}
+
+fn encode_rgb8(pixels: &[Vec<Vec3>], settings: &ExportSettings) -> Vec<u8> {
+    let settings = &ExportSettings {
+        bit_depth: BitDepth::Eight,
+        ..*settings
+    };
+    quantize_all(pixels, settings)
+        .flat_map(|pixel| [pixel.x, pixel.y, pixel.z].map(|c| c as u8))
+        .collect()
+}
+
+fn encode_rgb16(pixels: &[Vec<Vec3>], settings: &ExportSettings) -> Vec<u16> {
+    let settings = &ExportSettings {
+        bit_depth: BitDepth::Sixteen,
+        ..*settings
+    };
+    quantize_all(pixels, settings)
+        .flat_map(|pixel| [pixel.x, pixel.y, pixel.z].map(|c| c as u16))
+        .collect()
+}
+
+// PNG samples at the bit depth of the settings. Sixteen bit samples are big
+// endian.
+fn encode_png(pixels: &[Vec<Vec3>], settings: &ExportSettings) -> Vec<u8> {
+    match settings.bit_depth {
+        BitDepth::Eight => encode_rgb8(pixels, settings),
+        BitDepth::Sixteen => encode_rgb16(pixels, settings)
+            .into_iter()
+            .flat_map(u16::to_be_bytes)
+            .collect(),
+    }
+}
+
+fn png_depth(bit_depth: BitDepth) -> png::BitDepth {
+    match bit_depth {
+        BitDepth::Eight => png::BitDepth::Eight,
+        BitDepth::Sixteen => png::BitDepth::Sixteen,
+    }
+}
+
+// The pixels as 8-bit RGB values, row by row, encoded as they would be
+// exported.
+pub fn to_rgb8(pixels: &[Vec<Vec3>], settings: &ExportSettings) -> Vec<u8> {
+    encode_rgb8(pixels, &settings.resolve_exposure(pixels.iter().flatten()))
+}
+
+// The pixels as 16-bit RGB values, like to_rgb8.
+pub fn to_rgb16(pixels: &[Vec<Vec3>], settings: &ExportSettings) -> Vec<u16> {
+    encode_rgb16(pixels, &settings.resolve_exposure(pixels.iter().flatten()))
+}
+
+pub fn export_png(
+    path: &str,
+    pixels: &[Vec<Vec3>],
+    settings: &ExportSettings,
+) -> Result<(), RenderError> {
+    let (width, height) = image_size(pixels)?;
+    let settings = &settings.resolve_exposure(pixels.iter().flatten());
+
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png_depth(settings.bit_depth));
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&encode_png(pixels, settings))?;
+    Ok(())
+}
+
+// Writes the frames of an animation into the directory as numbered PNG files.
+// All frames are encoded with the same settings, so that an exposure chosen
+// for the animation doesn't flicker from frame to frame.
+pub fn export_sequence(
+    frames: &[Vec<Vec<Vec3>>],
+    settings: &ExportSettings,
+    dir: &str,
+) -> Result<(), RenderError> {
+    std::fs::create_dir_all(dir)?;
+    let settings = &settings.resolve_exposure(frames.iter().flatten().flatten());
+
+    for (i, frame) in frames.iter().enumerate() {
+        let path = Path::new(dir).join(format!("frame_{:04}.png", i));
+        export_png(&path.to_string_lossy(), frame, settings)?;
+    }
+
+    Ok(())
+}
+
+// Writes the frames into a single looping animated PNG, showing each frame for
+// the given number of milliseconds. There must be at least one frame, and all
+// of them the same size.
+pub fn export_apng(
+    path: &str,
+    frames: &[Vec<Vec<Vec3>>],
+    settings: &ExportSettings,
+    frame_delay: u16,
+) -> Result<(), RenderError> {
+    let (width, height) = match frames.first() {
+        Some(frame) => image_size(frame)?,
+        None => {
+            return Err(RenderError::InvalidSettings(
+                "Can't export an animation without frames.".to_string(),
+            ))
+        }
+    };
+    if frames
+        .iter()
+        .any(|frame| frame.len() != height || frame.iter().any(|row| row.len() != width))
+    {
+        return Err(RenderError::InvalidSettings(
+            "The frames of an animation must all be the same size.".to_string(),
+        ));
+    }
+    let settings = &settings.resolve_exposure(frames.iter().flatten().flatten());
+
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png_depth(settings.bit_depth));
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(frame_delay, 1000)?;
+
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(&encode_png(frame, settings))?;
+    }
+    writer.finish()?;
+    Ok(())
 }
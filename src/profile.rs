@@ -0,0 +1,110 @@
+use std::cmp::Reverse;
+use std::fmt;
+
+// What marching one ray took, for finding out which parts of a scene the
+// steps are spent on. Each step is attributed to the profiled map nearest to
+// the point it was taken from, see `SdfMap::profile`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RayProfile {
+    pub steps: u32,
+    // Steps by the name of the profiled map they were nearest to, in the
+    // order the maps were first nearest. Steps nearest to a map that isn't
+    // profiled aren't listed.
+    pub objects: Vec<(&'static str, u32)>,
+}
+
+impl RayProfile {
+    pub fn record_step(&mut self, nearest: Option<&'static str>) {
+        self.steps += 1;
+        let name = match nearest {
+            Some(name) => name,
+            None => return,
+        };
+        match self.objects.iter_mut().find(|(object, _)| *object == name) {
+            Some((_, steps)) => *steps += 1,
+            None => self.objects.push((name, 1)),
+        }
+    }
+}
+
+/// The profiles of many rays added up, like those of the camera rays of an
+/// image. A ray straight down onto a profiled plane takes all its steps
+/// near the plane:
+///
+/// ```
+/// use glam::Vec3;
+/// use raymarching_pathtracer::profile::Report;
+/// use raymarching_pathtracer::sdf::{plane, Material, RayMask, Sdf, SdfMap};
+///
+/// let ground = plane(Vec3::Z)
+///     .material(Material::Lambertian { color: Vec3::ONE })
+///     .profile("ground");
+/// let (hit, profile) =
+///     ground.ray_intersection_profiled(Vec3::new(0.0, 0.0, 2.0), -Vec3::Z, RayMask::CAMERA, 0.5);
+/// assert!(hit.is_some());
+///
+/// let mut report = Report::default();
+/// report.add(&profile);
+/// assert_eq!(report.rays, 1);
+/// assert!(report.max_steps > 1);
+/// assert_eq!(report.objects, vec![("ground", report.steps)]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub rays: u64,
+    pub steps: u64,
+    pub max_steps: u64,
+    // Steps by the name of the profiled map they were nearest to, with the
+    // map that took the most first.
+    pub objects: Vec<(&'static str, u64)>,
+}
+
+impl Report {
+    pub fn add(&mut self, profile: &RayProfile) {
+        self.rays += 1;
+        self.steps += profile.steps as u64;
+        self.max_steps = self.max_steps.max(profile.steps as u64);
+        for &(name, steps) in &profile.objects {
+            self.add_object(name, steps as u64);
+        }
+    }
+
+    // Adds up two reports, as when they were made in parallel.
+    pub fn merge(mut self, other: Report) -> Report {
+        self.rays += other.rays;
+        self.steps += other.steps;
+        self.max_steps = self.max_steps.max(other.max_steps);
+        for (name, steps) in other.objects {
+            self.add_object(name, steps);
+        }
+        self
+    }
+
+    fn add_object(&mut self, name: &'static str, steps: u64) {
+        match self.objects.iter_mut().find(|(object, _)| *object == name) {
+            Some((_, total)) => *total += steps,
+            None => self.objects.push((name, steps)),
+        }
+        self.objects.sort_by_key(|&(_, steps)| Reverse(steps));
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mean_steps = self.steps as f64 / self.rays.max(1) as f64;
+        writeln!(
+            f,
+            "Rays: {}, steps per ray: {:.1} on average, {} at most",
+            self.rays, mean_steps, self.max_steps
+        )?;
+        for (name, steps) in &self.objects {
+            writeln!(
+                f,
+                "{}: {:.1}% of the steps",
+                name,
+                100.0 * *steps as f64 / self.steps.max(1) as f64
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,63 +1,986 @@
+use super::background::{Background, BackgroundPreset, BackgroundSampler};
 use super::camera::*;
-use super::sampling;
+use super::composite::Layer;
+use super::error::RenderError;
+use super::export::{ExportSettings, ToneMap};
+use super::light::Light;
+use super::ppm;
+use super::profile::Report;
+use super::sampling::{self, RngSampler, Sampler};
 use super::sdf::*;
-use glam::Vec3;
-use rand::Rng;
+use super::sobol::Sobol;
+use super::spectral;
+use glam::{const_vec3, vec3, Vec3, Vec4};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rayon::prelude::*;
+use std::f32::consts::PI;
+use std::ops::{Add, Mul, Range};
+use std::time::{Duration, Instant};
 
 const MAX_BOUNCES: i32 = 4;
+const EMISSIVE_MIN_COS: f32 = 0.01;
+const DIFFUSE_SPREAD: f32 = 0.5;
+const CLAY: Surface = Surface::Lambertian {
+    color: const_vec3!([0.5, 0.5, 0.5]),
+};
+const PREVIEW_SHADOW_HARDNESS: f32 = 16.0;
+const PREVIEW_AMBIENT: f32 = 0.2;
+const MAX_PREVIEW_DIST: f32 = 30.0;
+const MAX_NESTED_MEDIA: usize = 4;
+// Pairs of sampler dimensions taken by the camera, for the pixel and the lens
+// positions, before the bounces of a path take theirs.
+const CAMERA_DIMENSIONS: u32 = 2;
+const SLICE_ISOLINE_SPACING: f32 = 0.1;
+const MARCH_FAILURE_COLOR: Vec3 = const_vec3!([1.0, 0.0, 1.0]);
+
+// Scene-linear HDR radiance as traced, before any exposure, tone mapping,
+// clamping or encoding. Those are only applied when exporting.
+#[derive(Clone, Debug)]
+pub struct RenderResult {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec<Vec3>>,
+}
+
+impl From<Vec<Vec<Vec3>>> for RenderResult {
+    fn from(pixels: Vec<Vec<Vec3>>) -> Self {
+        Self {
+            width: pixels.first().map_or(0, |row| row.len()),
+            height: pixels.len(),
+            pixels,
+        }
+    }
+}
+
+impl RenderResult {
+    pub fn to_ppm(&self, path: &str, settings: &ExportSettings) -> Result<(), RenderError> {
+        ppm::export_ppm(path, &self.pixels, settings)
+    }
+
+    pub fn to_png(&self, path: &str, settings: &ExportSettings) -> Result<(), RenderError> {
+        ppm::export_png(path, &self.pixels, settings)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub width: i32,
+    pub height: i32,
+    pub sample_count: i32,
+    // Light paths traced from each camera sample. The camera ray is marched
+    // once and shared by its paths, so each extra path costs its bounces
+    // but not the first hit. A pixel takes sample_count * paths_per_sample
+    // paths in all. Camera samples smooth edges and depth of field, while
+    // paths only reduce the noise of the lighting.
+    pub paths_per_sample: i32,
+    // Number of worker threads, or None to use the global rayon pool. Every
+    // path is seeded by its pixel, so the image is the same either way.
+    pub threads: Option<usize>,
+    // Renders every surface in neutral gray, to inspect the geometry and the
+    // lighting on their own.
+    pub clay: bool,
+    // Number of bounces after the first hit. With zero bounces, only the
+    // light arriving directly at the first hit is rendered.
+    pub max_bounces: i32,
+    // Shows paths where marching gave up in magenta instead of black. Rays
+    // give up when they run out of steps, or out of distance while still
+    // closing in on a surface, as they do slipping through the seams of a
+    // closed scene.
+    pub show_march_failures: bool,
+    // Scales every marching step. Values below one keep rays from tunneling
+    // through fields that overestimate the distance, like twisted or
+    // displaced ones and fractals.
+    pub step_scale: f32,
+    // Renders this many times the width and height and averages the result
+    // down, so that every pixel is sampled over a grid of sub-pixels. The
+    // sample count is per sub-pixel, so a factor of 2 takes four times the
+    // samples and the time. That gives cleaner edges than no supersampling
+    // at the same sample count, but only about as clean as no supersampling
+    // at four times the sample count, as the samples of a pixel are already
+    // jittered and stratified over it.
+    pub ssaa: u32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            sample_count: 100,
+            paths_per_sample: 1,
+            threads: None,
+            clay: false,
+            max_bounces: MAX_BOUNCES,
+            show_march_failures: false,
+            step_scale: 1.0,
+            ssaa: 1,
+        }
+    }
+}
+
+// Quality presets, trading render time for less noise and more bounces of
+// indirect light.
+impl RenderSettings {
+    pub fn preview() -> Self {
+        Self {
+            sample_count: 16,
+            max_bounces: 2,
+            ..Self::default()
+        }
+    }
+
+    pub fn medium() -> Self {
+        Self {
+            sample_count: 100,
+            max_bounces: MAX_BOUNCES,
+            ..Self::default()
+        }
+    }
+
+    pub fn high() -> Self {
+        Self {
+            sample_count: 1000,
+            max_bounces: 8,
+            // Shorter steps guard against holes in fields that aren't exact
+            // distances.
+            step_scale: 0.8,
+            ..Self::default()
+        }
+    }
+}
+
+fn validate_size(width: i32, height: i32) -> Result<(), RenderError> {
+    if width <= 0 || height <= 0 {
+        Err(RenderError::InvalidSettings(
+            "The image must be at least one pixel wide and high.".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn validate(&self) -> Result<(), RenderError> {
+        validate_size(self.width, self.height)?;
+        let invalid = |message: &str| Err(RenderError::InvalidSettings(message.to_string()));
+        if self.sample_count <= 0 {
+            invalid("The sample count must be positive.")
+        } else if self.paths_per_sample <= 0 {
+            invalid("The number of paths per sample must be positive.")
+        } else if self.max_bounces < 0 {
+            invalid("The number of bounces must not be negative.")
+        } else if self.threads == Some(0) {
+            invalid("The number of threads must be positive.")
+        } else if !(self.step_scale > 0.0 && self.step_scale.is_finite()) {
+            invalid("The step scale must be a positive number.")
+        } else if self.ssaa == 0 {
+            invalid("The supersampling factor must be positive.")
+        } else {
+            Ok(())
+        }
+    }
+
+    // The settings for rendering the sub-pixels.
+    fn supersampled(&self) -> Self {
+        let factor = self.ssaa.max(1) as i32;
+        Self {
+            width: factor * self.width,
+            height: factor * self.height,
+            ssaa: 1,
+            ..*self
+        }
+    }
+
+    // A thread pool of the configured size, or None for the global pool.
+    pub fn thread_pool(&self) -> Result<Option<rayon::ThreadPool>, RenderError> {
+        match self.threads {
+            Some(threads) => Ok(Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    // Runs `op` in a thread pool of the configured size, so that the parallel
+    // iterators inside it are limited to that many threads.
+    pub fn install<T: Send>(
+        &self,
+        op: impl FnOnce() -> Result<T, RenderError> + Send,
+    ) -> Result<T, RenderError> {
+        match self.thread_pool()? {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+}
 
 pub struct Scene {
     pub camera: Camera,
     pub map: Box<dyn SdfMap>,
-    pub background_color: Box<dyn Fn(Vec3) -> Vec3 + Sync>,
+    // Linear RGB radiance, like the colors of materials.
+    pub background_color: Box<Background>,
+    pub lights: Vec<Light>,
+    pub background_sampler: Option<BackgroundSampler>,
+    pub tone_map: ToneMap,
+    pub exposure: f32,
+    // Scales the light of the background, but not of the lights it shows.
+    pub env_intensity: f32,
+    pub suggested_settings: Option<RenderSettings>,
+}
+
+impl Scene {
+    // The map seen by the camera against a black background, with no lights
+    // and the default tone mapping, exposure and settings. The other fields
+    // can be set afterwards.
+    pub fn new(camera: Camera, map: impl SdfMap + 'static) -> Self {
+        Self {
+            camera,
+            map: Box::new(map),
+            background_color: Box::new(|_| Vec3::ZERO),
+            lights: vec![],
+            background_sampler: None,
+            tone_map: ToneMap::default(),
+            exposure: 1.0,
+            env_intensity: 1.0,
+            suggested_settings: None,
+        }
+    }
+
+    // Export settings with the tone mapping and exposure of the scene.
+    pub fn export_settings(&self) -> ExportSettings {
+        ExportSettings {
+            exposure: self.exposure,
+            tone_map: self.tone_map,
+            ..ExportSettings::default()
+        }
+    }
+
+    /// The background along the ray, with the environment scaled by
+    /// `env_intensity`. Lights such as the sun are sampled directly, so the
+    /// directions they cover are left unscaled:
+    ///
+    /// ```
+    /// use glam::{vec3, Vec3};
+    /// use raymarching_pathtracer::{background, camera::*, light::Light, renderer::*, sdf::*};
+    ///
+    /// let sun = vec3(1.0, 0.0, 1.0).normalize();
+    /// let camera = Camera::new(vec3(0.0, 0.0, 3.0), Vec3::ZERO, Vec3::Y, 0.5, 1.0, 0.0);
+    /// let white = Material::Lambertian { color: Vec3::ONE };
+    /// let mut scene = Scene::new(camera, plane(Vec3::Z).material(white));
+    /// scene.background_color = background::directional(|_| Vec3::ONE);
+    /// let radiance = Vec3::ONE;
+    /// scene.lights = vec![Light::Directional { direction: sun, cos_radius: 0.99, radiance }];
+    /// scene.env_intensity = 2.0;
+    ///
+    /// let ray = |direction| Ray { origin: Vec3::ZERO, direction };
+    /// assert_eq!(scene.background(&ray(Vec3::Z)), Vec3::splat(2.0));
+    /// assert_eq!(scene.background(&ray(sun)), Vec3::ONE);
+    /// ```
+    pub fn background(&self, ray: &Ray) -> Vec3 {
+        let radiance = (self.background_color)(ray);
+        if self.lights.iter().any(|light| light.covers(ray.direction)) {
+            radiance
+        } else {
+            self.env_intensity * radiance
+        }
+    }
+
+    /// The distance from the point to the closest surface of the scene,
+    /// negative inside objects, for effects like a glow around surfaces.
+    /// Every object counts, whatever its ray mask:
+    ///
+    /// ```
+    /// use glam::{vec3, Vec3};
+    /// use raymarching_pathtracer::{camera::Camera, renderer::*, sdf::*};
+    ///
+    /// let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 0.5, 1.0, 0.0);
+    /// let ball = sphere(1.0).material(Material::Lambertian { color: Vec3::ONE });
+    /// let scene = Scene::new(camera, ball.ray_mask(RayMask::SHADOW));
+    ///
+    /// assert!(scene.distance_to_nearest(vec3(1.0, 2.0, 2.0).normalize()).abs() < 1e-6);
+    /// assert!((scene.distance_to_nearest(vec3(0.0, 0.0, 3.0)) - 2.0).abs() < 1e-6);
+    /// assert!(scene.distance_to_nearest(Vec3::ZERO) < 0.0);
+    /// ```
+    pub fn distance_to_nearest(&self, p: Vec3) -> f32 {
+        self.map.dist(p, RayMask::NONE)
+    }
+
+    /// Replaces the background with a preset, along with the directional
+    /// lights, like the sun, that are part of it. Other lights and emitters
+    /// stay, so with `Black` only the emitters light the scene:
+    ///
+    /// ```
+    /// use glam::{vec3, Vec3};
+    /// use raymarching_pathtracer::{background::*, camera::Camera, renderer::*, sdf::*};
+    ///
+    /// let scene = |ball: Material| {
+    ///     let camera = Camera::new(vec3(0.0, -4.0, 2.0), Vec3::ZERO, Vec3::Z, 1.0, 1.0, 0.0);
+    ///     let ground = plane(Vec3::Z).material(Material::Lambertian { color: Vec3::ONE });
+    ///     let ball = sphere(0.5).position(0.5 * Vec3::Z).material(ball);
+    ///     let mut scene = Scene::new(camera, ground.merge(ball));
+    ///     (scene.background_color, scene.lights) = (sky_sun().0, vec![sky_sun().1]);
+    ///     scene
+    /// };
+    /// let (width, height, sample_count) = (8, 8, 4);
+    /// let settings = RenderSettings { width, height, sample_count, ..Default::default() };
+    /// let light = |scene: &Scene| render(&settings, scene).unwrap().iter().flatten().sum::<Vec3>();
+    ///
+    /// let mut lit = scene(Material::Emissive { color: Vec3::ONE, intensity: 1.0 });
+    /// let mut unlit = scene(Material::Lambertian { color: Vec3::ONE });
+    /// assert!(light(&unlit).length() > 0.0);
+    /// lit.set_background(&BackgroundPreset::Black).unwrap();
+    /// unlit.set_background(&BackgroundPreset::Black).unwrap();
+    /// assert!(unlit.lights.is_empty());
+    /// assert_eq!(light(&unlit), Vec3::ZERO);
+    /// assert!(light(&lit).length() > 0.0);
+    /// ```
+    pub fn set_background(&mut self, preset: &BackgroundPreset) -> Result<(), RenderError> {
+        let (background, lights) = preset.build()?;
+        self.background_color = background;
+        self.lights
+            .retain(|light| !matches!(light, Light::Directional { .. }));
+        self.lights.extend(lights);
+        self.background_sampler = None;
+        Ok(())
+    }
+
+    // Checks what a scene can get wrong without failing to build, but which
+    // would spoil the render with NaNs or a black image.
+    pub fn validate(&self) -> Result<(), RenderError> {
+        let malformed = |message: &str| Err(RenderError::MalformedScene(message.to_string()));
+        if !(self.exposure > 0.0 && self.exposure.is_finite()) {
+            malformed("The exposure must be a positive number.")
+        } else if !(self.env_intensity >= 0.0 && self.env_intensity.is_finite()) {
+            malformed("The environment intensity must be a non-negative number.")
+        } else if self
+            .lights
+            .iter()
+            .any(|light| !(light.power() >= 0.0 && light.power().is_finite()))
+        {
+            malformed("Every light must have a finite, non-negative power.")
+        } else if let Some(Err(error)) = self.suggested_settings.map(|settings| settings.validate())
+        {
+            malformed(&format!("The suggested settings are invalid: {}", error))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Ratio of the Oren-Nayar BRDF to the Lambertian one for incoming direction
+// `wi` and outgoing direction `wo`. Roughness is the standard deviation of the
+// microfacet slope angle in radians; zero gives a Lambertian surface.
+fn oren_nayar(normal: Vec3, wo: Vec3, wi: Vec3, roughness: f32) -> f32 {
+    let s2 = roughness * roughness;
+    let a = 1.0 - 0.5 * s2 / (s2 + 0.33);
+    let b = 0.45 * s2 / (s2 + 0.09);
+
+    let cos_i = normal.dot(wi).clamp(0.0, 1.0);
+    let cos_o = normal.dot(wo).clamp(0.0, 1.0);
+    let sin_i = (1.0 - cos_i * cos_i).sqrt();
+    let sin_o = (1.0 - cos_o * cos_o).sqrt();
+
+    let cos_phi = (wi - cos_i * normal)
+        .normalize_or_zero()
+        .dot((wo - cos_o * normal).normalize_or_zero())
+        .max(0.0);
+
+    let (sin_alpha, tan_beta) = if cos_i < cos_o {
+        (sin_i, sin_o / cos_o.max(1e-4))
+    } else {
+        (sin_o, sin_i / cos_i.max(1e-4))
+    };
+
+    a + b * cos_phi * sin_alpha * tan_beta
+}
+
+// Ratio of the energy-normalized Blinn-Phong specular lobe to the Lambertian
+// BRDF, for incoming direction `wi` and outgoing direction `wo`. The
+// normalization keeps the highlight as bright overall as it tightens.
+fn blinn_phong(normal: Vec3, wo: Vec3, wi: Vec3, shininess: f32) -> f32 {
+    let half = (wi + wo).normalize_or_zero();
+    (shininess + 8.0) / 8.0 * normal.dot(half).max(0.0).powf(shininess)
+}
+
+// Veach's power heuristic with an exponent of two: the weight of a sample
+// drawn with density `pdf` that another strategy could have drawn with
+// density `other_pdf`.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    pdf * pdf / (pdf * pdf + other_pdf * other_pdf)
+}
+
+// Background radiance along an escaping ray, excluding the parts that are
+// handled as lights.
+fn background_radiance(scene: &Scene, ray: &Ray) -> Vec3 {
+    if scene.lights.iter().any(|light| light.covers(ray.direction)) {
+        Vec3::ZERO
+    } else {
+        scene.env_intensity * (scene.background_color)(ray)
+    }
+}
+
+// Samples the background with the scene's background sampler. The light
+// reflected by `brdf` is weighted against the cosine-weighted sampling of the
+// diffuse bounce with the power heuristic, see the miss case in cast_ray.
+// The light reflected by `unsampled`, a lobe the bounce never samples, has
+// no other estimate and keeps its full weight.
+fn sample_background(
+    scene: &Scene,
+    settings: &RenderSettings,
+    origin: Vec3,
+    normal: Vec3,
+    brdf: impl Fn(Vec3) -> Vec3,
+    unsampled: impl Fn(Vec3) -> Vec3,
+    rng: &mut impl Sampler,
+) -> Vec3 {
+    let sampler = match &scene.background_sampler {
+        Some(sampler) => sampler,
+        None => return Vec3::ZERO,
+    };
+
+    let (direction, pdf) = (sampler.sample)(rng);
+    let cos = normal.dot(direction);
+
+    if cos <= 0.0
+        || pdf <= 0.0
+        || scene
+            .map
+            .ray_intersection(origin, direction, RayMask::SHADOW, settings.step_scale)
+            .is_some()
+    {
+        return Vec3::ZERO;
+    }
+
+    let weight = power_heuristic(pdf, cos / PI);
+    let radiance = background_radiance(scene, &Ray { origin, direction });
+    (weight * brdf(direction) + unsampled(direction)) * cos / PI * radiance / pdf
+}
+
+// Picks a light with probability proportional to its power, or uniformly if
+// none of them has any, and returns it with that probability.
+fn choose_light(lights: &[Light], u: f32) -> Option<(&Light, f32)> {
+    let total: f32 = lights.iter().map(Light::power).sum();
+
+    if lights.is_empty() {
+        return None;
+    } else if total <= 0.0 {
+        let i = ((u * lights.len() as f32) as usize).min(lights.len() - 1);
+        return Some((&lights[i], 1.0 / lights.len() as f32));
+    }
+
+    // Rounding can leave the target just past the last light with any power.
+    let mut target = u * total;
+    let mut chosen = None;
+    for light in lights {
+        let power = light.power();
+        if power > 0.0 {
+            chosen = Some((light, power / total));
+            if target < power {
+                break;
+            }
+            target -= power;
+        }
+    }
+    chosen
+}
+
+// Light arriving directly from the scene's lights at a diffuse surface, to be
+// multiplied by the albedo and path throughput. `brdf` is the ratio of the
+// surface's BRDF to the Lambertian one for a given incoming direction, per
+// color channel, and `unsampled` the same for a lobe that the bounce from
+// the surface never samples. A single light is sampled, chosen by choose_light, and its
+// light divided by the probability of choosing it. This keeps the cost of direct lighting the
+// same however many lights there are, and spends the shadow rays on the
+// lights that matter most.
+fn sample_lights(
+    scene: &Scene,
+    settings: &RenderSettings,
+    position: Vec3,
+    normal: Vec3,
+    brdf: impl Fn(Vec3) -> Vec3,
+    unsampled: impl Fn(Vec3) -> Vec3,
+    rng: &mut impl Sampler,
+) -> Vec3 {
+    let origin = position + 2.0 * SURFACE_DIST * normal;
+    let background = sample_background(scene, settings, origin, normal, &brdf, &unsampled, rng);
+
+    let (light, probability) = match choose_light(&scene.lights, rng.next_f32()) {
+        Some(choice) => choice,
+        None => return background,
+    };
+
+    // The cosine of the receiving surface is the only geometry term. Point
+    // lights include the falloff in the weight.
+    let sample = light.sample(origin, rng);
+    let cos = normal.dot(sample.direction);
+
+    if cos <= 0.0 {
+        return background;
+    }
+
+    match scene.map.ray_intersection(
+        origin,
+        sample.direction,
+        RayMask::SHADOW,
+        settings.step_scale,
+    ) {
+        Some(hit_info) if (hit_info.position - origin).length() < sample.distance => background,
+        _ => {
+            let brdf = brdf(sample.direction) + unsampled(sample.direction);
+            background + brdf * cos / PI * sample.weight / probability
+        }
+    }
 }
 
-fn cast_ray(scene: &Scene, mut origin: Vec3, mut direction: Vec3) -> Vec3 {
+#[derive(Clone, Debug)]
+pub struct Bounce {
+    pub depth: i32,
+    pub position: Vec3,
+    pub material: Material,
+    pub throughput: Vec3,
+}
+
+fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+    direction - 2.0 * direction.dot(normal) * normal
+}
+
+// The fraction of a spotlight's emission seen along a ray, smoothly falling
+// from one along the spot direction to zero at the edge of its cone. A cone
+// without any width lets no light out.
+fn spotlight_falloff(ray_direction: Vec3, spot_direction: Vec3, cos_radius: f32) -> f32 {
+    if cos_radius >= 1.0 {
+        return 0.0;
+    }
+    let cos = -ray_direction.dot(spot_direction.normalize());
+    let t = ((cos - cos_radius) / (1.0 - cos_radius)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Schlick's approximation of the Fresnel reflectance, with `cos` measured on
+// the optically thinner side of the interface.
+fn schlick(cos: f32, ior: f32) -> f32 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
+// The indices of refraction of the dielectrics a path is inside of, with the
+// innermost last. Outside all of them the path travels through air.
+#[derive(Clone, Copy, Default)]
+struct Media {
+    iors: [f32; MAX_NESTED_MEDIA],
+    len: usize,
+}
+
+impl Media {
+    fn current(&self) -> f32 {
+        match self.len {
+            0 => 1.0,
+            len => self.iors[len - 1],
+        }
+    }
+
+    // Media nested deeper than the stack can hold are ignored.
+    fn push(&mut self, ior: f32) {
+        if self.len < MAX_NESTED_MEDIA {
+            self.iors[self.len] = ior;
+            self.len += 1;
+        }
+    }
+
+    // The medium being left need not be the innermost one when objects
+    // overlap, so the innermost medium with that index is removed.
+    fn remove(&mut self, ior: f32) {
+        if let Some(i) = self.iors[..self.len].iter().rposition(|&n| n == ior) {
+            self.iors.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+    }
+}
+
+fn dielectric_ior(ior: f32, dispersion: f32, wavelength: Option<f32>) -> f32 {
+    match wavelength {
+        Some(wavelength) => spectral::index_of_refraction(ior, dispersion, wavelength),
+        None => ior,
+    }
+}
+
+// The dielectric that p is inside of, if any, as its index of refraction and
+// color.
+fn medium_at(
+    scene: &Scene,
+    p: Vec3,
+    mask: RayMask,
+    wavelength: Option<f32>,
+) -> Option<(f32, Vec3)> {
+    match scene.map.distinfo(p, mask) {
+        DistInfo {
+            distance,
+            material:
+                Material::Dielectric {
+                    color,
+                    ior,
+                    dispersion,
+                },
+        } if distance < 0.0 => Some((dielectric_ior(ior, dispersion, wavelength), color)),
+        _ => None,
+    }
+}
+
+// Mean curvature of the surface through p, from the Laplacian of the field.
+fn mean_curvature(scene: &Scene, p: Vec3, mask: RayMask) -> f32 {
+    let h = 10.0 * SURFACE_DIST;
+    let d = scene.map.dist(p, mask);
+    let laplacian = [Vec3::X, Vec3::Y, Vec3::Z]
+        .iter()
+        .map(|e| scene.map.dist(p + h * *e, mask) + scene.map.dist(p - h * *e, mask) - 2.0 * d)
+        .sum::<f32>()
+        / (h * h);
+    0.5 * laplacian
+}
+
+// In spectral mode, the path carries radiance at a single wavelength in all
+// three channels, and the RGB colors of the scene are converted to it.
+//
+// The path also tracks a ray cone, starting with the spread of a pixel, whose
+// width at a hit is the footprint over which textures are filtered.
+fn cast_ray(
+    scene: &Scene,
+    settings: &RenderSettings,
+    ray: &Ray,
+    primary: &March,
+    wavelength: Option<f32>,
+    sampler: &mut Sobol,
+    mut trace: Option<&mut Vec<Bounce>>,
+) -> Vec3 {
+    let tint = |color: Vec3| match wavelength {
+        Some(wavelength) => Vec3::splat(spectral::rgb_to_spectrum(color, wavelength)),
+        None => color,
+    };
+
+    let (mut origin, mut direction) = (ray.origin, ray.direction);
+    // Random choices along the path that don't take dimensions of the
+    // sampler, seeded by the path so that renders are reproducible.
+    let mut rng = RngSampler(StdRng::seed_from_u64(sampler.path_seed()));
     let mut acc = Vec3::ONE;
+    let mut direct = Vec3::ZERO;
     let mut bounces = 0;
+    let mut mask = RayMask::CAMERA;
+    let mut bsdf_pdf = 0.0;
+    let mut spread = scene.camera.pixel_spread(settings.width);
+    let mut cone_width = 0.0;
+    let mut media = Media::default();
 
     loop {
-        if bounces > MAX_BOUNCES {
+        if bounces > settings.max_bounces {
             acc = Vec3::ZERO;
             break;
         }
 
-        match scene.map.ray_intersection(origin, direction) {
-            Some(hit_info) => match hit_info.material {
-                Material::Lambertian { color } => {
-                    acc = color * acc;
-                    let normal = scene.map.normal(hit_info.position);
+        // Russian roulette: a path whose throughput has dropped is ended at
+        // random, and the paths that go on carry the light of those that
+        // didn't, so that dim paths are cut short without darkening the
+        // image.
+        let survival = acc.max_element().min(1.0);
+        if survival < 1.0 {
+            if rng.next_f32() >= survival {
+                acc = Vec3::ZERO;
+                break;
+            }
+            acc /= survival;
+        }
+
+        // The camera ray is marched by the caller, once for all its paths.
+        let march = if bounces == 0 {
+            primary.clone()
+        } else {
+            scene
+                .map
+                .march(origin, direction, mask, settings.step_scale)
+        };
+        let hit = match march {
+            March::Hit(hit_info) => Some(hit_info),
+            March::Escaped => None,
+            // A ray that gave up most likely slipped through a gap, so it
+            // mustn't pick up the background behind the surface.
+            March::GaveUp if settings.show_march_failures => return tint(MARCH_FAILURE_COLOR),
+            March::GaveUp => {
+                acc = Vec3::ZERO;
+                break;
+            }
+        };
+
+        // Each bounce draws its direction from the next pair of dimensions,
+        // whether or not it ends up needing it.
+        let bounce_sample = sampler.next_2d();
+
+        if let (Some(trace), Some(hit_info)) = (trace.as_deref_mut(), &hit) {
+            trace.push(Bounce {
+                depth: bounces,
+                position: hit_info.position,
+                material: hit_info.material.clone(),
+                throughput: acc,
+            });
+        }
+
+        // The normal is perturbed by bumps, and textures are filtered over
+        // the width of the ray cone.
+        let hit = hit.map(|hit_info| {
+            cone_width += spread * (hit_info.position - origin).length();
+            let normal = scene
+                .map
+                .normal_cached(hit_info.position, hit_info.distance, mask);
+            let normal = match &hit_info.material {
+                Material::Bumped { height, .. } => bumped_normal(normal, hit_info.position, height),
+                _ => normal,
+            };
+            let surface = hit_info
+                .material
+                .resolve(hit_info.position, normal, cone_width);
+            // Emitters are kept in clay mode, as they are the lights of
+            // the scene, and so are holdouts, which stand in for the plate.
+            let surface = match surface {
+                Surface::Emissive { .. } | Surface::Spotlight { .. } | Surface::Holdout => surface,
+                _ if settings.clay => CLAY,
+                _ => surface,
+            };
+            (hit_info, surface, normal)
+        });
+
+        match hit {
+            Some((hit_info, surface, normal)) => match surface {
+                Surface::Lambertian { color } => {
+                    acc = tint(color) * acc;
+                    direct += acc
+                        * tint(sample_lights(
+                            scene,
+                            settings,
+                            hit_info.position,
+                            normal,
+                            |_| Vec3::ONE,
+                            |_| Vec3::ZERO,
+                            &mut rng,
+                        ));
+                    origin = hit_info.position + 2.0 * SURFACE_DIST * normal;
+                    (direction, bsdf_pdf) =
+                        sampling::cos_weighted_hemisphere_pdf(normal, bounce_sample);
+                    spread = DIFFUSE_SPREAD;
+                    mask = RayMask::SHADOW;
+                }
+                Surface::OrenNayar { color, roughness } => {
+                    let wo = -direction;
+                    direct += tint(color)
+                        * acc
+                        * tint(sample_lights(
+                            scene,
+                            settings,
+                            hit_info.position,
+                            normal,
+                            |wi| Vec3::splat(oren_nayar(normal, wo, wi, roughness)),
+                            |_| Vec3::ZERO,
+                            &mut rng,
+                        ));
                     origin = hit_info.position + 2.0 * SURFACE_DIST * normal;
-                    direction = sampling::cos_weighted_hemisphere(normal);
+                    (direction, bsdf_pdf) =
+                        sampling::cos_weighted_hemisphere_pdf(normal, bounce_sample);
+                    acc = oren_nayar(normal, wo, direction, roughness) * tint(color) * acc;
+                    spread = DIFFUSE_SPREAD;
+                    mask = RayMask::SHADOW;
                 }
-                Material::Emissive { color } => {
-                    acc = color * acc;
+                Surface::Emissive { color, intensity } => {
+                    // A ray grazing a thin `Shell` can step across it and
+                    // report a hit on its far side. Only front-facing hits
+                    // emit, so such a shell doesn't glow through itself.
+                    if -direction.dot(normal) > EMISSIVE_MIN_COS {
+                        acc = intensity * tint(color) * acc;
+                    } else {
+                        acc = Vec3::ZERO;
+                    }
                     break;
                 }
-                Material::Specular {
+                Surface::Spotlight {
+                    color,
+                    intensity,
+                    direction: spot_direction,
+                    cos_radius,
+                } => {
+                    if -direction.dot(normal) > EMISSIVE_MIN_COS {
+                        let falloff = spotlight_falloff(direction, spot_direction, cos_radius);
+                        acc = falloff * intensity * tint(color) * acc;
+                    } else {
+                        acc = Vec3::ZERO;
+                    }
+                    break;
+                }
+                // Camera rays hitting a holdout are handled by the caller,
+                // so this is a path ray, which the holdout blocks.
+                Surface::Holdout => {
+                    acc = Vec3::ZERO;
+                    break;
+                }
+                Surface::Specular {
                     color,
                     specularity,
                     fuzziness,
                 } => {
-                    let normal = scene.map.normal(hit_info.position);
                     origin = hit_info.position + 2.0 * SURFACE_DIST * normal;
-                    if rand::random::<f32>() < specularity {
-                        let specular = direction - 2.0 * direction.dot(normal) * normal;
-                        direction = (specular + fuzziness * sampling::uniform_ball()).normalize();
+                    if rng.next_f32() < specularity {
+                        let specular = reflect(direction, normal);
+                        direction =
+                            (specular + fuzziness * sampling::uniform_ball(&mut rng)).normalize();
 
                         if direction.dot(normal) < 0.0 {
                             acc = Vec3::ZERO;
                             break;
                         }
+                        // A curved mirror widens or narrows the cone.
+                        spread +=
+                            2.0 * mean_curvature(scene, hit_info.position, mask).abs() * cone_width;
+                        mask = RayMask::REFLECTION;
                     } else {
-                        acc = color * acc;
-                        direction = sampling::cos_weighted_hemisphere(normal);
+                        acc = tint(color) * acc;
+                        direct += acc
+                            * tint(sample_lights(
+                                scene,
+                                settings,
+                                hit_info.position,
+                                normal,
+                                |_| Vec3::ONE,
+                                |_| Vec3::ZERO,
+                                &mut rng,
+                            ));
+                        (direction, bsdf_pdf) =
+                            sampling::cos_weighted_hemisphere_pdf(normal, bounce_sample);
+                        spread = DIFFUSE_SPREAD;
+                        mask = RayMask::SHADOW;
                     }
                 }
+                Surface::Plastic { color, ior } => {
+                    origin = hit_info.position + 2.0 * SURFACE_DIST * normal;
+                    let cos_i = (-direction.dot(normal)).max(0.0);
+                    if rng.next_f32() < schlick(cos_i, ior) {
+                        direction = reflect(direction, normal);
+                        spread +=
+                            2.0 * mean_curvature(scene, hit_info.position, mask).abs() * cone_width;
+                        mask = RayMask::REFLECTION;
+                    } else {
+                        acc = tint(color) * acc;
+                        direct += acc
+                            * tint(sample_lights(
+                                scene,
+                                settings,
+                                hit_info.position,
+                                normal,
+                                |_| Vec3::ONE,
+                                |_| Vec3::ZERO,
+                                &mut rng,
+                            ));
+                        (direction, bsdf_pdf) =
+                            sampling::cos_weighted_hemisphere_pdf(normal, bounce_sample);
+                        spread = DIFFUSE_SPREAD;
+                        mask = RayMask::SHADOW;
+                    }
+                }
+                // The highlights are only lit directly, while the light
+                // bounces on from the diffuse part.
+                Surface::Phong {
+                    diffuse,
+                    specular,
+                    shininess,
+                } => {
+                    let wo = -direction;
+                    direct += acc
+                        * tint(sample_lights(
+                            scene,
+                            settings,
+                            hit_info.position,
+                            normal,
+                            |_| diffuse,
+                            |wi| blinn_phong(normal, wo, wi, shininess) * specular,
+                            &mut rng,
+                        ));
+                    acc = tint(diffuse) * acc;
+                    origin = hit_info.position + 2.0 * SURFACE_DIST * normal;
+                    (direction, bsdf_pdf) =
+                        sampling::cos_weighted_hemisphere_pdf(normal, bounce_sample);
+                    spread = DIFFUSE_SPREAD;
+                    mask = RayMask::SHADOW;
+                }
+                Surface::Dielectric {
+                    color,
+                    ior,
+                    dispersion,
+                } => {
+                    let ior = dielectric_ior(ior, dispersion, wavelength);
+
+                    // A surface where two dielectrics touch is only reported
+                    // once, as the surface of one of them. When leaving that
+                    // one, the medium on the other side is found by looking
+                    // past the surface.
+                    let entering = direction.dot(normal) < 0.0;
+                    let mut beyond = media;
+                    let mut color = color;
+                    let normal = if entering {
+                        beyond.push(ior);
+                        normal
+                    } else {
+                        beyond.remove(ior);
+                        let p = hit_info.position + 2.0 * SURFACE_DIST * normal;
+                        match medium_at(scene, p, mask, wavelength) {
+                            Some((ior, medium_color)) if ior != beyond.current() => {
+                                beyond.push(ior);
+                                color = medium_color;
+                            }
+                            _ => color = Vec3::ONE,
+                        }
+                        -normal
+                    };
+
+                    let (n1, n2) = (media.current(), beyond.current());
+                    let eta = n1 / n2;
+                    let cos_i = -direction.dot(normal);
+                    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+                    let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+
+                    let reflectance = if sin2_t > 1.0 {
+                        1.0
+                    } else {
+                        schlick(if n1 <= n2 { cos_i } else { cos_t }, n2 / n1)
+                    };
+
+                    if rng.next_f32() < reflectance {
+                        origin = hit_info.position + 2.0 * SURFACE_DIST * normal;
+                        direction = reflect(direction, normal);
+                    } else {
+                        acc = tint(color) * acc;
+                        media = beyond;
+                        origin = hit_info.position - 2.0 * SURFACE_DIST * normal;
+                        direction = (eta * direction + (eta * cos_i - cos_t) * normal).normalize();
+                    }
+                    mask = RayMask::REFLECTION;
+                }
             },
             None => {
-                acc = (scene.background_color)(direction) * acc;
+                // After a diffuse bounce, the lights were already sampled
+                // directly, so they must not be counted again here. The
+                // background may have been sampled directly as well, in
+                // which case the two estimates are combined.
+                let ray = Ray { origin, direction };
+                if mask == RayMask::SHADOW {
+                    let weight = match &scene.background_sampler {
+                        Some(sampler) => power_heuristic(bsdf_pdf, (sampler.pdf)(direction)),
+                        None => 1.0,
+                    };
+                    acc = weight * tint(background_radiance(scene, &ray)) * acc;
+                } else {
+                    acc = tint(scene.background(&ray)) * acc;
+                }
                 break;
             }
         };
@@ -65,30 +988,764 @@ fn cast_ray(scene: &Scene, mut origin: Vec3, mut direction: Vec3) -> Vec3 {
         bounces += 1;
     }
 
-    acc
+    acc + direct
 }
 
-pub fn render(width: i32, height: i32, sample_count: i32, scene: &Scene) -> Vec<Vec<Vec3>> {
-    (0..height)
+// A ray through the pixel and the lens, jittered over both from the camera
+// dimensions of the sample.
+fn camera_ray(
+    scene: &Scene,
+    settings: &RenderSettings,
+    i: i32,
+    j: i32,
+    sampler: &mut Sobol,
+) -> Ray {
+    let (width, height) = (settings.width, settings.height);
+    let (u, v) = sampler.next_2d();
+    let x = -0.5 + (j as f32 + u) / width as f32;
+    let y = 0.5 - (i as f32 + v) / height as f32;
+    let (u, v) = sampler.next_2d();
+    let lens = sampling::uniform_disk_from(u, v);
+    scene.camera.get_ray(x, y, lens)
+}
+
+// Sum of the given samples of a pixel, recording every surface hit of every
+// sample if asked to. Samples whose camera ray hits a holdout show the
+// background, and their part of the sum is also returned separately, with
+// their number in `w`, so that it can be taken out of the coverage.
+fn sample_pixel(
+    scene: &Scene,
+    settings: &RenderSettings,
+    i: i32,
+    j: i32,
+    samples: Range<usize>,
+    mut trace: Option<&mut Vec<Bounce>>,
+) -> (Vec3, Vec4) {
+    let RenderSettings {
+        width,
+        paths_per_sample,
+        ..
+    } = *settings;
+    let paths = paths_per_sample as usize;
+
+    // The pixel position, the lens position and the bounces of a sample each
+    // take their own pair of dimensions, so they are stratified independently
+    // of each other. The camera dimensions are stratified over the samples
+    // and the bounces over all the paths of the pixel.
+    let mut sampler = Sobol::new((i * width + j) as u32);
+    let mut holdout = Vec4::ZERO;
+
+    // A NaN or infinity from a degenerate case anywhere along a path would
+    // spoil the whole sum, so it is dropped.
+    let finite = |radiance: Vec3| {
+        if radiance.is_finite() {
+            radiance
+        } else {
+            Vec3::ZERO
+        }
+    };
+
+    let sum = samples
+        .map(|k| {
+            sampler.start_sample(k as u32);
+            let ray = camera_ray(scene, settings, i, j, &mut sampler);
+            let primary = scene.map.march(
+                ray.origin,
+                ray.direction,
+                RayMask::CAMERA,
+                settings.step_scale,
+            );
+
+            if let March::Hit(HitInfo {
+                position,
+                material: Material::Holdout,
+                ..
+            }) = primary
+            {
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.push(Bounce {
+                        depth: 0,
+                        position,
+                        material: Material::Holdout,
+                        throughput: Vec3::ONE,
+                    });
+                }
+                let background = finite(scene.background(&ray));
+                holdout += background.extend(1.0);
+                return background;
+            }
+
+            let radiance = (0..paths)
+                .map(|path| {
+                    let index = k * paths + path;
+                    sampler.continue_sample(index as u32, CAMERA_DIMENSIONS);
+
+                    // The wavelength takes the first dimensions of the path,
+                    // so it is stratified over the paths of the pixel in an
+                    // order scrambled per pixel. Renders stopped after a few
+                    // samples then still see the whole spectrum.
+                    let wavelength = if cfg!(feature = "spectral") {
+                        Some(spectral::sample_wavelength(sampler.next_f32()))
+                    } else {
+                        None
+                    };
+
+                    let radiance = cast_ray(
+                        scene,
+                        settings,
+                        &ray,
+                        &primary,
+                        wavelength,
+                        &mut sampler,
+                        trace.as_deref_mut(),
+                    );
+
+                    finite(match wavelength {
+                        Some(wavelength) => spectral::spectrum_to_rgb(radiance.x, wavelength),
+                        None => radiance,
+                    })
+                })
+                .fold(Vec3::ZERO, |u, v| u + v);
+
+            radiance / paths as f32
+        })
+        .fold(Vec3::ZERO, |u, v| u + v);
+
+    (sum, holdout)
+}
+
+fn render_pixel(
+    scene: &Scene,
+    settings: &RenderSettings,
+    i: i32,
+    j: i32,
+    trace: Option<&mut Vec<Bounce>>,
+) -> Vec3 {
+    let sample_count = settings.sample_count as usize;
+    sample_pixel(scene, settings, i, j, 0..sample_count, trace).0 / sample_count as f32
+}
+
+/// Renders the image, averaging the samples of each pixel. Paths that come
+/// out NaN or infinite count as black, so that a degenerate case only darkens
+/// its pixel instead of turning it into NaN, while settings or scenes that
+/// can't be rendered at all are errors:
+///
+/// ```
+/// use glam::{vec3, Vec3};
+/// use raymarching_pathtracer::{camera::Camera, error::RenderError, renderer::*, sdf::*};
+///
+/// let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 0.5, 1.0, 0.0);
+/// let nan = Material::Emissive { color: Vec3::splat(f32::NAN), intensity: 1.0 };
+/// let mut scene = Scene::new(camera, sphere(0.5).material(nan));
+/// scene.background_color = Box::new(|_| Vec3::ONE);
+/// let settings = RenderSettings { width: 9, height: 9, sample_count: 16, ..Default::default() };
+/// let image = render(&settings, &scene).unwrap();
+///
+/// assert!(image.iter().flatten().all(|pixel| pixel.is_finite()));
+/// assert_eq!(image[4][4], Vec3::ZERO);
+/// let row = |i: usize| image[i].iter().sum::<Vec3>().length();
+/// assert!(row(4) > 0.0 && row(4) < row(0));
+///
+/// let empty = RenderSettings { width: 0, ..settings };
+/// assert!(matches!(render(&empty, &scene), Err(RenderError::InvalidSettings(_))));
+/// ```
+pub fn render(settings: &RenderSettings, scene: &Scene) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    settings.validate()?;
+    scene.validate()?;
+    let supersampled = settings.supersampled();
+    let pixels = (0..supersampled.height)
+        .into_par_iter()
+        .map(|i| {
+            (0..supersampled.width)
+                .map(|j| render_pixel(scene, &supersampled, i, j, None))
+                .collect()
+        })
+        .collect();
+    Ok(downsample(pixels, settings.ssaa.max(1) as usize))
+}
+
+// Renders the image as a layer whose alpha is the fraction of the camera
+// rays that don't hit a holdout, with the color premultiplied by it. Laid
+// over a plate, the plate shows through where the holdouts are, while the
+// objects in front of them and the shadows and reflections they block stay
+// in the render.
+pub fn render_layer(settings: &RenderSettings, scene: &Scene) -> Result<Layer, RenderError> {
+    settings.validate()?;
+    scene.validate()?;
+    let supersampled = settings.supersampled();
+    let scale = 1.0 / settings.sample_count as f32;
+    let pixels = (0..supersampled.height)
+        .into_par_iter()
+        .map(|i| {
+            (0..supersampled.width)
+                .map(|j| {
+                    let samples = 0..settings.sample_count as usize;
+                    let (sum, holdout) = sample_pixel(scene, &supersampled, i, j, samples, None);
+                    scale * (sum.extend(settings.sample_count as f32) - holdout)
+                })
+                .collect()
+        })
+        .collect();
+    Ok(downsample(pixels, settings.ssaa.max(1) as usize))
+}
+
+// The images of the left and the right eye.
+pub type StereoPair = (Vec<Vec<Vec3>>, Vec<Vec<Vec3>>);
+
+/// Renders the views of the left and the right eye, moved apart sideways by
+/// `eye_separation`. They look in the same direction, so nearer objects are
+/// shifted further apart between the views:
+///
+/// ```
+/// use glam::{vec3, Vec3};
+/// use raymarching_pathtracer::{camera::Camera, renderer::*, sdf::*};
+///
+/// let ball = Material::Emissive { color: Vec3::ONE, intensity: 1.0 };
+/// let near = sphere(0.3).position(vec3(-0.5, -2.0, 0.0)).material(ball.clone());
+/// let far = sphere(1.0).position(vec3(2.0, 5.0, 0.0)).material(ball);
+/// let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 1.0, 2.0, 0.0);
+/// let mut scene = Scene::new(camera, near.merge(far));
+/// let settings = RenderSettings { width: 64, height: 32, sample_count: 4, ..Default::default() };
+/// let (left, right) = render_stereo(&settings, &mut scene, 0.2).unwrap();
+///
+/// // The mean column of the light in the given columns. The near ball is in
+/// // the left half of the image.
+/// let column = |image: &[Vec<Vec3>], columns: std::ops::Range<usize>| {
+///     let pixels = image
+///         .iter()
+///         .flat_map(|row| columns.clone().map(move |j| (j, row[j].length())));
+///     let (sum, weight) = pixels.fold((0.0, 0.0), |(s, w), (j, l)| (s + j as f32 * l, w + l));
+///     sum / weight
+/// };
+/// let near = column(&left, 0..32) - column(&right, 0..32);
+/// let far = column(&left, 32..64) - column(&right, 32..64);
+/// assert!(far > 0.0 && near > 2.0 * far);
+/// ```
+pub fn render_stereo(
+    settings: &RenderSettings,
+    scene: &mut Scene,
+    eye_separation: f32,
+) -> Result<StereoPair, RenderError> {
+    let camera = scene.camera;
+    scene.camera.translate(-0.5 * eye_separation, 0.0, 0.0);
+    let left = render(settings, scene);
+    scene.camera = camera;
+    scene.camera.translate(0.5 * eye_separation, 0.0, 0.0);
+    let right = render(settings, scene);
+    scene.camera = camera;
+    Ok((left?, right?))
+}
+
+// Averages each block of `factor` by `factor` pixels into one.
+fn downsample<T>(pixels: Vec<Vec<T>>, factor: usize) -> Vec<Vec<T>>
+where
+    T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>,
+{
+    if factor == 1 {
+        return pixels;
+    }
+    let scale = 1.0 / (factor * factor) as f32;
+    pixels
+        .chunks(factor)
+        .map(|rows| {
+            (0..rows[0].len() / factor)
+                .map(|j| {
+                    let block = rows
+                        .iter()
+                        .flat_map(|row| &row[factor * j..factor * (j + 1)]);
+                    block.fold(T::default(), |u, v| u + *v) * scale
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Renders the image a few samples per pixel at a time, keeping the running
+/// sum of every pixel, so that the image can be shown while it converges.
+/// The sums start over whenever the camera has changed:
+///
+/// ```
+/// use glam::{vec3, Vec3};
+/// use raymarching_pathtracer::{camera::Camera, renderer::*, sdf::*};
+///
+/// let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 0.5, 1.0, 0.0);
+/// let light = Material::Emissive { color: Vec3::ONE, intensity: 1.0 };
+/// let mut scene = Scene::new(camera, sphere(0.5).material(light));
+/// let settings = RenderSettings { width: 21, height: 21, sample_count: 8, ..Default::default() };
+/// let mut accumulator = Accumulator::new(&settings);
+///
+/// accumulator.add_samples(&settings, &scene, 4);
+/// assert!(accumulator.mean()[10][10].length() > 0.0);
+/// scene.camera.translate(2.0, 0.0, 0.0);
+/// accumulator.add_samples(&settings, &scene, 4);
+/// assert_eq!(accumulator.samples(), 4);
+/// assert_eq!(accumulator.mean()[10][10], Vec3::ZERO);
+/// ```
+pub struct Accumulator {
+    sums: Vec<Vec<Vec3>>,
+    samples: usize,
+    ssaa: usize,
+    generation: u64,
+}
+
+impl Accumulator {
+    pub fn new(settings: &RenderSettings) -> Self {
+        let RenderSettings { width, height, .. } = settings.supersampled();
+        Self {
+            sums: vec![vec![Vec3::ZERO; width as usize]; height as usize],
+            samples: 0,
+            ssaa: settings.ssaa.max(1) as usize,
+            generation: 0,
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    // A moved camera needs the samples taken again, however many there are.
+    pub fn is_done(&self, settings: &RenderSettings, scene: &Scene) -> bool {
+        self.generation == scene.camera.generation()
+            && self.samples >= settings.sample_count as usize
+    }
+
+    // Adds up to `count` more samples to every pixel, starting over first if
+    // the camera has changed.
+    pub fn add_samples(&mut self, settings: &RenderSettings, scene: &Scene, count: usize) {
+        if scene.camera.generation() != self.generation {
+            self.reset();
+            self.generation = scene.camera.generation();
+        }
+        let end = (self.samples + count).min(settings.sample_count as usize);
+        let samples = self.samples..end;
+        let settings = &settings.supersampled();
+        self.sums.par_iter_mut().enumerate().for_each(|(i, row)| {
+            for (j, sum) in row.iter_mut().enumerate() {
+                *sum += sample_pixel(scene, settings, i as i32, j as i32, samples.clone(), None).0;
+            }
+        });
+        self.samples = end;
+    }
+
+    // Starts over, for when the scene has changed. Camera changes are picked
+    // up by themselves.
+    pub fn reset(&mut self) {
+        self.sums
+            .iter_mut()
+            .flatten()
+            .for_each(|sum| *sum = Vec3::ZERO);
+        self.samples = 0;
+    }
+
+    pub fn mean(&self) -> Vec<Vec<Vec3>> {
+        let scale = 1.0 / self.samples.max(1) as f32;
+        let pixels = self
+            .sums
+            .iter()
+            .map(|row| row.iter().map(|&sum| scale * sum).collect())
+            .collect();
+        downsample(pixels, self.ssaa)
+    }
+}
+
+// Renders one sample per pixel at a time for as long as the time budget
+// allows, up to `settings.sample_count` samples, and returns the image along
+// with the number of passes. The clock is only checked between passes, and
+// no pass is started that is expected to overrun the budget, judging by the
+// previous one. At least one pass is always rendered.
+pub fn render_timed(
+    settings: &RenderSettings,
+    scene: &Scene,
+    budget: Duration,
+) -> Result<(Vec<Vec<Vec3>>, usize), RenderError> {
+    settings.validate()?;
+    scene.validate()?;
+    let start = Instant::now();
+    let mut accumulator = Accumulator::new(settings);
+    let mut last_pass = Duration::ZERO;
+
+    while !accumulator.is_done(settings, scene) {
+        if accumulator.samples() > 0 && start.elapsed() + last_pass > budget {
+            break;
+        }
+        let pass_start = Instant::now();
+        accumulator.add_samples(settings, scene, 1);
+        last_pass = pass_start.elapsed();
+    }
+
+    Ok((accumulator.mean(), accumulator.samples()))
+}
+
+// The direct light reaching a point of the catcher, from one light and one
+// direction of the environment, both unshadowed and as shadowed by the scene.
+fn catcher_irradiance(
+    scene: &Scene,
+    settings: &RenderSettings,
+    origin: Vec3,
+    normal: Vec3,
+    sampler: &mut Sobol,
+) -> (Vec3, Vec3) {
+    let occluded = |direction: Vec3, distance: f32| match scene.map.ray_intersection(
+        origin,
+        direction,
+        RayMask::SHADOW,
+        settings.step_scale,
+    ) {
+        Some(hit_info) => (hit_info.position - origin).length() < distance,
+        None => false,
+    };
+
+    // Cosine-weighted directions cancel the cosine and pi of the irradiance.
+    let (direction, _) = sampling::cos_weighted_hemisphere_pdf(normal, sampler.next_2d());
+    let sky = background_radiance(scene, &Ray { origin, direction });
+    let mut unshadowed = sky;
+    let mut shadowed = if occluded(direction, f32::INFINITY) {
+        Vec3::ZERO
+    } else {
+        sky
+    };
+
+    if let Some((light, probability)) = choose_light(&scene.lights, sampler.next_f32()) {
+        let sample = light.sample(origin, sampler);
+        let cos = normal.dot(sample.direction);
+        if cos > 0.0 {
+            let light = cos / PI * sample.weight / probability;
+            unshadowed += light;
+            if !occluded(sample.direction, sample.distance) {
+                shadowed += light;
+            }
+        }
+    }
+
+    (unshadowed, shadowed)
+}
+
+// A shadow catcher pass, for compositing the scene onto a photo of a real
+// surface standing in for the catcher, usually a ground plane. The catcher
+// isn't part of the scene: camera rays see through it to the background,
+// but where it is the closest surface, the pass records the fraction of the
+// direct light from the lights and the environment that the scene blocks.
+// The result is a black layer with that fraction as its alpha, so that
+// laying it over the photo darkens the surface where it is in shadow.
+// Pixels covered by the scene stay transparent, as the scene is rendered
+// into its own layer. With supersampling, the density is averaged over the
+// sub-pixels like the colors of `render`.
+pub fn render_shadow_catcher(
+    settings: &RenderSettings,
+    scene: &Scene,
+    catcher: &dyn SdfMap,
+) -> Result<Layer, RenderError> {
+    settings.validate()?;
+    scene.validate()?;
+    let factor = settings.ssaa.max(1) as usize;
+    let settings = &settings.supersampled();
+    let RenderSettings {
+        width,
+        height,
+        sample_count,
+        ..
+    } = *settings;
+
+    let pixels = (0..height)
         .into_par_iter()
         .map(|i| {
-            let mut rng = rand::thread_rng();
             (0..width)
                 .map(|j| {
-                    (0..sample_count)
-                        .map(|_| {
-                            let x =
-                                -0.5 + (j as f32 + rng.gen::<f32>() - 0.5) / (width as f32 - 1.0);
-                            let y =
-                                0.5 - (i as f32 + rng.gen::<f32>() - 0.5) / (height as f32 - 1.0);
-                            let ray = scene.camera.get_ray(x, y);
-                            cast_ray(scene, ray.origin, ray.direction)
+                    let mut sampler = Sobol::new((i * width + j) as u32);
+                    let (unshadowed, shadowed) = (0..sample_count as u32)
+                        .map(|k| {
+                            sampler.start_sample(k);
+                            let ray = camera_ray(scene, settings, i, j, &mut sampler);
+
+                            let step_scale = settings.step_scale;
+                            let catcher_hit = catcher.ray_intersection(
+                                ray.origin,
+                                ray.direction,
+                                RayMask::ALL,
+                                step_scale,
+                            );
+                            let scene_hit = scene.map.ray_intersection(
+                                ray.origin,
+                                ray.direction,
+                                RayMask::CAMERA,
+                                step_scale,
+                            );
+                            let dist = |hit: &HitInfo| (hit.position - ray.origin).length();
+
+                            match (catcher_hit, scene_hit) {
+                                (Some(hit), Some(other)) if dist(&other) <= dist(&hit) => {
+                                    (Vec3::ZERO, Vec3::ZERO)
+                                }
+                                (Some(hit), _) => {
+                                    let normal = catcher.normal_cached(
+                                        hit.position,
+                                        hit.distance,
+                                        RayMask::ALL,
+                                    );
+                                    let origin = hit.position + 2.0 * SURFACE_DIST * normal;
+                                    catcher_irradiance(
+                                        scene,
+                                        settings,
+                                        origin,
+                                        normal,
+                                        &mut sampler,
+                                    )
+                                }
+                                (None, _) => (Vec3::ZERO, Vec3::ZERO),
+                            }
                         })
-                        .reduce(|u, v| u + v)
-                        .unwrap()
-                        / sample_count as f32
+                        .fold((Vec3::ZERO, Vec3::ZERO), |(a, b), (c, d)| (a + c, b + d));
+
+                    let (unshadowed, shadowed) =
+                        (unshadowed.dot(Vec3::ONE), shadowed.dot(Vec3::ONE));
+                    let density = if unshadowed > 0.0 {
+                        (1.0 - shadowed / unshadowed).clamp(0.0, 1.0)
+                    } else {
+                        0.0
+                    };
+                    Vec4::new(0.0, 0.0, 0.0, density)
                 })
                 .collect()
         })
-        .collect()
+        .collect();
+    Ok(downsample(pixels, factor))
+}
+
+// Traces a single pixel, recording every surface hit of every sample. The
+// pixel must be inside the image.
+pub fn debug_pixel(
+    scene: &Scene,
+    settings: &RenderSettings,
+    x: i32,
+    y: i32,
+) -> Result<(Vec3, Vec<Bounce>), RenderError> {
+    settings.validate()?;
+    scene.validate()?;
+    if !(0..settings.width).contains(&x) || !(0..settings.height).contains(&y) {
+        return Err(RenderError::InvalidSettings(format!(
+            "Pixel ({}, {}) is outside the {}x{} image.",
+            x, y, settings.width, settings.height
+        )));
+    }
+    let mut trace = Vec::new();
+    let color = render_pixel(scene, settings, y, x, Some(&mut trace));
+    Ok((color, trace))
+}
+
+fn shade_preview(
+    scene: &Scene,
+    origin: Vec3,
+    direction: Vec3,
+    hit: Option<HitInfo>,
+    spread: f32,
+    ambient: Vec3,
+) -> Vec3 {
+    let hit_info = match hit {
+        Some(hit_info) => hit_info,
+        None => return scene.background(&Ray { origin, direction }),
+    };
+
+    let footprint = spread * (hit_info.position - origin).length();
+    let normal = scene
+        .map
+        .normal_cached(hit_info.position, hit_info.distance, RayMask::CAMERA);
+
+    let albedo = match hit_info
+        .material
+        .resolve(hit_info.position, normal, footprint)
+    {
+        Surface::Lambertian { color }
+        | Surface::OrenNayar { color, .. }
+        | Surface::Specular { color, .. }
+        | Surface::Plastic { color, .. }
+        | Surface::Dielectric { color, .. }
+        | Surface::Phong { diffuse: color, .. } => color,
+        Surface::Emissive { color, intensity } => return intensity * color,
+        Surface::Spotlight {
+            color,
+            intensity,
+            direction: spot_direction,
+            cos_radius,
+        } => return spotlight_falloff(direction, spot_direction, cos_radius) * intensity * color,
+        Surface::Holdout => return scene.background(&Ray { origin, direction }),
+    };
+
+    let position = hit_info.position + 2.0 * SURFACE_DIST * normal;
+
+    let (light_direction, light_dist) = match scene.lights.first() {
+        Some(Light::Directional { direction, .. }) => (*direction, MAX_PREVIEW_DIST),
+        Some(Light::Point {
+            position: light_position,
+            ..
+        }) => {
+            // A light right at the surface doesn't light it.
+            let offset = *light_position - position;
+            (offset.normalize_or_zero(), offset.length())
+        }
+        None => (Vec3::Z, MAX_PREVIEW_DIST),
+    };
+
+    let diffuse = normal.dot(light_direction).max(0.0);
+    let shadow = scene.map.soft_shadow(
+        position,
+        light_direction,
+        PREVIEW_SHADOW_HARDNESS,
+        light_dist,
+    );
+    let ao = scene.map.ambient_occlusion(position, normal);
+
+    albedo * ((1.0 - PREVIEW_AMBIENT) * diffuse * shadow + PREVIEW_AMBIENT * ao + ambient)
+}
+
+// A quick, noise-free approximation for composing shots: one ray through the
+// center of each pixel and the pinhole, shaded by a single key light with soft
+// shadows and ambient occlusion. The constant ambient radiance lights every
+// surface evenly, so that crevices hidden from the key light and occluded
+// from the sky don't turn black.
+//
+// The primary rays of neighboring pixels are marched in packets of four.
+pub fn render_preview(
+    width: i32,
+    height: i32,
+    ambient: Vec3,
+    scene: &Scene,
+) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    validate_size(width, height)?;
+    if !(ambient.min_element() >= 0.0 && ambient.is_finite()) {
+        return Err(RenderError::InvalidSettings(
+            "The ambient radiance must be finite and non-negative.".to_string(),
+        ));
+    }
+    scene.validate()?;
+    let spread = scene.camera.pixel_spread(width);
+
+    Ok((0..height)
+        .into_par_iter()
+        .map(|i| {
+            let rays: Vec<Ray> = (0..width)
+                .map(|j| {
+                    let x = -0.5 + (j as f32 + 0.5) / width as f32;
+                    let y = 0.5 - (i as f32 + 0.5) / height as f32;
+                    scene.camera.get_ray(x, y, (0.0, 0.0))
+                })
+                .collect();
+
+            rays.chunks(4)
+                .flat_map(|packet| {
+                    // A partial packet at the end of the row is padded with
+                    // copies of its last ray.
+                    let ray = |k: usize| &packet[k.min(packet.len() - 1)];
+                    let hits = scene.map.ray_intersection4(
+                        [0, 1, 2, 3].map(|k| ray(k).origin),
+                        [0, 1, 2, 3].map(|k| ray(k).direction),
+                        RayMask::CAMERA,
+                    );
+
+                    packet
+                        .iter()
+                        .zip(hits)
+                        .map(|(ray, hit)| {
+                            shade_preview(scene, ray.origin, ray.direction, hit, spread, ambient)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        })
+        .collect())
+}
+
+// Marches a camera ray through each pixel, as the preview does, and adds up
+// the steps they take. Steps are told apart by the maps named with
+// `SdfMap::profile` that they were nearest to.
+pub fn profile_camera_rays(settings: &RenderSettings, scene: &Scene) -> Report {
+    let (width, height) = (settings.width, settings.height);
+
+    (0..height)
+        .into_par_iter()
+        .map(|i| {
+            let mut report = Report::default();
+            for j in 0..width {
+                let x = -0.5 + (j as f32 + 0.5) / width as f32;
+                let y = 0.5 - (i as f32 + 0.5) / height as f32;
+                let ray = scene.camera.get_ray(x, y, (0.0, 0.0));
+                let (_, profile) = scene.map.ray_intersection_profiled(
+                    ray.origin,
+                    ray.direction,
+                    RayMask::CAMERA,
+                    settings.step_scale,
+                );
+                report.add(&profile);
+            }
+            report
+        })
+        .reduce(Report::default, Report::merge)
+}
+
+// An axis-aligned plane through the scene, at the given coordinate along the
+// remaining axis.
+#[derive(Clone, Copy, Debug)]
+pub enum SlicePlane {
+    Xy { z: f32 },
+    Xz { y: f32 },
+    Yz { x: f32 },
+}
+
+impl SlicePlane {
+    fn point(&self, u: f32, v: f32) -> Vec3 {
+        match *self {
+            SlicePlane::Xy { z } => vec3(u, v, z),
+            SlicePlane::Xz { y } => vec3(u, y, v),
+            SlicePlane::Yz { x } => vec3(x, u, v),
+        }
+    }
+}
+
+// Blue inside and red outside, darkening towards the surface, with isolines
+// every SLICE_ISOLINE_SPACING and the surface itself drawn in white.
+fn distance_color(d: f32, pixel_size: f32) -> Vec3 {
+    let color = if d > 0.0 {
+        vec3(0.9, 0.35, 0.2)
+    } else {
+        vec3(0.25, 0.5, 0.9)
+    };
+    let color = color * (1.0 - (-4.0 * d.abs()).exp());
+    let color = color * (0.8 + 0.2 * (2.0 * PI * d / SLICE_ISOLINE_SPACING).cos());
+    let surface = (1.0 - d.abs() / (1.5 * pixel_size)).max(0.0);
+    color.lerp(Vec3::ONE, surface)
+}
+
+// The signed distance over a rectangle of the plane, given by its corners in
+// the two in-plane coordinates, with the first one running left to right and
+// the second one bottom to top. Each pixel shows the distance at its center.
+// Doesn't involve the camera at all, and shows objects hidden from any kind
+// of ray as well.
+pub fn render_slice(
+    map: &dyn SdfMap,
+    plane: SlicePlane,
+    bounds: ((f32, f32), (f32, f32)),
+    width: i32,
+    height: i32,
+) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    validate_size(width, height)?;
+    let ((u_min, v_min), (u_max, v_max)) = bounds;
+    let finite = [u_min, v_min, u_max, v_max].iter().all(|c| c.is_finite());
+    if !finite || u_min >= u_max || v_min >= v_max {
+        return Err(RenderError::InvalidSettings(
+            "The slice bounds must be finite, with the minimum below the maximum.".to_string(),
+        ));
+    }
+    let pixel_size = (u_max - u_min) / width as f32;
+
+    Ok((0..height)
+        .into_par_iter()
+        .map(|i| {
+            let v = v_max - (v_max - v_min) * (i as f32 + 0.5) / height as f32;
+            (0..width)
+                .map(|j| {
+                    let u = u_min + (u_max - u_min) * (j as f32 + 0.5) / width as f32;
+                    distance_color(map.dist(plane.point(u, v), RayMask::NONE), pixel_size)
+                })
+                .collect()
+        })
+        .collect())
 }
@@ -1,14 +1,80 @@
 use glam::{vec3, Vec3};
+use rand::rngs::ThreadRng;
 use rand::Rng;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
 
-pub fn uniform_disk() -> (f32, f32) {
-    let mut rng = rand::thread_rng();
+/// A source of uniform random numbers in [0, 1) for the sampling functions,
+/// so that the same math can draw from a random number generator, a
+/// low-discrepancy sequence or a recording. A recorded sequence replays the
+/// same samples:
+///
+/// ```
+/// use raymarching_pathtracer::sampling::{self, Sampler, ThreadRngSampler};
+/// use std::collections::VecDeque;
+///
+/// struct Recorder<S>(S, Vec<f32>);
+///
+/// impl<S: Sampler> Sampler for Recorder<S> {
+///     fn next_f32(&mut self) -> f32 {
+///         let x = self.0.next_f32();
+///         self.1.push(x);
+///         x
+///     }
+/// }
+///
+/// struct Replay(VecDeque<f32>);
+///
+/// impl Sampler for Replay {
+///     fn next_f32(&mut self) -> f32 {
+///         self.0.pop_front().unwrap()
+///     }
+/// }
+///
+/// let draw = |sampler: &mut dyn Sampler| {
+///     (0..100)
+///         .map(|_| (sampling::uniform_ball(sampler), sampling::uniform_sphere(sampler)))
+///         .collect::<Vec<_>>()
+/// };
+///
+/// let mut recorder = Recorder(ThreadRngSampler::default(), vec![]);
+/// let recorded = draw(&mut recorder);
+/// let replayed = draw(&mut Replay(recorder.1.into()));
+/// assert_eq!(recorded, replayed);
+/// ```
+pub trait Sampler {
+    fn next_f32(&mut self) -> f32;
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let x = self.next_f32();
+        (x, self.next_f32())
+    }
+}
+
+// Independent uniform numbers from a random number generator, such as a
+// seeded one for reproducible results.
+pub struct RngSampler<R>(pub R);
+
+impl<R: Rng> Sampler for RngSampler<R> {
+    fn next_f32(&mut self) -> f32 {
+        self.0.gen()
+    }
+}
+
+pub type ThreadRngSampler = RngSampler<ThreadRng>;
+
+impl Default for ThreadRngSampler {
+    fn default() -> Self {
+        RngSampler(rand::thread_rng())
+    }
+}
+
+pub fn uniform_disk(sampler: &mut (impl Sampler + ?Sized)) -> (f32, f32) {
     let mut x: f32;
     let mut y: f32;
 
     loop {
-        x = 2.0 * rng.gen::<f32>() - 1.0;
-        y = 2.0 * rng.gen::<f32>() - 1.0;
+        x = 2.0 * sampler.next_f32() - 1.0;
+        y = 2.0 * sampler.next_f32() - 1.0;
 
         if x * x + y * y <= 1.0 {
             return (x, y);
@@ -16,16 +82,35 @@ pub fn uniform_disk() -> (f32, f32) {
     }
 }
 
-pub fn uniform_ball() -> Vec3 {
-    let mut rng = rand::thread_rng();
+// Shirley and Chiu's concentric mapping from the unit square to the unit disk.
+// Unlike uniform_disk, it takes a fixed number of random numbers, as needed by
+// low-discrepancy samplers, and uniform inputs give uniform points.
+pub fn uniform_disk_from(u: f32, v: f32) -> (f32, f32) {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+
+    (r * phi.cos(), r * phi.sin())
+}
+
+pub fn uniform_ball(sampler: &mut (impl Sampler + ?Sized)) -> Vec3 {
     let mut x: f32;
     let mut y: f32;
     let mut z: f32;
 
     loop {
-        x = 2.0 * rng.gen::<f32>() - 1.0;
-        y = 2.0 * rng.gen::<f32>() - 1.0;
-        z = 2.0 * rng.gen::<f32>() - 1.0;
+        x = 2.0 * sampler.next_f32() - 1.0;
+        y = 2.0 * sampler.next_f32() - 1.0;
+        z = 2.0 * sampler.next_f32() - 1.0;
 
         if x * x + y * y + z * z <= 1.0 {
             return vec3(x, y, z);
@@ -33,14 +118,89 @@ pub fn uniform_ball() -> Vec3 {
     }
 }
 
-pub fn cos_weighted_hemisphere(normal: Vec3) -> Vec3 {
-    let (x, y) = uniform_disk();
-    let z = (1.0 - x * x - y * y).sqrt();
+pub fn uniform_sphere(sampler: &mut (impl Sampler + ?Sized)) -> Vec3 {
+    let z = 1.0 - 2.0 * sampler.next_f32();
+    let r = (1.0 - z * z).sqrt();
+    let phi = 2.0 * PI * sampler.next_f32();
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
     let e1 = if normal.x != 0.0 {
         vec3(normal.y, -normal.x, 0.0).normalize()
     } else {
         vec3(0.0, -normal.z, normal.y).normalize()
     };
     let e2 = Vec3::cross(e1, normal);
+    (e1, e2)
+}
+
+// Lifts a point of the unit disk straight up onto the hemisphere about the
+// normal, which turns uniform points into a cosine weighted distribution.
+pub fn disk_to_hemisphere(normal: Vec3, (x, y): (f32, f32)) -> Vec3 {
+    // Points on the rim of the disk can land just outside it after rounding.
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    let (e1, e2) = tangent_frame(normal);
     x * e1 + y * e2 + z * normal
 }
+
+// Maps a point of the unit square to a direction, so that uniform points give
+// a cosine weighted distribution.
+pub fn cos_weighted_hemisphere(normal: Vec3, (u, v): (f32, f32)) -> Vec3 {
+    disk_to_hemisphere(normal, uniform_disk_from(u, v))
+}
+
+/// Like cos_weighted_hemisphere, also returning the probability density of
+/// the direction, cos(theta) / pi, to weigh it against other strategies in
+/// multiple importance sampling. Dividing by the density counts solid angle,
+/// so a grid of samples measures the cap within 60 degrees of the normal
+/// as pi steradians:
+///
+/// ```
+/// use glam::Vec3;
+/// use raymarching_pathtracer::sampling::cos_weighted_hemisphere_pdf;
+/// use std::f32::consts::PI;
+///
+/// let n = 256;
+/// let mut solid_angle = 0.0;
+/// for i in 0..n {
+///     for j in 0..n {
+///         let uv = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+///         let (direction, pdf) = cos_weighted_hemisphere_pdf(Vec3::Z, uv);
+///         assert!((pdf - direction.z / PI).abs() < 1e-6);
+///         if direction.z > 0.5 {
+///             solid_angle += 1.0 / pdf;
+///         }
+///     }
+/// }
+/// solid_angle /= (n * n) as f32;
+///
+/// assert!((solid_angle / PI - 1.0).abs() < 0.01);
+/// ```
+pub fn cos_weighted_hemisphere_pdf(normal: Vec3, (u, v): (f32, f32)) -> (Vec3, f32) {
+    let direction = cos_weighted_hemisphere(normal, (u, v));
+    (direction, direction.dot(normal).max(0.0) / PI)
+}
+
+// Directions with density proportional to cos^exponent of the angle to the
+// normal. An exponent of 1 gives the same distribution as
+// cos_weighted_hemisphere.
+pub fn cosine_power_hemisphere(
+    normal: Vec3,
+    exponent: f32,
+    sampler: &mut (impl Sampler + ?Sized),
+) -> Vec3 {
+    let cos_theta = sampler.next_f32().powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * sampler.next_f32();
+    let (e1, e2) = tangent_frame(normal);
+    sin_theta * (phi.cos() * e1 + phi.sin() * e2) + cos_theta * normal
+}
+
+pub fn uniform_cone(axis: Vec3, cos_max: f32, sampler: &mut (impl Sampler + ?Sized)) -> Vec3 {
+    let cos_theta = 1.0 - sampler.next_f32() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * sampler.next_f32();
+    let (e1, e2) = tangent_frame(axis);
+    sin_theta * (phi.cos() * e1 + phi.sin() * e2) + cos_theta * axis
+}
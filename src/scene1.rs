@@ -1,19 +1,14 @@
+use super::background;
 use super::camera;
+use super::export::ToneMap;
 use super::renderer;
 use super::sdf::*;
+use raymarching_pathtracer::union_all;
 
 use glam::{vec3, Vec3};
 use std::f32::consts::PI;
 
 pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
-    fn background_color(direction: Vec3) -> Vec3 {
-        if direction.dot(vec3(1.0, 0.0, 0.5).normalize()) > 0.95 {
-            15.0 * vec3(1.0, 0.85, 0.75)
-        } else {
-            0.5 * vec3(0.4, 0.7, 1.0)
-        }
-    }
-
     let camera = camera::Camera::new(
         vec3(0.0, -12.0, 8.0),
         vec3(0.0, -1.0, 1.5),
@@ -57,13 +52,19 @@ pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
             color: Vec3::splat(0.3),
         });
 
-    let map: Box<dyn SdfMap> = Box::new(ground.merge(frame).merge(tube));
+    let map: Box<dyn SdfMap> = Box::new(union_all![ground, frame, tube]);
 
-    let background_color = Box::new(background_color);
+    let (background_color, sun) = background::sky_sun();
 
     renderer::Scene {
         camera,
         map,
         background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
     }
 }
@@ -0,0 +1,82 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+use raymarching_pathtracer::union_all;
+
+use glam::{vec3, Vec3};
+use std::f32::consts::PI;
+
+// Three copies of one table, each a top on four legs, placed around the
+// origin. The table is shared, so it is built and compiled once.
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    let camera = camera::Camera::new(
+        vec3(0.0, -8.0, 4.0),
+        vec3(0.0, 0.0, 0.5),
+        Vec3::Z,
+        0.25 * PI,
+        aspect_ratio,
+        0.0,
+    );
+
+    let wood = Material::Lambertian {
+        color: vec3(0.55, 0.35, 0.2),
+    };
+    let steel = Material::Specular {
+        color: Vec3::splat(0.7),
+        specularity: 0.6,
+        fuzziness: 0.2,
+    };
+
+    let leg = |x: f32, y: f32| {
+        cuboid(vec3(0.05, 0.05, 0.45))
+            .position(vec3(x, y, 0.45))
+            .material(steel.clone())
+    };
+    let table = union_all![
+        cuboid(vec3(0.8, 0.5, 0.04))
+            .round(0.02)
+            .position(vec3(0.0, 0.0, 0.92))
+            .material(wood),
+        leg(0.7, 0.4),
+        leg(-0.7, 0.4),
+        leg(0.7, -0.4),
+        leg(-0.7, -0.4),
+    ]
+    .share();
+
+    let ground = plane(Vec3::Z).material(Material::Lambertian {
+        color: Vec3::splat(0.6),
+    });
+
+    let map: Box<dyn SdfMap> = Box::new(union_all![
+        ground,
+        table.position(vec3(0.0, 1.0, 0.0)),
+        table
+            .rotate(Vec3::Z, 0.4 * PI)
+            .position(vec3(-2.2, -0.5, 0.0)),
+        table
+            .rotate(Vec3::Z, -0.3 * PI)
+            .position(vec3(2.2, -0.5, 0.0)),
+    ]);
+
+    let (background_color, sun) = background::sun_light(
+        vec3(-0.5, -1.0, 1.5),
+        0.995,
+        20.0 * vec3(1.0, 0.95, 0.85),
+        |_| vec3(0.5, 0.6, 0.8),
+    );
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::Aces,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
@@ -1,4 +1,6 @@
+use super::background;
 use super::camera;
+use super::export::ToneMap;
 use super::renderer;
 use super::sdf::*;
 
@@ -6,14 +8,6 @@ use glam::{vec3, Vec3};
 use std::f32::consts::PI;
 
 pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
-    fn background_color(direction: Vec3) -> Vec3 {
-        if direction.dot(vec3(1.0, 0.0, 0.5).normalize()) > 0.95 {
-            15.0 * vec3(1.0, 0.85, 0.75)
-        } else {
-            0.5 * vec3(0.4, 0.7, 1.0)
-        }
-    }
-
     let camera = camera::Camera::new(
         vec3(0.0, -6.0, 4.0),
         vec3(0.0, -1.0, 1.5),
@@ -23,11 +17,14 @@ pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
         0.075,
     );
 
-    let ground = plane(Vec3::Z).material(Material::Lambertian {
-        color: Vec3::splat(0.5),
+    let ground = plane(Vec3::Z).material(Material::Checker {
+        color1: Vec3::splat(0.6),
+        color2: Vec3::splat(0.4),
+        scale: 0.5,
     });
 
     let mandelbulb = Mandelbulb
+        .clip((Vec3::splat(-1.2), Vec3::splat(1.2)))
         .rotate(Vec3::Z, 0.25 * PI)
         .position(Vec3::Z)
         .material(Material::Lambertian {
@@ -36,11 +33,20 @@ pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
 
     let map: Box<dyn SdfMap> = Box::new(ground.merge(mandelbulb));
 
-    let background_color = Box::new(background_color);
+    let (background_color, sun) = background::sky_sun();
 
     renderer::Scene {
         camera,
         map,
         background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: Some(renderer::RenderSettings {
+            sample_count: 400,
+            ..Default::default()
+        }),
     }
 }
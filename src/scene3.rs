@@ -0,0 +1,59 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+
+use glam::{vec3, Vec3};
+use std::f32::consts::PI;
+
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    let camera = camera::Camera::new(
+        vec3(0.0, -10.0, 4.0),
+        vec3(0.0, 0.0, 1.0),
+        Vec3::Z,
+        0.15 * PI,
+        aspect_ratio,
+        0.05,
+    );
+
+    // The ground is hidden from the camera but still shows up in the mirror
+    // and still catches light bouncing off the other objects.
+    let ground = plane(Vec3::Z)
+        .material(Material::Lambertian {
+            color: vec3(0.6, 0.3, 0.2),
+        })
+        .ray_mask(RayMask::SHADOW | RayMask::REFLECTION);
+
+    let mirror = sphere(1.0)
+        .position(vec3(-1.2, 0.0, 1.0))
+        .material(Material::Specular {
+            color: Vec3::splat(0.9),
+            specularity: 1.0,
+            fuzziness: 0.0,
+        });
+
+    let cube = cuboid(Vec3::splat(0.6))
+        .round(0.1)
+        .rotate(Vec3::Z, 0.2 * PI)
+        .position(vec3(1.4, 0.5, 0.7))
+        .material(Material::Lambertian {
+            color: vec3(0.2, 0.4, 0.7),
+        });
+
+    let map: Box<dyn SdfMap> = Box::new(ground.merge(mirror).merge(cube));
+
+    let (background_color, sun) = background::sky_sun();
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
@@ -0,0 +1,54 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+
+use glam::{vec3, Vec3};
+use std::f32::consts::PI;
+
+// Regression scene for emissive thin shells: the cut open tube from scene1
+// is made emissive and viewed at a grazing angle.
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    fn background_color(_direction: Vec3) -> Vec3 {
+        Vec3::splat(0.02)
+    }
+
+    let camera = camera::Camera::new(
+        vec3(0.0, -8.0, 1.0),
+        vec3(0.0, 0.0, 0.4),
+        Vec3::Z,
+        0.2 * PI,
+        aspect_ratio,
+        0.0,
+    );
+
+    let ground = plane(Vec3::Z).material(Material::Lambertian {
+        color: Vec3::splat(0.5),
+    });
+
+    let tube = torus(1.5, 0.37)
+        .shell(0.03)
+        .subtract(plane(vec3(-1.0, 1.0, 0.0).normalize()))
+        .position(vec3(0.0, 0.0, 0.4))
+        .material(Material::Emissive {
+            color: vec3(1.0, 0.6, 0.3),
+            intensity: 4.0,
+        });
+
+    let map: Box<dyn SdfMap> = Box::new(ground.merge(tube));
+
+    let background_color = background::directional(background_color);
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: Vec::new(),
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
@@ -0,0 +1,57 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+use super::texture::{Image, Projection};
+
+use glam::{vec3, Vec3};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    let camera = camera::Camera::new(
+        vec3(0.0, -8.0, 5.0),
+        vec3(0.0, 0.0, 0.0),
+        Vec3::Z,
+        0.2 * PI,
+        aspect_ratio,
+        0.05,
+    );
+
+    // Square tiles with darker grout, one tile per unit.
+    let tiles = Image::from_fn(16, 16, |x, y| {
+        if x == 0 || y == 0 {
+            Vec3::splat(0.25)
+        } else {
+            vec3(0.55, 0.5, 0.45)
+        }
+    });
+
+    let ground = plane(Vec3::Z).material(Material::ImageTexture {
+        image: Arc::new(tiles),
+        projection: Projection::Triplanar { scale: 1.0 },
+    });
+
+    let spheres = sphere(0.45)
+        .repeat_hashed(vec3(1.0, 1.0, 100.0), 7)
+        .material(Material::Lambertian {
+            color: vec3(0.7, 0.3, 0.2),
+        });
+
+    let map: Box<dyn SdfMap> = Box::new(ground.merge(spheres));
+
+    let (background_color, sun) = background::sky_sun();
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
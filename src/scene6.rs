@@ -0,0 +1,61 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+
+use glam::{vec3, Vec3};
+use std::f32::consts::PI;
+
+// A glass prism in front of a striped sky. The edges of the stripes seen
+// through the prism only show rainbow fringes when rendering with the
+// `spectral` feature enabled.
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    fn background_color(direction: Vec3) -> Vec3 {
+        if (8.0 * direction.z).rem_euclid(1.0) < 0.15 {
+            Vec3::splat(3.0)
+        } else {
+            Vec3::splat(0.02)
+        }
+    }
+
+    let camera = camera::Camera::new(
+        vec3(0.0, -6.0, 0.0),
+        Vec3::ZERO,
+        Vec3::Z,
+        0.2 * PI,
+        aspect_ratio,
+        0.0,
+    );
+
+    // Everything outside the given side of the triangular cross section.
+    let outside = |angle: f32| {
+        let normal = vec3(0.0, angle.cos(), angle.sin());
+        plane(normal).position(0.5 * normal).evert()
+    };
+
+    let prism = cuboid(vec3(1.5, 2.0, 2.0))
+        .subtract(outside(-0.5 * PI))
+        .subtract(outside(PI / 6.0))
+        .subtract(outside(5.0 * PI / 6.0));
+
+    let map: Box<dyn SdfMap> = Box::new(prism.material(Material::Dielectric {
+        color: Vec3::ONE,
+        ior: 1.5,
+        dispersion: 0.01,
+    }));
+
+    let background_color = background::directional(background_color);
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: Vec::new(),
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
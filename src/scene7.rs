@@ -0,0 +1,56 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+
+use glam::{vec3, Vec3};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    let camera = camera::Camera::new(
+        vec3(0.0, -9.0, 3.0),
+        vec3(0.0, 0.0, 1.2),
+        Vec3::Z,
+        0.15 * PI,
+        aspect_ratio,
+        0.05,
+    );
+
+    // Ripples spreading out from under the text.
+    let ground = plane(Vec3::Z).material(Material::Bumped {
+        base: Arc::new(Material::Lambertian {
+            color: Vec3::splat(0.5),
+        }),
+        height: Height::new(|p| 0.03 * (8.0 * p.truncate().length()).sin()),
+    });
+
+    let title = glyph("SDF", 0.06)
+        .scale(1.2)
+        .position(vec3(0.0, 0.0, 1.4))
+        .material(Material::Lambertian {
+            color: vec3(0.8, 0.2, 0.1),
+        });
+
+    let map: Box<dyn SdfMap> = Box::new(ground.merge(title));
+
+    let (background_color, sun) = background::sun_light(
+        vec3(1.0, -1.0, 1.0),
+        0.95,
+        15.0 * vec3(1.0, 0.85, 0.75),
+        |_| 0.5 * vec3(0.4, 0.7, 1.0),
+    );
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
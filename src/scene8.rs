@@ -0,0 +1,59 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+
+use glam::{const_vec3, vec3, Vec3};
+use std::f32::consts::PI;
+
+const RED: Material = Material::Lambertian {
+    color: const_vec3!([0.7, 0.15, 0.1]),
+};
+
+const BLUE: Material = Material::Lambertian {
+    color: const_vec3!([0.1, 0.25, 0.7]),
+};
+
+// A checkerboard of spheres, colored by the parity of their cell.
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    let camera = camera::Camera::new(
+        vec3(0.0, -8.0, 5.0),
+        vec3(0.0, 0.0, 0.0),
+        Vec3::Z,
+        0.2 * PI,
+        aspect_ratio,
+        0.05,
+    );
+
+    let ground = plane(Vec3::Z).material(Material::Lambertian {
+        color: Vec3::splat(0.5),
+    });
+
+    let spheres = sphere(0.4)
+        .position(vec3(0.0, 0.0, 0.4))
+        .material(RED)
+        .repeat_cells(vec3(1.0, 1.0, 100.0), |cell, _| {
+            if (cell.x + cell.y).rem_euclid(2.0) == 0.0 {
+                RED
+            } else {
+                BLUE
+            }
+        });
+
+    let map: Box<dyn SdfMap> = Box::new(ground.merge(spheres));
+
+    let (background_color, sun) = background::sky_sun();
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::None,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
@@ -0,0 +1,56 @@
+use super::background;
+use super::camera;
+use super::export::ToneMap;
+use super::renderer;
+use super::sdf::*;
+
+use glam::{vec3, Vec3};
+use std::f32::consts::PI;
+
+// An asteroid made of a sphere roughened by fractal noise, with a smaller
+// moonlet, lit by a distant sun against a dark sky.
+pub fn create_scene(aspect_ratio: f32) -> renderer::Scene {
+    let camera = camera::Camera::new(
+        vec3(0.0, -7.0, 1.5),
+        vec3(0.0, 0.0, 0.0),
+        Vec3::Z,
+        0.2 * PI,
+        aspect_ratio,
+        0.0,
+    );
+
+    let rock = Material::Lambertian {
+        color: vec3(0.35, 0.32, 0.3),
+    };
+
+    let asteroid = sphere(1.0)
+        .scale(1.2)
+        .displace_noise(0.3, 1.2, 5, 1)
+        .material(rock.clone());
+
+    let moonlet = sphere(0.3)
+        .displace_noise(0.1, 3.0, 4, 2)
+        .position(vec3(1.5, -1.5, 0.9))
+        .material(rock);
+
+    let map: Box<dyn SdfMap> = Box::new(asteroid.merge(moonlet));
+
+    let (background_color, sun) = background::sun_light(
+        vec3(1.0, -0.5, 0.6),
+        0.995,
+        40.0 * vec3(1.0, 0.95, 0.9),
+        |_| Vec3::splat(0.02),
+    );
+
+    renderer::Scene {
+        camera,
+        map,
+        background_color,
+        lights: vec![sun],
+        background_sampler: None,
+        tone_map: ToneMap::Aces,
+        exposure: 1.0,
+        env_intensity: 1.0,
+        suggested_settings: None,
+    }
+}
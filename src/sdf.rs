@@ -1,22 +1,390 @@
-use glam::{swizzles::Vec3Swizzles, vec2, vec3, Quat, Vec3};
+use super::font;
+use super::noise;
+use super::profile::RayProfile;
+use super::texture::{Image, Projection};
+use glam::{swizzles::Vec3Swizzles, vec2, vec3, EulerRot, Quat, Vec2, Vec3, Vec4};
+use std::fmt;
+use std::ops::{Add, BitOr, Mul, Sub};
+use std::sync::Arc;
 
 pub const SURFACE_DIST: f32 = 0.001;
 const MAX_DIST: f32 = 30.0;
 const MAX_STEPS: i32 = 1000;
+const CLIP_MARGIN: f32 = 0.1;
+
+// Colors are linear RGB, the albedo of surfaces and the radiance of
+// emitters. Colors picked in sRGB should go through
+// `texture::srgb_to_linear` first, or they come out too light, and their
+// hues shift in the indirect light. Colors picked in HSV can be made with
+// `color::from_hsv`, which does this itself.
+#[derive(Clone, Debug)]
+pub enum Material {
+    Lambertian {
+        color: Vec3,
+    },
+    OrenNayar {
+        color: Vec3,
+        roughness: f32,
+    },
+    // A Lambertian 3D checkerboard with cubic cells of the given size.
+    Checker {
+        color1: Vec3,
+        color2: Vec3,
+        scale: f32,
+    },
+    Emissive {
+        color: Vec3,
+        intensity: f32,
+    },
+    // An emitter lighting only a cone of directions about `direction`, like
+    // a stage light. The emission is full along the direction and fades out
+    // towards the edge of the cone, where the cosine of the angle from the
+    // direction falls to `cos_radius`. With `cos_radius` at one or more there
+    // is no cone, and the emitter stays dark.
+    Spotlight {
+        color: Vec3,
+        intensity: f32,
+        direction: Vec3,
+        cos_radius: f32,
+    },
+    Specular {
+        color: Vec3,
+        specularity: f32,
+        fuzziness: f32,
+    },
+    // A diffuse base under a clear coat, like plastic or varnished wood. The
+    // coat reflects specularly as much as a dielectric of the given index of
+    // refraction does, more so at grazing angles, and the rest of the light
+    // reaches the base.
+    Plastic {
+        color: Vec3,
+        ior: f32,
+    },
+    // A diffuse surface with a Blinn-Phong highlight from the lights, for
+    // stylized or quick renders. Higher shininess gives a smaller, sharper
+    // highlight. The highlight doesn't reflect the rest of the scene.
+    Phong {
+        diffuse: Vec3,
+        specular: Vec3,
+        shininess: f32,
+    },
+    // The index of refraction is given at 589.3 nm. Dispersion is the B
+    // coefficient of Cauchy's equation in square micrometers, and only has
+    // an effect in spectral mode.
+    Dielectric {
+        color: Vec3,
+        ior: f32,
+        dispersion: f32,
+    },
+    // The base material with its shading normal perturbed as if the surface
+    // were displaced along the normal by the height function.
+    Bumped {
+        base: Arc<Material>,
+        height: Height,
+    },
+    // A Lambertian material with its color looked up from an image. Copies
+    // of the material share the image.
+    ImageTexture {
+        image: Arc<Image>,
+        projection: Projection,
+    },
+    // An emitter showing an image, like a screen or a stained glass window.
+    EmissiveTexture {
+        image: Arc<Image>,
+        projection: Projection,
+        intensity: f32,
+    },
+    // A stand-in for an object of a live-action plate, for cutting the
+    // render into it. It blocks light and reflections like any object, but
+    // the camera sees the background through it, with zero coverage in the
+    // alpha of `render_layer`.
+    Holdout,
+}
 
+// The plain materials that the others resolve to at a point, which is what
+// the renderer shades. See `Material` for each of them.
 #[derive(Clone, Copy, Debug)]
-pub enum Material {
+pub enum Surface {
     Lambertian {
         color: Vec3,
     },
+    OrenNayar {
+        color: Vec3,
+        roughness: f32,
+    },
     Emissive {
         color: Vec3,
+        intensity: f32,
+    },
+    Spotlight {
+        color: Vec3,
+        intensity: f32,
+        direction: Vec3,
+        cos_radius: f32,
     },
     Specular {
         color: Vec3,
         specularity: f32,
         fuzziness: f32,
     },
+    Plastic {
+        color: Vec3,
+        ior: f32,
+    },
+    Phong {
+        diffuse: Vec3,
+        specular: Vec3,
+        shininess: f32,
+    },
+    Dielectric {
+        color: Vec3,
+        ior: f32,
+        dispersion: f32,
+    },
+    Holdout,
+}
+
+// Box filtered checkerboard, after Inigo Quilez's "Filtering Procedurals"
+// article. Returns the fraction of the footprint covered by the second color.
+fn filtered_checker(p: Vec3, width: f32) -> f32 {
+    let w = width + 0.001;
+    let tri = |x: f32| (0.5 * x - (0.5 * x).floor() - 0.5).abs();
+    let i = |x: f32| 2.0 * (tri(x - 0.5 * w) - tri(x + 0.5 * w)) / w;
+    0.5 - 0.5 * i(p.x) * i(p.y) * i(p.z)
+}
+
+// A height function for bump mapping, shared by the copies of a material.
+#[derive(Clone)]
+pub struct Height(Arc<dyn Fn(Vec3) -> f32 + Send + Sync>);
+
+impl Height {
+    pub fn new(height: impl Fn(Vec3) -> f32 + Send + Sync + 'static) -> Self {
+        Height(Arc::new(height))
+    }
+
+    pub fn at(&self, p: Vec3) -> f32 {
+        (self.0)(p)
+    }
+}
+
+impl fmt::Debug for Height {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Height").finish_non_exhaustive()
+    }
+}
+
+// Tilts the normal against the gradient of the height function along the
+// surface, with the gradient computed like in SdfMap::normal.
+pub fn bumped_normal(normal: Vec3, p: Vec3, height: &Height) -> Vec3 {
+    let dx = vec3(SURFACE_DIST, 0.0, 0.0);
+    let dy = dx.yxy();
+    let dz = dx.yyx();
+
+    let x = height.at(p + dx) - height.at(p - dx);
+    let y = height.at(p + dy) - height.at(p - dy);
+    let z = height.at(p + dz) - height.at(p - dz);
+
+    let gradient = vec3(x, y, z) / (2.0 * SURFACE_DIST);
+    let surface_gradient = gradient - gradient.dot(normal) * normal;
+
+    (normal - surface_gradient).normalize()
+}
+
+impl Material {
+    // Evaluates procedural materials at a point into the plain material they
+    // behave like there, averaged over a footprint of the given width. Bumps
+    // only affect the normal, and are dropped.
+    pub fn resolve(&self, position: Vec3, normal: Vec3, footprint: f32) -> Surface {
+        match *self {
+            Material::Lambertian { color } => Surface::Lambertian { color },
+            Material::OrenNayar { color, roughness } => Surface::OrenNayar { color, roughness },
+            Material::Emissive { color, intensity } => Surface::Emissive { color, intensity },
+            Material::Spotlight {
+                color,
+                intensity,
+                direction,
+                cos_radius,
+            } => Surface::Spotlight {
+                color,
+                intensity,
+                direction,
+                cos_radius,
+            },
+            Material::Specular {
+                color,
+                specularity,
+                fuzziness,
+            } => Surface::Specular {
+                color,
+                specularity,
+                fuzziness,
+            },
+            Material::Plastic { color, ior } => Surface::Plastic { color, ior },
+            Material::Phong {
+                diffuse,
+                specular,
+                shininess,
+            } => Surface::Phong {
+                diffuse,
+                specular,
+                shininess,
+            },
+            Material::Dielectric {
+                color,
+                ior,
+                dispersion,
+            } => Surface::Dielectric {
+                color,
+                ior,
+                dispersion,
+            },
+            Material::Holdout => Surface::Holdout,
+            Material::Bumped { ref base, .. } => base.resolve(position, normal, footprint),
+            Material::ImageTexture {
+                ref image,
+                projection,
+            } => Surface::Lambertian {
+                color: projection.sample(image, position, normal),
+            },
+            Material::EmissiveTexture {
+                ref image,
+                projection,
+                intensity,
+            } => Surface::Emissive {
+                color: projection.sample(image, position, normal),
+                intensity,
+            },
+            Material::Checker {
+                color1,
+                color2,
+                scale,
+            } => {
+                // Offsetting by half a cell keeps axis-aligned surfaces
+                // through the origin, like the ground, away from the cell
+                // boundaries.
+                let t = filtered_checker(position / scale + 0.5, footprint / scale);
+                Surface::Lambertian {
+                    color: color1.lerp(color2, t),
+                }
+            }
+        }
+    }
+
+    // Mixes two materials, with `t` being the weight of `other`. Materials of
+    // the same kind have all their parameters interpolated. Materials of
+    // different kinds can't be mixed, so the one with the larger weight wins.
+    pub fn blend(&self, other: &Material, t: f32) -> Material {
+        let lerp = |a: f32, b: f32| a + t * (b - a);
+
+        match (self.clone(), other.clone()) {
+            (Material::Lambertian { color: c1 }, Material::Lambertian { color: c2 }) => {
+                Material::Lambertian {
+                    color: c1.lerp(c2, t),
+                }
+            }
+            (
+                Material::OrenNayar {
+                    color: c1,
+                    roughness: r1,
+                },
+                Material::OrenNayar {
+                    color: c2,
+                    roughness: r2,
+                },
+            ) => Material::OrenNayar {
+                color: c1.lerp(c2, t),
+                roughness: lerp(r1, r2),
+            },
+            (
+                Material::Emissive {
+                    color: c1,
+                    intensity: i1,
+                },
+                Material::Emissive {
+                    color: c2,
+                    intensity: i2,
+                },
+            ) => Material::Emissive {
+                color: c1.lerp(c2, t),
+                intensity: lerp(i1, i2),
+            },
+            (
+                Material::Specular {
+                    color: c1,
+                    specularity: s1,
+                    fuzziness: f1,
+                },
+                Material::Specular {
+                    color: c2,
+                    specularity: s2,
+                    fuzziness: f2,
+                },
+            ) => Material::Specular {
+                color: c1.lerp(c2, t),
+                specularity: lerp(s1, s2),
+                fuzziness: lerp(f1, f2),
+            },
+            (
+                Material::Dielectric {
+                    color: c1,
+                    ior: n1,
+                    dispersion: d1,
+                },
+                Material::Dielectric {
+                    color: c2,
+                    ior: n2,
+                    dispersion: d2,
+                },
+            ) => Material::Dielectric {
+                color: c1.lerp(c2, t),
+                ior: lerp(n1, n2),
+                dispersion: lerp(d1, d2),
+            },
+            (
+                Material::Checker {
+                    color1: a1,
+                    color2: b1,
+                    scale: s1,
+                },
+                Material::Checker {
+                    color1: a2,
+                    color2: b2,
+                    scale: s2,
+                },
+            ) => Material::Checker {
+                color1: a1.lerp(a2, t),
+                color2: b1.lerp(b2, t),
+                scale: lerp(s1, s2),
+            },
+            _ if t > 0.5 => other.clone(),
+            _ => self.clone(),
+        }
+    }
+}
+
+// There are no explicit shadow rays: diffuse bounces are what make an object
+// cast shadows and light its surroundings, so they use the SHADOW bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RayMask(u8);
+
+impl RayMask {
+    pub const NONE: RayMask = RayMask(0);
+    pub const CAMERA: RayMask = RayMask(1);
+    pub const SHADOW: RayMask = RayMask(2);
+    pub const REFLECTION: RayMask = RayMask(4);
+    pub const ALL: RayMask = RayMask(7);
+
+    pub fn contains(self, other: RayMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for RayMask {
+    type Output = RayMask;
+
+    fn bitor(self, other: RayMask) -> RayMask {
+        RayMask(self.0 | other.0)
+    }
 }
 
 pub struct DistInfo {
@@ -24,69 +392,387 @@ pub struct DistInfo {
     pub material: Material,
 }
 
-pub trait Mergeable: Copy {
+pub trait Mergeable: Clone {
     fn merge<Other>(&self, other: Other) -> Union<Self, Other> {
         Union {
-            sdf1: *self,
+            sdf1: self.clone(),
             sdf2: other,
         }
     }
 }
 
-impl<T: Copy> Mergeable for T {}
+impl<T: Clone> Mergeable for T {}
+
+// Four points in structure-of-arrays layout, so that a field can be
+// evaluated for a packet of rays with SIMD operations.
+#[derive(Clone, Copy, Debug)]
+pub struct Vec3x4 {
+    pub x: Vec4,
+    pub y: Vec4,
+    pub z: Vec4,
+}
+
+impl Vec3x4 {
+    pub fn splat(v: Vec3) -> Self {
+        Self {
+            x: Vec4::splat(v.x),
+            y: Vec4::splat(v.y),
+            z: Vec4::splat(v.z),
+        }
+    }
+
+    pub fn lane(&self, i: usize) -> Vec3 {
+        vec3(self.x[i], self.y[i], self.z[i])
+    }
+
+    pub fn to_array(self) -> [Vec3; 4] {
+        [self.lane(0), self.lane(1), self.lane(2), self.lane(3)]
+    }
+
+    pub fn dot(self, v: Vec3) -> Vec4 {
+        v.x * self.x + v.y * self.y + v.z * self.z
+    }
+
+    pub fn length(self) -> Vec4 {
+        let length_squared = self.x * self.x + self.y * self.y + self.z * self.z;
+        Vec4::from(length_squared.to_array().map(f32::sqrt))
+    }
+}
+
+impl From<[Vec3; 4]> for Vec3x4 {
+    fn from(p: [Vec3; 4]) -> Self {
+        Self {
+            x: Vec4::new(p[0].x, p[1].x, p[2].x, p[3].x),
+            y: Vec4::new(p[0].y, p[1].y, p[2].y, p[3].y),
+            z: Vec4::new(p[0].z, p[1].z, p[2].z, p[3].z),
+        }
+    }
+}
+
+impl Add for Vec3x4 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+impl Sub for Vec3x4 {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+// Axis-aligned boxes, as (min, max) corner pairs, containing every surface of
+// an object. Unbounded objects and those that don't know their extent have
+// no bounds.
+pub type Bounds = Option<(Vec3, Vec3)>;
+
+fn expand_bounds(bounds: Bounds, margin: f32) -> Bounds {
+    bounds.map(|(min, max)| (min - Vec3::splat(margin), max + Vec3::splat(margin)))
+}
+
+fn union_bounds(bounds1: Bounds, bounds2: Bounds) -> Bounds {
+    let ((min1, max1), (min2, max2)) = (bounds1?, bounds2?);
+    Some((min1.min(min2), max1.max(max2)))
+}
+
+fn intersect_bounds(bounds1: Bounds, bounds2: Bounds) -> Bounds {
+    match (bounds1, bounds2) {
+        (Some((min1, max1)), Some((min2, max2))) => Some((min1.max(min2), max1.min(max2))),
+        _ => bounds1.or(bounds2),
+    }
+}
+
+// Bounds of the box after mapping its corners with the transformation.
+fn transform_bounds(bounds: Bounds, f: impl Fn(Vec3) -> Vec3) -> Bounds {
+    let (min, max) = bounds?;
+    let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+        f(vec3(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        ))
+    });
+    Some((
+        corners
+            .iter()
+            .fold(Vec3::splat(f32::INFINITY), |a, b| a.min(*b)),
+        corners
+            .iter()
+            .fold(Vec3::splat(f32::NEG_INFINITY), |a, b| a.max(*b)),
+    ))
+}
+
+// Signed distance from the point to the box. A field with its surfaces
+// within the bounds is never less than this, as its inside is within the box
+// too. Without bounds, this is minus infinity.
+fn bounds_dist(bounds: Bounds, p: Vec3) -> f32 {
+    match bounds {
+        Some((min, max)) => {
+            let q = (min - p).max(p - max);
+            q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+        }
+        None => f32::NEG_INFINITY,
+    }
+}
+
+// Distances along the ray at which it enters and leaves the box, if it
+// hits it at all.
+fn box_intersection(origin: Vec3, direction: Vec3, (min, max): (Vec3, Vec3)) -> Option<(f32, f32)> {
+    let t1 = (min - origin) / direction;
+    let t2 = (max - origin) / direction;
+    let near = t1.min(t2).max_element();
+    let far = t1.max(t2).min_element();
+
+    if near <= far && far >= 0.0 {
+        Some((near, far))
+    } else {
+        None
+    }
+}
+
+// The part of a ray worth marching: up to MAX_DIST, and inside the bounds if
+// there are any. Hits are detected slightly off the surface, so the bounds
+// get a small margin.
+fn march_range(bounds: Bounds, origin: Vec3, direction: Vec3) -> Option<(f32, f32)> {
+    match expand_bounds(bounds, 2.0 * SURFACE_DIST) {
+        Some(bounds) => {
+            let (near, far) = box_intersection(origin, direction, bounds)?;
+            Some((near.max(0.0), far.min(MAX_DIST)))
+        }
+        None => Some((0.0, MAX_DIST)),
+    }
+}
+
+// Marches the ray through the map, see SdfMap::march, calling `step` with
+// the point each step is taken from.
+fn march_steps<M: SdfMap + ?Sized>(
+    map: &M,
+    origin: Vec3,
+    direction: Vec3,
+    mask: RayMask,
+    step_scale: f32,
+    mut step: impl FnMut(Vec3),
+) -> March {
+    let (mut acc, max_dist) = match march_range(map.bounds(), origin, direction) {
+        Some(range) if map.may_hit(origin, direction) => range,
+        _ => return March::Escaped,
+    };
+    let mut steps = 0;
+    let mut dist = f32::INFINITY;
+
+    loop {
+        let position = origin + acc * direction;
+        step(position);
+        let last_dist = dist;
+        // Rays refracted into an object march inside it, where the distance
+        // is negative. Everywhere else it is positive, so rays outside every
+        // object take the same steps as without `abs`.
+        dist = map.dist(position, mask).abs();
+        acc += step_scale * dist;
+        steps += 1;
+        if dist < SURFACE_DIST {
+            let position = origin + acc * direction;
+            let info = map.distinfo(position, mask);
+            return March::Hit(HitInfo {
+                position,
+                material: info.material,
+                distance: info.distance,
+            });
+        } else if acc > max_dist {
+            return if max_dist < MAX_DIST || dist >= last_dist {
+                March::Escaped
+            } else {
+                March::GaveUp
+            };
+        } else if steps > MAX_STEPS {
+            return March::GaveUp;
+        }
+    }
+}
+
+impl Mul<Vec4> for Vec3x4 {
+    type Output = Self;
+
+    fn mul(self, t: Vec4) -> Self {
+        Self {
+            x: t * self.x,
+            y: t * self.y,
+            z: t * self.z,
+        }
+    }
+}
 
-pub trait Sdf: Sync + Copy {
+pub trait Sdf: Sync + Clone {
     fn dist(&self, p: Vec3) -> f32;
 
+    fn bounds(&self) -> Bounds {
+        None
+    }
+
+    // The gradient of the field at the point, for fields that know it in
+    // closed form. Normals are taken from it instead of finite differences,
+    // which are noisy where the field varies quickly.
+    fn grad(&self, _p: Vec3) -> Option<Vec3> {
+        None
+    }
+
+    // Distances to four points at once. Fields without a vectorized version
+    // are evaluated point by point.
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        Vec4::from(p.to_array().map(|p| self.dist(p)))
+    }
+
+    // Distances at the points of a regular grid spanning the box from `min`
+    // to `max`, with `resolution` points along each axis including both
+    // faces of the box. The point (i, j, k) is at index i + nx * (j + ny * k),
+    // so x varies fastest and z slowest, which is the layout raw volume
+    // files are usually read in.
+    fn sample_grid(&self, (min, max): (Vec3, Vec3), resolution: [usize; 3]) -> Vec<f32> {
+        let [nx, ny, nz] = resolution;
+        let steps = vec3(nx as f32, ny as f32, nz as f32) - Vec3::ONE;
+        let spacing = (max - min) / steps.max(Vec3::ONE);
+
+        (0..nz)
+            .flat_map(|k| (0..ny).flat_map(move |j| (0..nx).map(move |i| (i, j, k))))
+            .map(|(i, j, k)| self.dist(min + spacing * vec3(i as f32, j as f32, k as f32)))
+            .collect()
+    }
+
     fn evert(&self) -> Eversion<Self> {
-        Eversion { sdf: *self }
+        Eversion { sdf: self.clone() }
     }
 
     fn round(&self, r: f32) -> Round<Self> {
-        Round { sdf: *self, r }
+        Round {
+            sdf: self.clone(),
+            r,
+        }
     }
 
     fn repeat(&self, period: Vec3) -> Repeat<Self> {
-        Repeat { sdf: *self, period }
+        self.repeat_offset(period, Vec3::ZERO)
+    }
+
+    // The copies are centered at `offset` plus multiples of the period.
+    fn repeat_offset(&self, period: Vec3, offset: Vec3) -> Repeat<Self> {
+        Repeat {
+            sdf: self.clone(),
+            period,
+            offset,
+        }
+    }
+
+    fn repeat_hashed(&self, period: Vec3, seed: u32) -> RepeatHashed<Self> {
+        RepeatHashed {
+            sdf: self.clone(),
+            period,
+            seed,
+        }
     }
 
     fn position(&self, offset: Vec3) -> Translation<Self> {
-        Translation { sdf: *self, offset }
+        Translation {
+            sdf: self.clone(),
+            offset,
+        }
+    }
+
+    fn scale(&self, factor: f32) -> Scale<Self> {
+        Scale {
+            sdf: self.clone(),
+            factor,
+        }
     }
 
+    // Rotation stores the inverse rotation, since it is applied to the query
+    // point rather than to the object. Hence the negated angle below.
     fn rotate(&self, axis: Vec3, angle: f32) -> Rotation<Self> {
         Rotation {
-            sdf: *self,
+            sdf: self.clone(),
             q: Quat::from_axis_angle(axis, -angle),
         }
     }
 
+    fn rotate_quat(&self, q: Quat) -> Rotation<Self> {
+        Rotation {
+            sdf: self.clone(),
+            q: q.inverse(),
+        }
+    }
+
+    // Yaw about z (up), then pitch about y, then roll about x.
+    fn rotate_euler(&self, yaw: f32, pitch: f32, roll: f32) -> Rotation<Self> {
+        self.rotate_quat(Quat::from_euler(EulerRot::ZYX, yaw, pitch, roll))
+    }
+
     fn smooth_union<Other>(&self, k: f32, other: Other) -> SmoothUnion<Self, Other> {
         SmoothUnion {
-            sdf1: *self,
+            sdf1: self.clone(),
             sdf2: other,
             k,
         }
     }
 
-    fn subtract<Other>(&self, other: Other) -> Difference<Self, Other> {
+    fn subtract<Other: Sdf>(&self, other: Other) -> Difference<Self, Other> {
         Difference {
-            sdf1: *self,
+            sdf1: self.clone(),
+            bounds2: other.bounds(),
             sdf2: other,
         }
     }
 
     fn shell(&self, thickness: f32) -> Shell<Self> {
         Shell {
-            sdf: *self,
+            sdf: self.clone(),
             thickness,
         }
     }
 
+    // Roughens the surface by moving it in or out by up to `amplitude`,
+    // following fractal noise with `frequency` cells per unit.
+    fn displace_noise(
+        &self,
+        amplitude: f32,
+        frequency: f32,
+        octaves: u32,
+        seed: u32,
+    ) -> NoiseDisplacement<Self> {
+        NoiseDisplacement {
+            sdf: self.clone(),
+            amplitude,
+            frequency,
+            octaves,
+            seed,
+            step: 1.0 / (1.0 + amplitude * frequency * noise::fbm_lipschitz(octaves)),
+        }
+    }
+
+    fn clip(&self, bounds: (Vec3, Vec3)) -> Clip<Self> {
+        let (min, max) = bounds;
+        Clip {
+            sdf: self.clone(),
+            center: 0.5 * (min + max),
+            half_size: 0.5 * (max - min),
+        }
+    }
+
     fn material(&self, material: Material) -> SdfObject<Self> {
         SdfObject {
-            sdf: *self,
+            sdf: self.clone(),
             material,
+            ray_mask: RayMask::ALL,
         }
     }
 }
@@ -120,6 +806,10 @@ impl Sdf for Mandelbulb {
 
         0.25 * m.ln() * m.sqrt() / dz
     }
+
+    fn bounds(&self) -> Bounds {
+        Some((Vec3::splat(-1.2), Vec3::splat(1.2)))
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -131,6 +821,18 @@ impl Sdf for Sphere {
     fn dist(&self, p: Vec3) -> f32 {
         p.length() - self.radius
     }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        p.length() - Vec4::splat(self.radius)
+    }
+
+    fn grad(&self, p: Vec3) -> Option<Vec3> {
+        Some(p.normalize_or_zero())
+    }
+
+    fn bounds(&self) -> Bounds {
+        Some((Vec3::splat(-self.radius), Vec3::splat(self.radius)))
+    }
 }
 
 pub fn sphere(radius: f32) -> Sphere {
@@ -147,6 +849,17 @@ impl Sdf for Torus {
     fn dist(&self, p: Vec3) -> f32 {
         vec2(p.xy().length() - self.radius1, p.z).length() - self.radius2
     }
+
+    fn grad(&self, p: Vec3) -> Option<Vec3> {
+        let radial = p.xy().normalize_or_zero();
+        let q = vec2(p.xy().length() - self.radius1, p.z).normalize_or_zero();
+        Some(vec3(q.x * radial.x, q.x * radial.y, q.y))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let r = self.radius1 + self.radius2;
+        Some((vec3(-r, -r, -self.radius2), vec3(r, r, self.radius2)))
+    }
 }
 
 pub fn torus(radius1: f32, radius2: f32) -> Torus {
@@ -163,6 +876,26 @@ impl Sdf for Cuboid {
         let p = p.abs() - self.dimensions;
         p.max(Vec3::ZERO).length() + p.x.max(p.y).max(p.z).min(0.0)
     }
+
+    // Outside, the gradient points away from the closest point of the box.
+    // Inside, it is the normal of the closest face.
+    fn grad(&self, p: Vec3) -> Option<Vec3> {
+        let q = p.abs() - self.dimensions;
+        let grad = if q.max_element() > 0.0 {
+            q.max(Vec3::ZERO).normalize()
+        } else if q.x >= q.y && q.x >= q.z {
+            Vec3::X
+        } else if q.y >= q.z {
+            Vec3::Y
+        } else {
+            Vec3::Z
+        };
+        Some(grad * p.signum())
+    }
+
+    fn bounds(&self) -> Bounds {
+        Some((-self.dimensions, self.dimensions))
+    }
 }
 
 pub fn cuboid(dimensions: Vec3) -> Cuboid {
@@ -175,205 +908,1601 @@ pub struct Plane {
 }
 
 impl Sdf for Plane {
+    // Only a true distance if the normal has unit length, which `plane`
+    // makes sure of.
     fn dist(&self, p: Vec3) -> f32 {
         self.normal.dot(p)
     }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        p.dot(self.normal)
+    }
+
+    fn grad(&self, _p: Vec3) -> Option<Vec3> {
+        Some(self.normal)
+    }
 }
 
+/// A plane through the origin, with the solid side opposite the normal. The
+/// normal doesn't need to be normalized, but it must not be zero.
+///
+/// ```
+/// use glam::vec3;
+/// use raymarching_pathtracer::sdf::*;
+///
+/// let floor = plane(vec3(0.0, 0.0, 2.0));
+/// assert_eq!(floor.dist(vec3(1.0, -4.0, 3.0)), 3.0);
+/// assert_eq!(floor.dist(vec3(0.0, 0.0, -0.5)), -0.5);
+/// ```
 pub fn plane(normal: Vec3) -> Plane {
-    Plane { normal }
+    assert!(
+        normal.length_squared() > 0.0,
+        "Plane normal must not be zero."
+    );
+    Plane {
+        normal: normal.normalize(),
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
-pub struct Eversion<S> {
-    sdf: S,
+pub struct Disk {
+    pub radius: f32,
 }
 
-impl<S: Sdf> Sdf for Eversion<S> {
+impl Sdf for Disk {
     fn dist(&self, p: Vec3) -> f32 {
-        -self.sdf.dist(p)
+        vec2((p.xy().length() - self.radius).max(0.0), p.z).length()
     }
-}
 
-#[derive(Clone, Copy, Debug)]
-pub struct Round<S> {
-    sdf: S,
-    r: f32,
+    fn bounds(&self) -> Bounds {
+        let r = self.radius;
+        Some((vec3(-r, -r, 0.0), vec3(r, r, 0.0)))
+    }
 }
 
-impl<S: Sdf> Sdf for Round<S> {
-    fn dist(&self, p: Vec3) -> f32 {
-        self.sdf.dist(p) - self.r
-    }
+pub fn disk(radius: f32) -> Disk {
+    Disk { radius }
 }
 
 #[derive(Clone, Copy, Debug)]
-pub struct Repeat<S> {
-    sdf: S,
-    period: Vec3,
+pub struct Rectangle {
+    pub half_extent: Vec2,
 }
 
-impl<S: Sdf> Sdf for Repeat<S> {
+impl Sdf for Rectangle {
     fn dist(&self, p: Vec3) -> f32 {
-        let p = p - self.period * (p / self.period + 0.5).floor();
-        self.sdf.dist(p)
+        let q = (p.xy().abs() - self.half_extent).max(Vec2::ZERO);
+        vec3(q.x, q.y, p.z).length()
+    }
+
+    fn bounds(&self) -> Bounds {
+        let e = self.half_extent.extend(0.0);
+        Some((-e, e))
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct Translation<S> {
-    sdf: S,
-    offset: Vec3,
+pub fn rectangle(half_extent: Vec2) -> Rectangle {
+    Rectangle { half_extent }
 }
 
-impl<S: Sdf> Sdf for Translation<S> {
-    fn dist(&self, p: Vec3) -> f32 {
-        self.sdf.dist(p - self.offset)
-    }
+fn segment_dist(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+    (pa - h * ba).length()
 }
 
+// A line of text in the xz-plane, centered at the origin and facing the
+// negative y-axis. The characters are one unit tall and their strokes are
+// capsules of the given radius.
 #[derive(Clone, Copy, Debug)]
-pub struct Rotation<S> {
-    sdf: S,
-    q: Quat,
+pub struct Glyph {
+    pub text: &'static str,
+    pub radius: f32,
 }
 
-impl<S: Sdf> Sdf for Rotation<S> {
+impl Sdf for Glyph {
     fn dist(&self, p: Vec3) -> f32 {
-        self.sdf.dist(self.q.mul_vec3(p))
+        let count = self.text.chars().count() as f32;
+        let start = vec2(
+            -0.5 * (count * font::ADVANCE - (font::ADVANCE - font::WIDTH)),
+            -0.5 * font::HEIGHT,
+        );
+        let q = p.xz() * font::HEIGHT - start;
+        let extent = vec2(font::WIDTH, font::HEIGHT);
+
+        // Distance to the closest stroke in font units.
+        let mut min_dist = f32::INFINITY;
+
+        for (i, c) in self.text.chars().enumerate() {
+            let local = q - vec2(i as f32 * font::ADVANCE, 0.0);
+
+            // Glyphs whose bounding box is farther than the closest stroke
+            // so far can be skipped.
+            let outside = (local - 0.5 * extent).abs() - 0.5 * extent;
+            if outside.max(Vec2::ZERO).length() >= min_dist {
+                continue;
+            }
+
+            for stroke in font::strokes(c) {
+                for pair in stroke.windows(2) {
+                    let a = vec2(pair[0].0 as f32, pair[0].1 as f32);
+                    let b = vec2(pair[1].0 as f32, pair[1].1 as f32);
+                    min_dist = min_dist.min(segment_dist(local, a, b));
+                }
+            }
+        }
+
+        vec2(min_dist / font::HEIGHT, p.y).length() - self.radius
+    }
+
+    fn bounds(&self) -> Bounds {
+        let count = self.text.chars().count() as f32;
+        let half_width = 0.5 * (count * font::ADVANCE - (font::ADVANCE - font::WIDTH));
+        let e = vec3(half_width / font::HEIGHT, 0.0, 0.5);
+        expand_bounds(Some((-e, e)), self.radius)
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct Union<S1, S2> {
-    pub sdf1: S1,
-    pub sdf2: S2,
+pub fn glyph(text: &'static str, radius: f32) -> Glyph {
+    Glyph { text, radius }
 }
 
-impl<S1: Sdf, S2: Sdf> Sdf for Union<S1, S2> {
-    fn dist(&self, p: Vec3) -> f32 {
-        self.sdf1.dist(p).min(self.sdf2.dist(p))
-    }
+fn segment_dist3(p: Vec3, a: Vec3, b: Vec3) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = if ba == Vec3::ZERO {
+        0.0
+    } else {
+        (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0)
+    };
+    (pa - h * ba).length()
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct SmoothUnion<S1, S2> {
-    sdf1: S1,
-    sdf2: S2,
+// A tube of the given radius along a polyline, made of a capsule for each
+// segment. With k = 0 the capsules are simply merged, which already joins
+// them smoothly with round elbows. A positive k blends them like
+// smooth_union, filleting the inside of sharp bends, but also swells the
+// tube at every joint by up to a quarter of k. Every segment is visited for
+// every evaluation, so the cost grows with the number of points.
+#[derive(Clone, Debug)]
+pub struct PolyCapsule {
+    points: Arc<[Vec3]>,
+    radius: f32,
     k: f32,
 }
 
-impl<S1: Sdf, S2: Sdf> Sdf for SmoothUnion<S1, S2> {
+impl Sdf for PolyCapsule {
     fn dist(&self, p: Vec3) -> f32 {
-        let d1 = self.sdf1.dist(p);
-        let d2 = self.sdf2.dist(p);
-        let h1 = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
-        let h2 = 1.0 - h1;
-        h1 * d1 + h2 * d2 - self.k * h1 * h2
+        self.points
+            .windows(2)
+            .map(|pair| segment_dist3(p, pair[0], pair[1]) - self.radius)
+            .fold(f32::INFINITY, |d1, d2| {
+                if self.k > 0.0 {
+                    smooth_min(d1, d2, self.k).0
+                } else {
+                    d1.min(d2)
+                }
+            })
     }
-}
 
-#[derive(Clone, Copy, Debug)]
-pub struct Difference<S1, S2> {
-    sdf1: S1,
-    sdf2: S2,
+    fn bounds(&self) -> Bounds {
+        let min = self
+            .points
+            .iter()
+            .fold(Vec3::splat(f32::INFINITY), |a, b| a.min(*b));
+        let max = self
+            .points
+            .iter()
+            .fold(Vec3::splat(f32::NEG_INFINITY), |a, b| a.max(*b));
+        expand_bounds(Some((min, max)), self.radius + 0.25 * self.k)
+    }
 }
 
-impl<S1: Sdf, S2: Sdf> Sdf for Difference<S1, S2> {
-    fn dist(&self, p: Vec3) -> f32 {
-        self.sdf1.dist(p).max(-self.sdf2.dist(p))
-    }
+/// A tube along the points. Copies of the tube share the points. The tube is
+/// solid all along the polyline:
+///
+/// ```
+/// use glam::vec3;
+/// use raymarching_pathtracer::sdf::*;
+///
+/// let points = [
+///     vec3(0.0, 0.0, 0.0),
+///     vec3(1.0, 0.0, 0.0),
+///     vec3(1.0, 1.0, 0.5),
+///     vec3(-0.5, 1.5, 0.0),
+/// ];
+/// let tube = poly_capsule(&points[..], 0.1, 0.05);
+///
+/// for pair in points.windows(2) {
+///     for i in 0..=10 {
+///         let p = pair[0].lerp(pair[1], i as f32 / 10.0);
+///         assert!(tube.dist(p) < 0.0);
+///     }
+/// }
+/// assert!(tube.dist(vec3(0.5, 0.5, 0.0)) > 0.0);
+/// ```
+pub fn poly_capsule(points: impl Into<Arc<[Vec3]>>, radius: f32, k: f32) -> PolyCapsule {
+    let points = points.into();
+    assert!(
+        points.len() >= 2,
+        "A poly capsule needs at least two points."
+    );
+    PolyCapsule { points, radius, k }
 }
 
 #[derive(Clone, Copy, Debug)]
-pub struct Shell<S> {
+pub struct Eversion<S> {
     sdf: S,
-    thickness: f32,
 }
 
-impl<S: Sdf> Sdf for Shell<S> {
+impl<S: Sdf> Sdf for Eversion<S> {
     fn dist(&self, p: Vec3) -> f32 {
-        self.sdf.dist(p).abs() - self.thickness
+        -self.sdf.dist(p)
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct Round<S> {
+    sdf: S,
+    r: f32,
+}
+
+impl<S: Sdf> Sdf for Round<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        self.sdf.dist(p) - self.r
+    }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        self.sdf.dist4(p) - Vec4::splat(self.r)
+    }
+
+    fn bounds(&self) -> Bounds {
+        expand_bounds(self.sdf.bounds(), self.r)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Repeat<S> {
+    sdf: S,
+    period: Vec3,
+    offset: Vec3,
+}
+
+impl<S: Sdf> Sdf for Repeat<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        let p = p - self.offset;
+        let p = p - self.period * (p / self.period + 0.5).floor();
+        self.sdf.dist(p)
+    }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        let wrap = |x: Vec4, offset: f32, period: f32| {
+            let x = x - Vec4::splat(offset);
+            x - period * (x / period + 0.5).floor()
+        };
+        self.sdf.dist4(Vec3x4 {
+            x: wrap(p.x, self.offset.x, self.period.x),
+            y: wrap(p.y, self.offset.y, self.period.y),
+            z: wrap(p.z, self.offset.z, self.period.z),
+        })
+    }
+}
+
+// Deterministic hash of a lattice cell to a value in [0, 1).
+pub fn cell_hash(cell: Vec3, seed: u32) -> f32 {
+    let mut h = seed;
+    for c in [cell.x as i32, cell.y as i32, cell.z as i32] {
+        h ^= c as u32;
+        h = h.wrapping_mul(0x27d4_eb2d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x8553_7a1b);
+        h ^= h >> 13;
+    }
+    (h >> 8) as f32 / (1 << 24) as f32
+}
+
+// Like Repeat, but every copy is uniformly scaled by a factor in [0.5, 1)
+// derived from the hash of its cell.
+#[derive(Clone, Copy, Debug)]
+pub struct RepeatHashed<S> {
+    sdf: S,
+    period: Vec3,
+    seed: u32,
+}
+
+impl<S: Sdf> Sdf for RepeatHashed<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        let cell = (p / self.period + 0.5).floor();
+        let p = p - self.period * cell;
+        let scale = 0.5 + 0.5 * cell_hash(cell, self.seed);
+        scale * self.sdf.dist(p / scale)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Translation<S> {
+    sdf: S,
+    offset: Vec3,
+}
+
+impl<S: Sdf> Sdf for Translation<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        self.sdf.dist(p - self.offset)
+    }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        self.sdf.dist4(p - Vec3x4::splat(self.offset))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let (min, max) = self.sdf.bounds()?;
+        Some((min + self.offset, max + self.offset))
+    }
+
+    fn grad(&self, p: Vec3) -> Option<Vec3> {
+        self.sdf.grad(p - self.offset)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rotation<S> {
+    sdf: S,
+    q: Quat,
+}
+
+impl<S: Sdf> Sdf for Rotation<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        self.sdf.dist(self.q.mul_vec3(p))
+    }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        self.sdf
+            .dist4(Vec3x4::from(p.to_array().map(|p| self.q.mul_vec3(p))))
+    }
+
+    fn bounds(&self) -> Bounds {
+        transform_bounds(self.sdf.bounds(), |p| self.q.inverse().mul_vec3(p))
+    }
+
+    fn grad(&self, p: Vec3) -> Option<Vec3> {
+        let grad = self.sdf.grad(self.q.mul_vec3(p))?;
+        Some(self.q.inverse().mul_vec3(grad))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Scale<S> {
+    sdf: S,
+    factor: f32,
+}
+
+impl<S: Sdf> Sdf for Scale<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        self.factor * self.sdf.dist(p / self.factor)
+    }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        self.factor * self.sdf.dist4(p * Vec4::splat(1.0 / self.factor))
+    }
+
+    fn bounds(&self) -> Bounds {
+        transform_bounds(self.sdf.bounds(), |p| self.factor * p)
+    }
+
+    fn grad(&self, p: Vec3) -> Option<Vec3> {
+        self.sdf.grad(p / self.factor)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Union<S1, S2> {
+    pub sdf1: S1,
+    pub sdf2: S2,
+}
+
+impl<S1: Sdf, S2: Sdf> Sdf for Union<S1, S2> {
+    fn dist(&self, p: Vec3) -> f32 {
+        self.sdf1.dist(p).min(self.sdf2.dist(p))
+    }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        self.sdf1.dist4(p).min(self.sdf2.dist4(p))
+    }
+
+    fn bounds(&self) -> Bounds {
+        union_bounds(self.sdf1.bounds(), self.sdf2.bounds())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SmoothUnion<S1, S2> {
+    sdf1: S1,
+    sdf2: S2,
+    k: f32,
+}
+
+// Returns the smoothed distance and the weight of the second field.
+fn smooth_min(d1: f32, d2: f32, k: f32) -> (f32, f32) {
+    // A child hidden by a ray mask is infinitely far away.
+    if !d1.is_finite() || !d2.is_finite() {
+        return if d1 <= d2 { (d1, 0.0) } else { (d2, 1.0) };
+    }
+
+    let h1 = (0.5 + 0.5 * (d2 - d1) / k).clamp(0.0, 1.0);
+    let h2 = 1.0 - h1;
+    (h1 * d1 + h2 * d2 - k * h1 * h2, h2)
+}
+
+impl<S1: Sdf, S2: Sdf> Sdf for SmoothUnion<S1, S2> {
+    fn dist(&self, p: Vec3) -> f32 {
+        smooth_min(self.sdf1.dist(p), self.sdf2.dist(p), self.k).0
+    }
+
+    // Blending moves the surface out by at most a quarter of k.
+    fn bounds(&self) -> Bounds {
+        expand_bounds(
+            union_bounds(self.sdf1.bounds(), self.sdf2.bounds()),
+            0.25 * self.k,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Difference<S1, S2> {
+    sdf1: S1,
+    sdf2: S2,
+    bounds2: Bounds,
+}
+
+impl<S1: Sdf, S2: Sdf> Sdf for Difference<S1, S2> {
+    // The cut can't reach out of its bounds, so it is only evaluated where
+    // it could cut deeper than the first field is from its surface.
+    fn dist(&self, p: Vec3) -> f32 {
+        let dist1 = self.sdf1.dist(p);
+        if dist1 >= -bounds_dist(self.bounds2, p) {
+            return dist1;
+        }
+        dist1.max(-self.sdf2.dist(p))
+    }
+
+    fn dist4(&self, p: Vec3x4) -> Vec4 {
+        self.sdf1.dist4(p).max(-self.sdf2.dist4(p))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.sdf1.bounds()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Shell<S> {
+    sdf: S,
+    thickness: f32,
+}
+
+impl<S: Sdf> Sdf for Shell<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        self.sdf.dist(p).abs() - self.thickness
+    }
+
+    fn bounds(&self) -> Bounds {
+        expand_bounds(self.sdf.bounds(), self.thickness)
+    }
+}
+
+// The displaced field is no longer a distance, but its gradient is bounded,
+// so scaling it down by that bound keeps marching from overshooting.
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseDisplacement<S> {
+    sdf: S,
+    amplitude: f32,
+    frequency: f32,
+    octaves: u32,
+    seed: u32,
+    step: f32,
+}
+
+impl<S: Sdf> Sdf for NoiseDisplacement<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        let displacement = noise::fbm(self.frequency * p, self.octaves, self.seed);
+        self.step * (self.sdf.dist(p) + self.amplitude * displacement)
+    }
+
+    fn bounds(&self) -> Bounds {
+        expand_bounds(self.sdf.bounds(), self.amplitude)
+    }
+}
+
+// The distance to the bounding box is a lower bound for the distance to
+// anything inside it, so the inner field is only evaluated close to the box.
+#[derive(Clone, Copy, Debug)]
+pub struct Clip<S> {
+    sdf: S,
+    center: Vec3,
+    half_size: Vec3,
+}
+
+impl<S: Sdf> Sdf for Clip<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        let d = cuboid(self.half_size).dist(p - self.center);
+        if d > CLIP_MARGIN {
+            d
+        } else {
+            self.sdf.dist(p)
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        intersect_bounds(
+            Some((self.center - self.half_size, self.center + self.half_size)),
+            self.sdf.bounds(),
+        )
+    }
+}
+
+#[derive(Clone)]
 pub struct HitInfo {
     pub position: Vec3,
     pub material: Material,
+    // Distance at the hit position, for normal_cached.
+    pub distance: f32,
+}
+
+// How marching a ray ended. A ray escapes when it leaves the bounds of the
+// map, or runs out of distance while moving away from every surface. It gives
+// up when it runs out of steps, or out of distance while still closing in on
+// a surface, as rays creeping along a wall towards a corner do.
+#[derive(Clone)]
+pub enum March {
+    Hit(HitInfo),
+    Escaped,
+    GaveUp,
+}
+
+pub trait SdfMap: Sync {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32;
+
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        Vec4::from(p.to_array().map(|p| self.dist(p, mask)))
+    }
+
+    fn bounds(&self) -> Bounds {
+        None
+    }
+
+    // Whether the ray passes through the bounds of the map, and so can hit
+    // it at all. Unions test each of their children, so that rays through
+    // the empty space between separate objects aren't marched.
+    fn may_hit(&self, origin: Vec3, direction: Vec3) -> bool {
+        march_range(self.bounds(), origin, direction).is_some()
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo;
+
+    // The gradient of the field where it is known in closed form, see
+    // Sdf::grad. Maps built only of such fields, merged and transformed, know
+    // it too. Others fall back to finite differences for their normals.
+    fn grad(&self, _p: Vec3, _mask: RayMask) -> Option<Vec3> {
+        None
+    }
+
+    // The distance together with the gradient. Unions take the gradient of
+    // their nearest child, which they find by its distance, so this lets
+    // them find it while evaluating each child once, and skip the children
+    // that their bounds rule out as in `dist`.
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        (self.dist(p, mask), self.grad(p, mask))
+    }
+
+    // The material of the surface closest to the point, or of the object the
+    // point is in. No ray type is excluded by its mask, as this isn't a ray.
+    fn material_at(&self, p: Vec3) -> Material {
+        self.distinfo(p, RayMask::NONE).material
+    }
+
+    fn normal(&self, p: Vec3, mask: RayMask) -> Vec3 {
+        if let Some(grad) = self.grad(p, mask) {
+            return grad.normalize();
+        }
+
+        let dx = vec3(SURFACE_DIST, 0.0, 0.0);
+        let dy = dx.yxy();
+        let dz = dx.yyx();
+
+        let x = self.dist(p + dx, mask) - self.dist(p - dx, mask);
+        let y = self.dist(p + dy, mask) - self.dist(p - dy, mask);
+        let z = self.dist(p + dz, mask) - self.dist(p - dz, mask);
+
+        vec3(x, y, z).normalize()
+    }
+
+    // Normal by forward differences from the distance at the point, when it
+    // is already known, such as at a hit. This takes three evaluations of
+    // the distance instead of six.
+    fn normal_cached(&self, p: Vec3, dist: f32, mask: RayMask) -> Vec3 {
+        if let Some(grad) = self.grad(p, mask) {
+            return grad.normalize();
+        }
+
+        let dx = vec3(SURFACE_DIST, 0.0, 0.0);
+        let dy = dx.yxy();
+        let dz = dx.yyx();
+
+        let x = self.dist(p + dx, mask) - dist;
+        let y = self.dist(p + dy, mask) - dist;
+        let z = self.dist(p + dz, mask) - dist;
+
+        vec3(x, y, z).normalize()
+    }
+
+    fn ray_intersection(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        mask: RayMask,
+        step_scale: f32,
+    ) -> Option<HitInfo> {
+        match self.march(origin, direction, mask, step_scale) {
+            March::Hit(hit_info) => Some(hit_info),
+            March::Escaped | March::GaveUp => None,
+        }
+    }
+
+    // Rays that miss the bounds of the map aren't marched at all, and the
+    // others only where they are inside the bounds. Each step is the distance
+    // times `step_scale`, which can be made less than one for fields that
+    // overestimate the distance, at the cost of taking more steps.
+    fn march(&self, origin: Vec3, direction: Vec3, mask: RayMask, step_scale: f32) -> March {
+        march_steps(self, origin, direction, mask, step_scale, |_| ())
+    }
+
+    // Like ray_intersection, along with the steps the ray took and the
+    // profiled maps they were nearest to. The ray takes the same steps as
+    // without profiling.
+    fn ray_intersection_profiled(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        mask: RayMask,
+        step_scale: f32,
+    ) -> (Option<HitInfo>, RayProfile) {
+        let mut profile = RayProfile::default();
+        let march = march_steps(self, origin, direction, mask, step_scale, |p| {
+            profile.record_step(self.profiled_at(p, mask))
+        });
+        let hit = match march {
+            March::Hit(hit_info) => Some(hit_info),
+            March::Escaped | March::GaveUp => None,
+        };
+        (hit, profile)
+    }
+
+    // The name of the profiled map whose surface is the nearest to the
+    // point, if any, see `profile`. Maps made of others ask the child that
+    // is the nearest, and a profiled map inside another names itself.
+    fn profiled_at(&self, _p: Vec3, _mask: RayMask) -> Option<&'static str> {
+        None
+    }
+
+    // Marches four rays together, for coherent rays such as primary rays.
+    // Every ray takes the same steps as with ray_intersection, so the results
+    // match it up to rounding.
+    fn ray_intersection4(
+        &self,
+        origins: [Vec3; 4],
+        directions: [Vec3; 4],
+        mask: RayMask,
+    ) -> [Option<HitInfo>; 4] {
+        let origin = Vec3x4::from(origins);
+        let direction = Vec3x4::from(directions);
+        let bounds = self.bounds();
+        let ranges = [0, 1, 2, 3].map(|i| {
+            march_range(bounds, origins[i], directions[i])
+                .filter(|_| self.may_hit(origins[i], directions[i]))
+        });
+        let mut acc = Vec4::from(ranges.map(|range| range.map_or(0.0, |(near, _)| near)));
+        let max_dist = ranges.map(|range| range.map_or(0.0, |(_, far)| far));
+        let mut active = Vec4::from(ranges.map(|range| if range.is_some() { 1.0 } else { 0.0 }));
+        let mut hits = [None, None, None, None];
+
+        for _ in 0..=MAX_STEPS {
+            let dist = self.dist4(origin + direction * acc, mask).abs();
+            acc += active * dist;
+
+            for i in 0..4 {
+                if active[i] == 0.0 {
+                    continue;
+                }
+                if dist[i] < SURFACE_DIST {
+                    let position = origins[i] + acc[i] * directions[i];
+                    let info = self.distinfo(position, mask);
+                    hits[i] = Some(HitInfo {
+                        position,
+                        material: info.material,
+                        distance: info.distance,
+                    });
+                    active[i] = 0.0;
+                } else if acc[i] > max_dist[i] {
+                    active[i] = 0.0;
+                }
+            }
+
+            if active == Vec4::ZERO {
+                break;
+            }
+        }
+
+        hits
+    }
+
+    // Fraction of a light in the given direction that is visible from the
+    // origin, estimated from how closely the ray passes by the surfaces. A
+    // larger `k` gives a harder penumbra.
+    fn soft_shadow(&self, origin: Vec3, direction: Vec3, k: f32, max_dist: f32) -> f32 {
+        let mut visibility: f32 = 1.0;
+        let mut acc = 2.0 * SURFACE_DIST;
+        let mut steps = 0;
+
+        while acc < max_dist && steps < MAX_STEPS {
+            let dist = self.dist(origin + acc * direction, RayMask::SHADOW);
+            if dist < SURFACE_DIST {
+                return 0.0;
+            }
+            visibility = visibility.min(k * dist / acc);
+            acc += dist;
+            steps += 1;
+        }
+
+        visibility
+    }
+
+    // How unoccluded a surface point is, estimated by comparing the field to
+    // the distance from the surface at a few steps along the normal.
+    fn ambient_occlusion(&self, p: Vec3, normal: Vec3) -> f32 {
+        let mut occlusion = 0.0;
+        let mut weight = 1.0;
+
+        for i in 1..=5 {
+            let h = 0.05 * i as f32;
+            occlusion += weight * (h - self.dist(p + h * normal, RayMask::SHADOW));
+            weight *= 0.6;
+        }
+
+        (1.0 - 3.0 * occlusion).clamp(0.0, 1.0)
+    }
+
+    fn intersect<Other>(&self, other: Other) -> IntersectionMap<Self, Other>
+    where
+        Self: Sized + Clone,
+    {
+        IntersectionMap {
+            sdf1: self.clone(),
+            sdf2: other,
+        }
+    }
+
+    // Cuts `other` out of this map. The faces exposed by the cut get the
+    // interior material, whatever the material of `other` is.
+    fn subtract_with_interior<Other: SdfMap>(
+        &self,
+        other: Other,
+        interior: Material,
+    ) -> DifferenceMap<Self, Other>
+    where
+        Self: Sized + Clone,
+    {
+        DifferenceMap {
+            sdf1: self.clone(),
+            bounds2: other.bounds(),
+            sdf2: other,
+            interior,
+        }
+    }
+
+    // Repeats the map like Sdf::repeat, passing the cell index of each copy
+    // and the material of the original to `cell_material` to pick the
+    // material of the copy. The copy at the origin is in cell zero.
+    fn repeat_cells(
+        &self,
+        period: Vec3,
+        cell_material: fn(Vec3, Material) -> Material,
+    ) -> RepeatMap<Self>
+    where
+        Self: Sized + Clone,
+    {
+        RepeatMap {
+            sdf: self.clone(),
+            period,
+            cell_material,
+        }
+    }
+
+    // Keeps the geometry of the map but paints it by position, with the
+    // material `selector` gives for each point of the surface.
+    fn material_by_region(&self, selector: fn(Vec3) -> Material) -> MaterialByRegion<Self>
+    where
+        Self: Sized + Clone,
+    {
+        MaterialByRegion {
+            sdf: self.clone(),
+            selector,
+        }
+    }
+
+    fn smooth_union<Other>(&self, k: f32, other: Other) -> SmoothUnion<Self, Other>
+    where
+        Self: Sized + Clone,
+    {
+        SmoothUnion {
+            sdf1: self.clone(),
+            sdf2: other,
+            k,
+        }
+    }
+
+    fn position(&self, offset: Vec3) -> Translation<Self>
+    where
+        Self: Sized + Clone,
+    {
+        Translation {
+            sdf: self.clone(),
+            offset,
+        }
+    }
+
+    fn rotate(&self, axis: Vec3, angle: f32) -> Rotation<Self>
+    where
+        Self: Sized + Clone,
+    {
+        Rotation {
+            sdf: self.clone(),
+            q: Quat::from_axis_angle(axis, -angle),
+        }
+    }
+
+    fn scale(&self, factor: f32) -> Scale<Self>
+    where
+        Self: Sized + Clone,
+    {
+        Scale {
+            sdf: self.clone(),
+            factor,
+        }
+    }
+
+    // Moves the map behind a reference count, so that it can be placed any
+    // number of times as a single small type.
+    fn share(&self) -> Shared
+    where
+        Self: Sized + Clone + Send + 'static,
+    {
+        Shared {
+            map: Arc::new(self.clone()),
+        }
+    }
+
+    // Names the map for attributing the steps of profiled rays to it, see
+    // ray_intersection_profiled.
+    fn profile(&self, name: &'static str) -> Profiled<Self>
+    where
+        Self: Sized + Clone,
+    {
+        Profiled {
+            sdf: self.clone(),
+            name,
+        }
+    }
+}
+
+impl<S1: SdfMap, S2: SdfMap> SdfMap for SmoothUnion<S1, S2> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        smooth_min(self.sdf1.dist(p, mask), self.sdf2.dist(p, mask), self.k).0
+    }
+
+    // Along the seam the materials are mixed with the same weights as the
+    // distances, see Material::blend.
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        let distinfo1 = self.sdf1.distinfo(p, mask);
+        let distinfo2 = self.sdf2.distinfo(p, mask);
+        let (distance, t) = smooth_min(distinfo1.distance, distinfo2.distance, self.k);
+
+        DistInfo {
+            distance,
+            material: distinfo1.material.blend(&distinfo2.material, t),
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        expand_bounds(
+            union_bounds(self.sdf1.bounds(), self.sdf2.bounds()),
+            0.25 * self.k,
+        )
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        if self.sdf1.dist(p, mask) <= self.sdf2.dist(p, mask) {
+            self.sdf1.profiled_at(p, mask)
+        } else {
+            self.sdf2.profiled_at(p, mask)
+        }
+    }
 }
 
-pub trait SdfMap: Sync {
-    fn dist(&self, p: Vec3) -> f32;
+impl<S: SdfMap> SdfMap for Translation<S> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.sdf.dist(p - self.offset, mask)
+    }
 
-    fn distinfo(&self, p: Vec3) -> DistInfo;
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        self.sdf.dist4(p - Vec3x4::splat(self.offset), mask)
+    }
 
-    fn normal(&self, p: Vec3) -> Vec3 {
-        let dx = vec3(SURFACE_DIST, 0.0, 0.0);
-        let dy = dx.yxy();
-        let dz = dx.yyx();
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        self.sdf.distinfo(p - self.offset, mask)
+    }
+
+    fn bounds(&self) -> Bounds {
+        let (min, max) = self.sdf.bounds()?;
+        Some((min + self.offset, max + self.offset))
+    }
 
-        let x = self.dist(p + dx) - self.dist(p - dx);
-        let y = self.dist(p + dy) - self.dist(p - dy);
-        let z = self.dist(p + dz) - self.dist(p - dz);
+    fn may_hit(&self, origin: Vec3, direction: Vec3) -> bool {
+        self.sdf.may_hit(origin - self.offset, direction)
+    }
 
-        vec3(x, y, z).normalize()
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        self.sdf.grad(p - self.offset, mask)
     }
 
-    fn ray_intersection(&self, origin: Vec3, direction: Vec3) -> Option<HitInfo> {
-        let mut acc = 0.0;
-        let mut steps = 0;
-        let mut position;
-        let mut dist;
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        self.sdf.dist_grad(p - self.offset, mask)
+    }
 
-        loop {
-            position = origin + acc * direction;
-            dist = self.dist(position);
-            acc += dist;
-            steps += 1;
-            if dist < SURFACE_DIST {
-                return Some(HitInfo {
-                    position: origin + acc * direction,
-                    material: self.distinfo(origin + acc * direction).material,
-                });
-            } else if acc > MAX_DIST || steps > MAX_STEPS {
-                return None;
-            }
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        self.sdf.profiled_at(p - self.offset, mask)
+    }
+}
+
+impl<S: SdfMap> SdfMap for Rotation<S> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.sdf.dist(self.q.mul_vec3(p), mask)
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        self.sdf.distinfo(self.q.mul_vec3(p), mask)
+    }
+
+    fn bounds(&self) -> Bounds {
+        transform_bounds(self.sdf.bounds(), |p| self.q.inverse().mul_vec3(p))
+    }
+
+    fn may_hit(&self, origin: Vec3, direction: Vec3) -> bool {
+        self.sdf
+            .may_hit(self.q.mul_vec3(origin), self.q.mul_vec3(direction))
+    }
+
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        let grad = self.sdf.grad(self.q.mul_vec3(p), mask)?;
+        Some(self.q.inverse().mul_vec3(grad))
+    }
+
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        let (dist, grad) = self.sdf.dist_grad(self.q.mul_vec3(p), mask);
+        (dist, grad.map(|grad| self.q.inverse().mul_vec3(grad)))
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        self.sdf.profiled_at(self.q.mul_vec3(p), mask)
+    }
+}
+
+impl<S: SdfMap> SdfMap for Scale<S> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.factor * self.sdf.dist(p / self.factor, mask)
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        let distinfo = self.sdf.distinfo(p / self.factor, mask);
+        DistInfo {
+            distance: self.factor * distinfo.distance,
+            material: distinfo.material,
         }
     }
+
+    fn bounds(&self) -> Bounds {
+        transform_bounds(self.sdf.bounds(), |p| self.factor * p)
+    }
+
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        self.sdf.grad(p / self.factor, mask)
+    }
+
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        let (dist, grad) = self.sdf.dist_grad(p / self.factor, mask);
+        (self.factor * dist, grad)
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        self.sdf.profiled_at(p / self.factor, mask)
+    }
 }
 
 impl<S1: SdfMap, S2: SdfMap> SdfMap for Union<S1, S2> {
-    fn dist(&self, p: Vec3) -> f32 {
-        self.sdf1.dist(p).min(self.sdf2.dist(p))
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.sdf1.dist(p, mask).min(self.sdf2.dist(p, mask))
     }
 
-    fn distinfo(&self, p: Vec3) -> DistInfo {
-        let distinfo1 = self.sdf1.distinfo(p);
-        let distinfo2 = self.sdf2.distinfo(p);
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        self.sdf1.dist4(p, mask).min(self.sdf2.dist4(p, mask))
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        let distinfo1 = self.sdf1.distinfo(p, mask);
+        let distinfo2 = self.sdf2.distinfo(p, mask);
 
-        if distinfo1.distance < distinfo2.distance {
+        // Ties go to the first map, so that of coincident surfaces, the one
+        // merged first, or listed first in union_all!, is seen.
+        if distinfo1.distance <= distinfo2.distance {
             distinfo1
         } else {
             distinfo2
         }
     }
+
+    fn bounds(&self) -> Bounds {
+        union_bounds(self.sdf1.bounds(), self.sdf2.bounds())
+    }
+
+    fn may_hit(&self, origin: Vec3, direction: Vec3) -> bool {
+        self.sdf1.may_hit(origin, direction) || self.sdf2.may_hit(origin, direction)
+    }
+
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        self.dist_grad(p, mask).1
+    }
+
+    // Ties go to the first map, as in distinfo.
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        let dist_grad1 = self.sdf1.dist_grad(p, mask);
+        let dist_grad2 = self.sdf2.dist_grad(p, mask);
+        if dist_grad1.0 <= dist_grad2.0 {
+            dist_grad1
+        } else {
+            dist_grad2
+        }
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        if self.sdf1.dist(p, mask) <= self.sdf2.dist(p, mask) {
+            self.sdf1.profiled_at(p, mask)
+        } else {
+            self.sdf2.profiled_at(p, mask)
+        }
+    }
+}
+
+// A union that skips evaluating one child where the other is provably
+// closer, which is where the distance to the bounds of the skipped child is
+// at least the distance to the other. The bounds of both children are
+// computed once, when the union is made.
+#[derive(Clone, Copy, Debug)]
+pub struct BoundedUnion<S1, S2> {
+    sdf1: S1,
+    sdf2: S2,
+    bounds1: Bounds,
+    bounds2: Bounds,
+}
+
+impl<S1: SdfMap, S2: SdfMap> BoundedUnion<S1, S2> {
+    pub fn new(sdf1: S1, sdf2: S2) -> Self {
+        Self {
+            bounds1: sdf1.bounds(),
+            bounds2: sdf2.bounds(),
+            sdf1,
+            sdf2,
+        }
+    }
+}
+
+impl<S1: SdfMap, S2: SdfMap> SdfMap for BoundedUnion<S1, S2> {
+    // The child whose bounds are closer is evaluated first, as it is the
+    // likelier one to be closer itself.
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        let near1 = bounds_dist(self.bounds1, p);
+        let near2 = bounds_dist(self.bounds2, p);
+
+        if near1 <= near2 {
+            let dist1 = self.sdf1.dist(p, mask);
+            if dist1 <= near2 {
+                return dist1;
+            }
+            dist1.min(self.sdf2.dist(p, mask))
+        } else {
+            let dist2 = self.sdf2.dist(p, mask);
+            if dist2 < near1 {
+                return dist2;
+            }
+            self.sdf1.dist(p, mask).min(dist2)
+        }
+    }
+
+    // The four points are skipped only together.
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        let dist1 = self.sdf1.dist4(p, mask);
+        let near2 = Vec4::from(p.to_array().map(|p| bounds_dist(self.bounds2, p)));
+        if dist1.cmple(near2).all() {
+            return dist1;
+        }
+        dist1.min(self.sdf2.dist4(p, mask))
+    }
+
+    // Ties go to the first map, as in Union, so the second map skips the
+    // first only when it is strictly closer than its bounds.
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        let near1 = bounds_dist(self.bounds1, p);
+        let near2 = bounds_dist(self.bounds2, p);
+
+        if near1 <= near2 {
+            let distinfo1 = self.sdf1.distinfo(p, mask);
+            if distinfo1.distance <= near2 {
+                return distinfo1;
+            }
+            let distinfo2 = self.sdf2.distinfo(p, mask);
+            if distinfo1.distance <= distinfo2.distance {
+                distinfo1
+            } else {
+                distinfo2
+            }
+        } else {
+            let distinfo2 = self.sdf2.distinfo(p, mask);
+            if distinfo2.distance < near1 {
+                return distinfo2;
+            }
+            let distinfo1 = self.sdf1.distinfo(p, mask);
+            if distinfo1.distance <= distinfo2.distance {
+                distinfo1
+            } else {
+                distinfo2
+            }
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        union_bounds(self.bounds1, self.bounds2)
+    }
+
+    fn may_hit(&self, origin: Vec3, direction: Vec3) -> bool {
+        self.sdf1.may_hit(origin, direction) || self.sdf2.may_hit(origin, direction)
+    }
+
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        self.dist_grad(p, mask).1
+    }
+
+    // The children are skipped as in dist, and ties go to the first map as
+    // in distinfo.
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        let near1 = bounds_dist(self.bounds1, p);
+        let near2 = bounds_dist(self.bounds2, p);
+
+        let (dist_grad1, dist_grad2) = if near1 <= near2 {
+            let dist_grad1 = self.sdf1.dist_grad(p, mask);
+            if dist_grad1.0 <= near2 {
+                return dist_grad1;
+            }
+            (dist_grad1, self.sdf2.dist_grad(p, mask))
+        } else {
+            let dist_grad2 = self.sdf2.dist_grad(p, mask);
+            if dist_grad2.0 < near1 {
+                return dist_grad2;
+            }
+            (self.sdf1.dist_grad(p, mask), dist_grad2)
+        };
+
+        if dist_grad1.0 <= dist_grad2.0 {
+            dist_grad1
+        } else {
+            dist_grad2
+        }
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        if self.sdf1.dist(p, mask) <= self.sdf2.dist(p, mask) {
+            self.sdf1.profiled_at(p, mask)
+        } else {
+            self.sdf2.profiled_at(p, mask)
+        }
+    }
+}
+
+/// Merges any number of `SdfMap`s into a balanced tree of `BoundedUnion`s.
+///
+/// ```
+/// use glam::{vec3, Vec3};
+/// use raymarching_pathtracer::{sdf::*, union_all};
+///
+/// let gray = Material::Lambertian {
+///     color: Vec3::splat(0.5),
+/// };
+///
+/// let map = union_all![
+///     plane(Vec3::Z).material(gray.clone()),
+///     sphere(1.0).position(vec3(0.0, 0.0, 1.0)).material(gray.clone()),
+///     cuboid(Vec3::ONE).position(vec3(3.0, 0.0, 1.0)).material(gray.clone()),
+///     torus(1.0, 0.2).position(vec3(-3.0, 0.0, 1.0)).material(gray),
+/// ];
+///
+/// assert!(map.dist(vec3(0.0, 0.0, 1.0), RayMask::ALL) < 0.0);
+/// ```
+///
+/// Where surfaces coincide, the one listed first is seen:
+///
+/// ```
+/// use glam::{vec3, Vec3};
+/// use raymarching_pathtracer::{sdf::*, union_all};
+///
+/// let red = Material::Lambertian {
+///     color: vec3(1.0, 0.0, 0.0),
+/// };
+/// let blue = Material::Lambertian {
+///     color: vec3(0.0, 0.0, 1.0),
+/// };
+///
+/// let map = union_all![
+///     plane(Vec3::Z).material(red),
+///     plane(Vec3::Z).material(blue.clone()),
+///     plane(Vec3::Z).material(blue),
+/// ];
+///
+/// for p in [vec3(0.0, 0.0, 0.0), vec3(2.0, -1.0, 0.5), vec3(0.3, 0.7, -1.0)] {
+///     assert!(matches!(
+///         map.material_at(p),
+///         Material::Lambertian { color } if color == vec3(1.0, 0.0, 0.0)
+///     ));
+/// }
+/// ```
+#[macro_export]
+macro_rules! union_all {
+    (@pairs [$($acc:expr),*] $a:expr, $b:expr, $($rest:expr),+) => {
+        $crate::union_all!(@pairs [$($acc,)* $crate::sdf::BoundedUnion::new($a, $b)] $($rest),+)
+    };
+    (@pairs [$($acc:expr),*] $a:expr, $b:expr) => {
+        $crate::union_all!($($acc,)* $crate::sdf::BoundedUnion::new($a, $b))
+    };
+    (@pairs [$($acc:expr),*] $a:expr) => {
+        $crate::union_all!($($acc,)* $a)
+    };
+    ($a:expr $(,)?) => {
+        $a
+    };
+    ($($x:expr),+ $(,)?) => {
+        $crate::union_all!(@pairs [] $($x),+)
+    };
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IntersectionMap<S1, S2> {
+    sdf1: S1,
+    sdf2: S2,
+}
+
+impl<S1: SdfMap, S2: SdfMap> SdfMap for IntersectionMap<S1, S2> {
+    // A first child hidden by the mask hides the whole intersection. Bounds
+    // can't tell anything more without evaluating the second child, as it
+    // may be hidden too.
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        let dist1 = self.sdf1.dist(p, mask);
+        if dist1 == f32::INFINITY {
+            return dist1;
+        }
+        dist1.max(self.sdf2.dist(p, mask))
+    }
+
+    // The surface of an intersection belongs to the child that is farther
+    // away. On edges where both are equally far, the first child wins.
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        let distinfo1 = self.sdf1.distinfo(p, mask);
+        let distinfo2 = self.sdf2.distinfo(p, mask);
+
+        if distinfo2.distance > distinfo1.distance {
+            distinfo2
+        } else {
+            distinfo1
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        intersect_bounds(self.sdf1.bounds(), self.sdf2.bounds())
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        if self.sdf2.dist(p, mask) > self.sdf1.dist(p, mask) {
+            self.sdf2.profiled_at(p, mask)
+        } else {
+            self.sdf1.profiled_at(p, mask)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DifferenceMap<S1, S2> {
+    sdf1: S1,
+    sdf2: S2,
+    bounds2: Bounds,
+    interior: Material,
+}
+
+// The cut is skipped where it can't reach, as in Difference. A cut hidden
+// by the mask doesn't cut at all, which skipping it agrees with.
+impl<S1: SdfMap, S2: SdfMap> SdfMap for DifferenceMap<S1, S2> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        let dist1 = self.sdf1.dist(p, mask);
+        if dist1 >= -bounds_dist(self.bounds2, p) {
+            return dist1;
+        }
+        dist1.max(-self.sdf2.dist(p, mask))
+    }
+
+    // Where the cut is the active constraint, the surface is a cut face.
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        let distinfo1 = self.sdf1.distinfo(p, mask);
+        if distinfo1.distance >= -bounds_dist(self.bounds2, p) {
+            return distinfo1;
+        }
+        let distance2 = -self.sdf2.dist(p, mask);
+
+        if distance2 > distinfo1.distance {
+            DistInfo {
+                distance: distance2,
+                material: self.interior.clone(),
+            }
+        } else {
+            distinfo1
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.sdf1.bounds()
+    }
+
+    // Cut faces count as part of the object that is cut.
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        self.sdf1.profiled_at(p, mask)
+    }
+}
+
+// A map referenced through dynamic dispatch. Deeply nested generic maps make
+// for long compile times and large binaries, and every copy of a map placed
+// several times adds to both. Sharing it compiles it once, but each call
+// through the reference is dynamic, which can't be inlined into the
+// surrounding tree. This pays off for large sub-assemblies and costs a
+// little for small primitives, which are better copied.
+#[derive(Clone)]
+pub struct Shared {
+    map: Arc<dyn SdfMap + Send>,
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shared").finish_non_exhaustive()
+    }
+}
+
+impl SdfMap for Shared {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.map.dist(p, mask)
+    }
+
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        self.map.dist4(p, mask)
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.map.bounds()
+    }
+
+    fn may_hit(&self, origin: Vec3, direction: Vec3) -> bool {
+        self.map.may_hit(origin, direction)
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        self.map.distinfo(p, mask)
+    }
+
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        self.map.grad(p, mask)
+    }
+
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        self.map.dist_grad(p, mask)
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        self.map.profiled_at(p, mask)
+    }
+}
+
+// A map with a name that profiled rays attribute their steps to, for finding
+// the parts of a scene that marching spends its time on. Otherwise it only
+// forwards to the map.
+#[derive(Clone, Copy, Debug)]
+pub struct Profiled<S> {
+    sdf: S,
+    name: &'static str,
+}
+
+impl<S: SdfMap> SdfMap for Profiled<S> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.sdf.dist(p, mask)
+    }
+
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        self.sdf.dist4(p, mask)
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.sdf.bounds()
+    }
+
+    fn may_hit(&self, origin: Vec3, direction: Vec3) -> bool {
+        self.sdf.may_hit(origin, direction)
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        self.sdf.distinfo(p, mask)
+    }
+
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        self.sdf.grad(p, mask)
+    }
+
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        self.sdf.dist_grad(p, mask)
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        self.sdf.profiled_at(p, mask).or(Some(self.name))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RepeatMap<S> {
+    sdf: S,
+    period: Vec3,
+    cell_material: fn(Vec3, Material) -> Material,
+}
+
+impl<S: SdfMap> SdfMap for RepeatMap<S> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        let cell = (p / self.period + 0.5).floor();
+        self.sdf.dist(p - self.period * cell, mask)
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        let cell = (p / self.period + 0.5).floor();
+        let distinfo = self.sdf.distinfo(p - self.period * cell, mask);
+        DistInfo {
+            distance: distinfo.distance,
+            material: (self.cell_material)(cell, distinfo.material),
+        }
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        let cell = (p / self.period + 0.5).floor();
+        self.sdf.profiled_at(p - self.period * cell, mask)
+    }
 }
 
+/// A map painted by region, see `SdfMap::material_by_region`. A single
+/// sphere can be red below the plane z = 0 and blue above it:
+///
+/// ```
+/// use glam::{vec3, Vec3};
+/// use raymarching_pathtracer::sdf::*;
+///
+/// let gray = Material::Lambertian {
+///     color: Vec3::splat(0.5),
+/// };
+/// let sphere = sphere(1.0).material(gray);
+/// let painted = sphere.material_by_region(|p| Material::Lambertian {
+///     color: if p.z < 0.0 { vec3(1.0, 0.0, 0.0) } else { vec3(0.0, 0.0, 1.0) },
+/// });
+///
+/// let color = |p| match painted.material_at(p) {
+///     Material::Lambertian { color } => color,
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(color(vec3(0.6, 0.0, -0.8)), vec3(1.0, 0.0, 0.0));
+/// assert_eq!(color(vec3(0.6, 0.0, 0.8)), vec3(0.0, 0.0, 1.0));
+///
+/// let p = vec3(0.3, -0.2, 0.5);
+/// assert_eq!(painted.dist(p, RayMask::ALL), sphere.dist(p, RayMask::ALL));
+/// ```
 #[derive(Clone, Copy, Debug)]
+pub struct MaterialByRegion<S> {
+    sdf: S,
+    selector: fn(Vec3) -> Material,
+}
+
+impl<S: SdfMap> SdfMap for MaterialByRegion<S> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.sdf.dist(p, mask)
+    }
+
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        self.sdf.dist4(p, mask)
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.sdf.bounds()
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        DistInfo {
+            distance: self.sdf.dist(p, mask),
+            material: (self.selector)(p),
+        }
+    }
+
+    fn grad(&self, p: Vec3, mask: RayMask) -> Option<Vec3> {
+        self.sdf.grad(p, mask)
+    }
+
+    fn dist_grad(&self, p: Vec3, mask: RayMask) -> (f32, Option<Vec3>) {
+        self.sdf.dist_grad(p, mask)
+    }
+
+    fn profiled_at(&self, p: Vec3, mask: RayMask) -> Option<&'static str> {
+        self.sdf.profiled_at(p, mask)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct SdfObject<S: Sdf> {
     sdf: S,
     material: Material,
+    ray_mask: RayMask,
+}
+
+impl<S: Sdf> SdfObject<S> {
+    pub fn ray_mask(&self, ray_mask: RayMask) -> Self {
+        Self {
+            ray_mask,
+            ..self.clone()
+        }
+    }
 }
 
 impl<S: Sdf> SdfMap for SdfObject<S> {
-    fn dist(&self, p: Vec3) -> f32 {
-        self.sdf.dist(p)
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        if self.ray_mask.contains(mask) {
+            self.sdf.dist(p)
+        } else {
+            f32::INFINITY
+        }
     }
 
-    fn distinfo(&self, p: Vec3) -> DistInfo {
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> Vec4 {
+        if self.ray_mask.contains(mask) {
+            self.sdf.dist4(p)
+        } else {
+            Vec4::splat(f32::INFINITY)
+        }
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
         DistInfo {
-            distance: self.sdf.dist(p),
-            material: self.material,
+            distance: self.dist(p, mask),
+            material: self.material.clone(),
         }
     }
+
+    fn bounds(&self) -> Bounds {
+        self.sdf.bounds()
+    }
+
+    fn grad(&self, p: Vec3, _mask: RayMask) -> Option<Vec3> {
+        self.sdf.grad(p)
+    }
 }
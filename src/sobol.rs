@@ -0,0 +1,125 @@
+use super::sampling::Sampler;
+
+// Scrambled Sobol points, after Burley, "Practical Hash-based Owen Scrambling"
+// (2020). Dimensions are drawn in pairs, each pair being a shuffled and
+// Owen-scrambled copy of the first two Sobol dimensions. This keeps every
+// pair well stratified without needing direction numbers for hundreds of
+// dimensions.
+#[derive(Clone, Copy, Debug)]
+pub struct Sobol {
+    seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+fn hash_combine(seed: u32, value: u32) -> u32 {
+    seed ^ value
+        .wrapping_add(0x9e3779b9)
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2)
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// The first two dimensions of the Sobol sequence as 32-bit fractions.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut v = 1 << 31;
+    let mut i = index;
+
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+
+    (index.reverse_bits(), y)
+}
+
+fn to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+impl Sobol {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed: hash(seed),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Moves on to the given point of the sequence, starting again from its
+    // first pair of dimensions.
+    pub fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // Coordinates of a point in the given pair of dimensions, in [0, 1).
+    pub fn sample_2d(&self, index: u32, dimension: u32) -> (f32, f32) {
+        let seed = hash_combine(self.seed, hash(dimension));
+        let (x, y) = sobol_2d(nested_uniform_scramble(index, seed));
+        (
+            to_unit(nested_uniform_scramble(x, hash_combine(seed, 0))),
+            to_unit(nested_uniform_scramble(y, hash_combine(seed, 1))),
+        )
+    }
+
+    // Moves on to the given point of the sequence, continuing from the given
+    // pair of dimensions, as when several samples share their first
+    // dimensions.
+    pub fn continue_sample(&mut self, index: u32, dimension: u32) {
+        self.index = index;
+        self.dimension = dimension;
+    }
+
+    // A seed for the random choices along the current path, different for
+    // every pixel and path but the same from one render to the next.
+    pub fn path_seed(&self) -> u64 {
+        (self.seed as u64) << 32 | self.index as u64
+    }
+
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        let sample = self.sample_2d(self.index, self.dimension);
+        self.dimension += 1;
+        sample
+    }
+
+    // Uses up a whole pair of dimensions for a single value.
+    pub fn next_f32(&mut self) -> f32 {
+        self.next_2d().0
+    }
+}
+
+impl Sampler for Sobol {
+    fn next_f32(&mut self) -> f32 {
+        Sobol::next_f32(self)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        Sobol::next_2d(self)
+    }
+}
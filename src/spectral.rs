@@ -0,0 +1,69 @@
+use glam::{vec3, Vec3};
+
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+// Wavelength of the sodium D line, at which indices of refraction are given.
+const REFERENCE_WAVELENGTH: f32 = 589.3;
+
+// Linear sRGB response to a flat spectrum of unit radiance, used to map such a
+// spectrum to white.
+const WHITE_RGB: [f32; 3] = [128.36269, 101.54864, 97.04956];
+
+fn gaussian(wavelength: f32, mean: f32, sigma1: f32, sigma2: f32) -> f32 {
+    let sigma = if wavelength < mean { sigma1 } else { sigma2 };
+    let t = (wavelength - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 color matching functions, as fitted by Wyman, Sloan and Shirley,
+// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+// (2013).
+fn color_matching(wavelength: f32) -> Vec3 {
+    let l = wavelength;
+    vec3(
+        1.056 * gaussian(l, 599.8, 37.9, 31.0) + 0.362 * gaussian(l, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(l, 501.1, 20.4, 26.2),
+        0.821 * gaussian(l, 568.8, 46.9, 40.5) + 0.286 * gaussian(l, 530.9, 16.3, 31.1),
+        1.217 * gaussian(l, 437.0, 11.8, 36.0) + 0.681 * gaussian(l, 459.0, 26.0, 13.8),
+    )
+}
+
+fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    vec3(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+// Value of a smooth spectrum with the given RGB color at the wavelength. The
+// basis functions sum to one, so gray colors have flat spectra.
+pub fn rgb_to_spectrum(color: Vec3, wavelength: f32) -> f32 {
+    let basis = vec3(
+        gaussian(wavelength, 610.0, 40.0, 60.0),
+        gaussian(wavelength, 545.0, 35.0, 35.0),
+        gaussian(wavelength, 450.0, 60.0, 40.0),
+    );
+    color.dot(basis) / (basis.x + basis.y + basis.z)
+}
+
+// Linear RGB contribution of radiance at a wavelength that was sampled
+// uniformly over the visible range.
+pub fn spectrum_to_rgb(radiance: f32, wavelength: f32) -> Vec3 {
+    let xyz = (MAX_WAVELENGTH - MIN_WAVELENGTH) * radiance * color_matching(wavelength);
+    xyz_to_rgb(xyz) / Vec3::from(WHITE_RGB)
+}
+
+// A wavelength uniformly over the visible range, for u uniform in [0, 1).
+pub fn sample_wavelength(u: f32) -> f32 {
+    MIN_WAVELENGTH + (MAX_WAVELENGTH - MIN_WAVELENGTH) * u
+}
+
+// Cauchy's equation, with `dispersion` being the B coefficient in square
+// micrometers and `ior` the index of refraction at the reference wavelength.
+pub fn index_of_refraction(ior: f32, dispersion: f32, wavelength: f32) -> f32 {
+    let micrometers = 0.001 * wavelength;
+    let reference = 0.001 * REFERENCE_WAVELENGTH;
+    ior + dispersion * (1.0 / (micrometers * micrometers) - 1.0 / (reference * reference))
+}
@@ -0,0 +1,183 @@
+use super::error::RenderError;
+use glam::{vec2, vec3, Vec2, Vec3};
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+
+// What texture lookups outside of the unit square of UV coordinates return.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Wrap {
+    // The image tiles the plane.
+    #[default]
+    Repeat,
+    // The edge texels extend outwards.
+    Clamp,
+}
+
+// Linear RGB texels, stored row by row starting from the top of the image.
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+    pub wrap: Wrap,
+}
+
+impl fmt::Debug for Image {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Image")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("wrap", &self.wrap)
+            .finish_non_exhaustive()
+    }
+}
+
+fn srgb_decode(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linearizes an sRGB encoded color, like one picked from a color picker,
+/// into the linear RGB that materials and backgrounds take as radiance and
+/// albedo. Export encodes it back, exactly for `ColorSpace::Srgb`, and to
+/// within a level for the default gamma of 2.2:
+///
+/// ```
+/// use glam::Vec3;
+/// use raymarching_pathtracer::export::{ColorSpace, ExportSettings};
+/// use raymarching_pathtracer::ppm;
+/// use raymarching_pathtracer::texture::srgb_to_linear;
+///
+/// let gray = srgb_to_linear(Vec3::splat(128.0 / 255.0));
+/// assert!((gray.x - 0.216).abs() < 1e-3);
+///
+/// let export = |color_space| {
+///     let settings = ExportSettings { color_space, dither: false, ..ExportSettings::default() };
+///     ppm::to_rgb8(&[vec![gray]], &settings)[0]
+/// };
+/// assert_eq!(export(ColorSpace::Srgb), 128);
+/// assert!((export(ColorSpace::default()) as i32 - 128).abs() <= 1);
+/// ```
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    vec3(
+        srgb_decode(color.x),
+        srgb_decode(color.y),
+        srgb_decode(color.z),
+    )
+}
+
+impl Image {
+    // Loads an 8-bit sRGB encoded PNG file.
+    pub fn load_png(path: &str) -> Result<Image, RenderError> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+
+        let channels = info.color_type.samples();
+        let decode = |c: u8| srgb_decode(c as f32 / 255.0);
+        let pixels = data[..info.buffer_size()]
+            .chunks(channels)
+            .map(|texel| match channels {
+                1 | 2 => Vec3::splat(decode(texel[0])),
+                _ => vec3(decode(texel[0]), decode(texel[1]), decode(texel[2])),
+            })
+            .collect();
+
+        Ok(Image {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+            wrap: Wrap::default(),
+        })
+    }
+
+    // An image with the color of each texel given by its column and row.
+    pub fn from_fn(width: usize, height: usize, f: impl Fn(usize, usize) -> Vec3) -> Image {
+        Image {
+            width,
+            height,
+            pixels: (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| f(x, y))
+                .collect(),
+            wrap: Wrap::default(),
+        }
+    }
+
+    pub fn wrap(self, wrap: Wrap) -> Self {
+        Self { wrap, ..self }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let (x, y) = match self.wrap {
+            Wrap::Repeat => (
+                x.rem_euclid(self.width as i64),
+                y.rem_euclid(self.height as i64),
+            ),
+            Wrap::Clamp => (
+                x.clamp(0, self.width as i64 - 1),
+                y.clamp(0, self.height as i64 - 1),
+            ),
+        };
+        self.pixels[y as usize * self.width + x as usize]
+    }
+
+    // Bilinearly interpolated color at the UV coordinates, with (0, 0) at the
+    // bottom left corner of the image and (1, 1) at the top right.
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0).lerp(self.texel(x0 + 1, y0), tx);
+        let bottom = self.texel(x0, y0 + 1).lerp(self.texel(x0 + 1, y0 + 1), tx);
+        top.lerp(bottom, ty)
+    }
+}
+
+// How points on a surface are mapped to UV coordinates.
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+    // Projects along the normal of the plane spanned by `u` and `v`. The
+    // lengths of the axes set how many times the image repeats per unit.
+    Planar { u: Vec3, v: Vec3 },
+    // Longitude and latitude around the center, with the poles on the z-axis.
+    Spherical { center: Vec3 },
+    // Planar projections along the x-, y- and z-axes, blended by how much
+    // the normal faces each axis. The image repeats `scale` times per unit.
+    Triplanar { scale: f32 },
+}
+
+// Higher values give sharper transitions between the three projections.
+const TRIPLANAR_SHARPNESS: f32 = 4.0;
+
+impl Projection {
+    pub fn sample(&self, image: &Image, p: Vec3, normal: Vec3) -> Vec3 {
+        match *self {
+            Projection::Planar { u, v } => image.sample(vec2(p.dot(u), p.dot(v))),
+            Projection::Spherical { center } => {
+                let d = (p - center).normalize();
+                let u = 0.5 + d.y.atan2(d.x) / (2.0 * PI);
+                let v = 0.5 + d.z.clamp(-1.0, 1.0).asin() / PI;
+                image.sample(vec2(u, v))
+            }
+            Projection::Triplanar { scale } => {
+                let q = scale * p;
+                let w = normal.abs().powf(TRIPLANAR_SHARPNESS);
+                let w = w / (w.x + w.y + w.z);
+                w.x * image.sample(vec2(q.y, q.z))
+                    + w.y * image.sample(vec2(q.x, q.z))
+                    + w.z * image.sample(vec2(q.x, q.y))
+            }
+        }
+    }
+}
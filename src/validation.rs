@@ -0,0 +1,58 @@
+use super::sampling::Sampler;
+use super::sdf::Sdf;
+use glam::{vec3, Vec3};
+
+const LIPSCHITZ_TOLERANCE: f32 = 1e-3;
+const MAX_PAIR_DIST: f32 = 0.1;
+
+// The pair of points where the field changed the fastest relative to the
+// distance between them. A distance field may change at most as fast as the
+// distance itself, i.e. the ratio may not exceed one.
+#[derive(Clone, Copy, Debug)]
+pub struct LipschitzReport {
+    pub worst_ratio: f32,
+    pub a: Vec3,
+    pub b: Vec3,
+}
+
+impl LipschitzReport {
+    pub fn is_lipschitz(&self) -> bool {
+        self.worst_ratio <= 1.0 + LIPSCHITZ_TOLERANCE
+    }
+}
+
+// Samples random pairs of nearby points within the bounds, drawn from the
+// given sampler so that a seeded one gives the same report every time.
+// Marching a field that fails this check can overshoot the surface and leave
+// holes in renders.
+pub fn validate_sdf<S: Sdf>(
+    sdf: &S,
+    bounds: (Vec3, Vec3),
+    samples: usize,
+    sampler: &mut impl Sampler,
+) -> LipschitzReport {
+    let (min, max) = bounds;
+    let mut point = || vec3(sampler.next_f32(), sampler.next_f32(), sampler.next_f32());
+
+    let mut report = LipschitzReport {
+        worst_ratio: 0.0,
+        a: min,
+        b: min,
+    };
+
+    for _ in 0..samples {
+        let a = min + (max - min) * point();
+        let b = a + MAX_PAIR_DIST * point() - 0.5 * MAX_PAIR_DIST;
+        let ratio = (sdf.dist(a) - sdf.dist(b)).abs() / (a - b).length();
+
+        if ratio > report.worst_ratio {
+            report = LipschitzReport {
+                worst_ratio: ratio,
+                a,
+                b,
+            };
+        }
+    }
+
+    report
+}
@@ -0,0 +1,89 @@
+use super::error::RenderError;
+use super::export::ExportSettings;
+use super::ppm;
+use super::renderer::{Accumulator, RenderSettings, Scene};
+
+use glam::Vec3;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+// How far the camera moves per key press.
+const NUDGE_DISTANCE: f32 = 0.1;
+// Samples per pixel rendered between updates of the window.
+const SAMPLES_PER_UPDATE: usize = 1;
+const MAX_FPS: usize = 60;
+
+// The window is the output device here, so its failures count as IO errors.
+fn window_error(error: minifb::Error) -> RenderError {
+    RenderError::Io(std::io::Error::other(error.to_string()))
+}
+
+// Camera movement for a key, along its right, up and forward directions.
+fn nudge(key: Key) -> Option<Vec3> {
+    match key {
+        Key::D | Key::Right => Some(Vec3::X),
+        Key::A | Key::Left => Some(-Vec3::X),
+        Key::E => Some(Vec3::Y),
+        Key::Q => Some(-Vec3::Y),
+        Key::W | Key::Up => Some(Vec3::Z),
+        Key::S | Key::Down => Some(-Vec3::Z),
+        _ => None,
+    }
+}
+
+// Shows the render in a window as it converges, until the window is closed
+// or Escape is pressed, and returns the image as it was then. WASD and the
+// arrow keys move the camera sideways and forwards, and Q and E down and up,
+// starting the render over.
+pub fn show(
+    settings: &RenderSettings,
+    scene: &mut Scene,
+    export_settings: &ExportSettings,
+) -> Result<Vec<Vec<Vec3>>, RenderError> {
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    let mut window = Window::new(
+        "raymarching-pathtracer",
+        width,
+        height,
+        WindowOptions::default(),
+    )
+    .map_err(window_error)?;
+    window.set_target_fps(MAX_FPS);
+
+    // The pool is built once for the whole session rather than per update.
+    let pool = settings.thread_pool()?;
+    let mut accumulator = Accumulator::new(settings);
+    let mut buffer = vec![0; width * height];
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let movement = window
+            .get_keys_pressed(KeyRepeat::Yes)
+            .into_iter()
+            .filter_map(nudge)
+            .fold(Vec3::ZERO, |u, v| u + v);
+        if movement != Vec3::ZERO {
+            let movement = NUDGE_DISTANCE * movement;
+            scene.camera.translate(movement.x, movement.y, movement.z);
+        }
+
+        if accumulator.is_done(settings, scene) {
+            window.update();
+            continue;
+        }
+
+        let scene = &*scene;
+        let mut add_samples = || accumulator.add_samples(settings, scene, SAMPLES_PER_UPDATE);
+        match &pool {
+            Some(pool) => pool.install(add_samples),
+            None => add_samples(),
+        }
+        let rgb = ppm::to_rgb8(&accumulator.mean(), export_settings);
+        for (pixel, c) in buffer.iter_mut().zip(rgb.chunks(3)) {
+            *pixel = u32::from_be_bytes([0, c[0], c[1], c[2]]);
+        }
+        window
+            .update_with_buffer(&buffer, width, height)
+            .map_err(window_error)?;
+    }
+
+    Ok(accumulator.mean())
+}
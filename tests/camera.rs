@@ -0,0 +1,212 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::camera::{Camera, Ray};
+use raymarching_pathtracer::error::RenderError;
+use raymarching_pathtracer::renderer::{render_preview, Scene};
+use raymarching_pathtracer::sdf::*;
+
+// A scene with nothing in view, whose background shows the direction of
+// each ray, shifted to be positive.
+fn directions_scene(width: i32, height: i32) -> Scene {
+    let aspect_ratio = width as f32 / height as f32;
+    let camera = Camera::new(Vec3::ZERO, Vec3::Y, Vec3::Z, 1.0, aspect_ratio, 0.0);
+    let behind = sphere(0.1)
+        .position(-10.0 * Vec3::Y)
+        .material(Material::Lambertian { color: Vec3::ONE });
+    let mut scene = Scene::new(camera, behind);
+    scene.background_color = Box::new(|ray: &Ray| ray.direction + Vec3::ONE);
+    scene
+}
+
+// Checks that the direction seen in each pixel points at its center, in the
+// screen coordinates of Camera::get_ray.
+fn assert_pixel_centers(scene: &Scene, image: &[Vec<Vec3>], tolerance: f32) {
+    let (height, width) = (image.len() as f32, image[0].len() as f32);
+    for (i, row) in image.iter().enumerate() {
+        for (j, pixel) in row.iter().enumerate() {
+            let (x, y) = scene.camera.project(*pixel - Vec3::ONE).unwrap();
+            let center = (
+                (j as f32 + 0.5) / width - 0.5,
+                0.5 - (i as f32 + 0.5) / height,
+            );
+            assert!(
+                (x - center.0).abs() < tolerance && (y - center.1).abs() < tolerance,
+                "pixel ({}, {}) looks at ({}, {}) instead of {:?}",
+                i,
+                j,
+                x,
+                y,
+                center
+            );
+        }
+    }
+}
+
+#[test]
+fn preview_rays_go_through_pixel_centers() {
+    for (width, height) in [(8, 2), (5, 3), (1, 1)] {
+        let scene = directions_scene(width, height);
+        let image = render_preview(width, height, Vec3::ZERO, &scene).unwrap();
+        assert_pixel_centers(&scene, &image, 1e-4);
+    }
+}
+
+#[test]
+fn previews_reject_invalid_input() {
+    let invalid = |result| matches!(result, Err(RenderError::InvalidSettings(_)));
+    let scene = directions_scene(8, 8);
+    assert!(invalid(render_preview(0, 8, Vec3::ZERO, &scene)));
+    assert!(invalid(render_preview(8, -1, Vec3::ZERO, &scene)));
+    assert!(invalid(render_preview(8, 8, -Vec3::X, &scene)));
+    assert!(invalid(render_preview(8, 8, Vec3::splat(f32::NAN), &scene)));
+
+    let mut scene = scene;
+    scene.exposure = 0.0;
+    let result = render_preview(8, 8, Vec3::ZERO, &scene);
+    assert!(matches!(result, Err(RenderError::MalformedScene(_))));
+}
+
+// In spectral mode the colors that show the directions are only matched on
+// average over many more samples.
+#[cfg(not(feature = "spectral"))]
+#[test]
+fn render_samples_are_spread_around_pixel_centers() {
+    use raymarching_pathtracer::renderer::{render, RenderSettings};
+
+    for (width, height) in [(8, 2), (5, 3), (1, 1)] {
+        let scene = directions_scene(width, height);
+        let settings = RenderSettings {
+            width,
+            height,
+            sample_count: 64,
+            ..Default::default()
+        };
+        let image = render(&settings, &scene).unwrap();
+        assert_pixel_centers(&scene, &image, 0.01);
+    }
+}
+
+#[test]
+fn pixels_are_square_at_the_matching_aspect_ratio() {
+    // Neighboring pixels are as far apart across as down.
+    let scene = directions_scene(8, 2);
+    let image = render_preview(8, 2, Vec3::ZERO, &scene).unwrap();
+    let direction = |i: usize, j: usize| image[i][j] - Vec3::ONE;
+    let across = direction(0, 3).angle_between(direction(0, 4));
+    let down = direction(0, 3).angle_between(direction(1, 3));
+    assert!(
+        (across / down - 1.0).abs() < 0.01,
+        "{} against {}",
+        across,
+        down
+    );
+    assert!(direction(0, 3).dot(vec3(0.0, 1.0, 0.0)) > 0.99);
+}
+
+// Distance from the point to the line along the ray.
+fn distance_to_ray(ray: &Ray, point: Vec3) -> f32 {
+    let d = point - ray.origin;
+    (d - d.dot(ray.direction) * ray.direction).length()
+}
+
+#[test]
+fn rays_through_the_screen_center_converge_at_the_focus() {
+    let position = vec3(1.0, -4.0, 2.0);
+    let target = vec3(0.3, 2.0, 0.5);
+    let mut camera = Camera::new(position, target, Vec3::Z, 0.8, 1.5, 0.4);
+    // Focus nearer than the point the camera was made to look at.
+    let focus = position + 0.5 * (target - position);
+    camera.focus_on(focus).unwrap();
+
+    let lens = [(0.0, 0.0), (1.0, 0.0), (0.0, -1.0), (-0.6, 0.7), (0.5, 0.5)];
+    for (dx, dy) in lens {
+        let ray = camera.get_ray(0.0, 0.0, (dx, dy));
+        assert!(distance_to_ray(&ray, focus) < 1e-5, "{}", ray.direction);
+        if (dx, dy) != (0.0, 0.0) {
+            assert!(distance_to_ray(&ray, target) > 0.01);
+        }
+    }
+}
+
+#[test]
+fn focusing_behind_the_camera_is_rejected() {
+    let mut camera = Camera::new(Vec3::ZERO, Vec3::Y, Vec3::Z, 1.0, 1.0, 0.4);
+    let generation = camera.generation();
+    for point in [-Vec3::Y, Vec3::X, Vec3::ZERO, Vec3::NAN] {
+        assert!(camera.focus_on(point).is_err());
+    }
+    assert_eq!(camera.generation(), generation);
+
+    // The focus is still on the point the camera looks at.
+    let ray = camera.get_ray(0.0, 0.0, (1.0, 0.0));
+    assert!(distance_to_ray(&ray, Vec3::Y) < 1e-6);
+}
+
+// An evenly glowing ball and box against black, so that the only error in
+// the image is in how much of each pixel the edges cover.
+#[cfg(not(feature = "spectral"))]
+fn edges_scene() -> Scene {
+    let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 0.8, 1.0, 0.0);
+    let glow = Material::Emissive {
+        color: Vec3::ONE,
+        intensity: 1.0,
+    };
+    let ball = sphere(1.0).material(glow.clone());
+    let cube = cuboid(Vec3::splat(0.6))
+        .rotate(Vec3::Y, 0.37)
+        .position(vec3(1.2, 0.0, 0.9))
+        .material(glow);
+    let mut scene = Scene::new(camera, ball.merge(cube));
+    scene.background_color = Box::new(|_| Vec3::ZERO);
+    scene
+}
+
+// In spectral mode the white of the edges is noisy in itself.
+#[cfg(not(feature = "spectral"))]
+#[test]
+fn supersampling_costs_as_much_as_the_samples_it_adds() {
+    use raymarching_pathtracer::renderer::{render, RenderSettings};
+
+    let scene = edges_scene();
+    let settings = |ssaa, sample_count| RenderSettings {
+        width: 40,
+        height: 40,
+        sample_count,
+        ssaa,
+        ..Default::default()
+    };
+    let reference = render(&settings(1, 512), &scene).unwrap();
+    // Root mean square error over the pixels the edges cross.
+    let edge_error = |ssaa, sample_count| {
+        let image = render(&settings(ssaa, sample_count), &scene).unwrap();
+        let errors: Vec<f32> = image
+            .iter()
+            .flatten()
+            .zip(reference.iter().flatten())
+            .filter(|(_, expected)| expected.x > 0.01 && expected.x < 0.99)
+            .map(|(pixel, expected)| (pixel.x - expected.x).powi(2))
+            .collect();
+        assert!(errors.len() > 50);
+        (errors.iter().sum::<f32>() / errors.len() as f32).sqrt()
+    };
+
+    // At the same sample count, supersampling takes four times the samples
+    // and cuts the error at the edges.
+    let plain = edge_error(1, 4);
+    let supersampled = edge_error(2, 4);
+    assert!(
+        supersampled < 0.5 * plain,
+        "{} against {}",
+        supersampled,
+        plain
+    );
+
+    // At the same number of samples in all, it is about as good as taking
+    // them all over the whole pixel.
+    let plain = edge_error(1, 16);
+    assert!(
+        (supersampled / plain - 1.0).abs() < 0.25,
+        "{} against {}",
+        supersampled,
+        plain
+    );
+}
@@ -0,0 +1,46 @@
+use std::process::Command;
+
+fn run(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_raymarching-pathtracer"))
+        .args(args)
+        .current_dir(env!("CARGO_TARGET_TMPDIR"))
+        .output()
+        .expect("Failed to run the renderer.")
+}
+
+#[test]
+fn unknown_scene_is_reported() {
+    let output = run(&["scene0"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim(),
+        "Scene \"scene0\" not found."
+    );
+}
+
+#[test]
+fn invalid_settings_are_reported_instead_of_panicking() {
+    let output = run(&["scene1", "--width", "0"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim(),
+        "Flag \"--width\" needs a positive integer value."
+    );
+}
+
+#[test]
+fn profile_reports_the_steps_of_the_camera_rays() {
+    let output = run(&[
+        "scene1",
+        "--width",
+        "16",
+        "--height",
+        "12",
+        "--samples",
+        "1",
+        "--profile",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Rays: 192, steps per ray: "), "{}", stdout);
+}
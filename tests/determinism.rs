@@ -0,0 +1,59 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::light::Light;
+use raymarching_pathtracer::renderer::{render, RenderSettings, Scene};
+use raymarching_pathtracer::sdf::*;
+
+// A floor with a fuzzy mirror and a glass ball on it, lit by a sun and a sky,
+// so that paths make random choices at every kind of surface.
+fn mixed_scene() -> Scene {
+    let camera = Camera::new(vec3(0.0, -5.0, 1.5), Vec3::ZERO, Vec3::Z, 0.8, 1.0, 0.0);
+    let floor = plane(Vec3::Z)
+        .position(-Vec3::Z)
+        .material(Material::Lambertian { color: Vec3::ONE });
+    let mirror = sphere(0.8)
+        .position(vec3(-1.0, 0.0, -0.2))
+        .material(Material::Specular {
+            color: Vec3::ONE,
+            specularity: 0.7,
+            fuzziness: 0.2,
+        });
+    let glass = sphere(0.8)
+        .position(vec3(1.0, 0.0, -0.2))
+        .material(Material::Dielectric {
+            color: Vec3::ONE,
+            ior: 1.5,
+            dispersion: 0.01,
+        });
+    let mut scene = Scene::new(camera, floor.merge(mirror).merge(glass));
+    scene.background_color = Box::new(|ray| vec3(0.3, 0.5, 0.8) * (ray.direction.z + 1.0));
+    scene.lights = vec![Light::Directional {
+        direction: vec3(1.0, -1.0, 2.0).normalize(),
+        cos_radius: 0.999,
+        radiance: Vec3::splat(1000.0),
+    }];
+    scene
+}
+
+#[test]
+fn renders_are_the_same_on_any_number_of_threads() {
+    let scene = mixed_scene();
+    let parallel = RenderSettings {
+        width: 32,
+        height: 32,
+        sample_count: 8,
+        ..Default::default()
+    };
+    let single = RenderSettings {
+        threads: Some(1),
+        ..parallel
+    };
+
+    let image = render(&parallel, &scene).unwrap();
+    assert!(image
+        .iter()
+        .flatten()
+        .any(|pixel| pixel.min_element() > 0.0));
+    assert_eq!(render(&parallel, &scene).unwrap(), image);
+    assert_eq!(single.install(|| render(&single, &scene)).unwrap(), image);
+}
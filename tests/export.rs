@@ -0,0 +1,174 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::export::{ClampMode, ColorSpace, ExportSettings};
+use raymarching_pathtracer::{error::RenderError, ppm};
+
+// Exports to a file of its own for each test, as the tests run in parallel.
+fn export_apng(name: &str, frames: &[Vec<Vec<Vec3>>]) -> Result<String, RenderError> {
+    let path = format!("{}/{}.png", env!("CARGO_TARGET_TMPDIR"), name);
+    ppm::export_apng(&path, frames, &ExportSettings::default(), 100)?;
+    Ok(path)
+}
+
+#[test]
+fn animation_without_frames_is_rejected() {
+    assert!(matches!(
+        export_apng("no_frames", &[]),
+        Err(RenderError::InvalidSettings(_))
+    ));
+}
+
+#[test]
+fn animation_of_empty_images_is_rejected() {
+    assert!(matches!(
+        export_apng("empty_frame", &[Vec::new()]),
+        Err(RenderError::InvalidSettings(_))
+    ));
+    assert!(matches!(
+        export_apng("empty_rows", &[vec![Vec::new()]]),
+        Err(RenderError::InvalidSettings(_))
+    ));
+}
+
+#[test]
+fn animation_frames_must_match_in_size() {
+    let frames = [vec![vec![Vec3::ONE; 4]; 3], vec![vec![Vec3::ONE; 3]; 4]];
+    assert!(matches!(
+        export_apng("mismatched_frames", &frames),
+        Err(RenderError::InvalidSettings(_))
+    ));
+    assert!(export_apng("matching_frames", &vec![vec![vec![Vec3::ONE; 4]; 3]; 2]).is_ok());
+}
+
+#[test]
+fn identical_frames_are_encoded_identically() {
+    // A shallow gradient, which dithering spreads over two levels.
+    let frame: Vec<Vec<Vec3>> = (0..6)
+        .map(|i| {
+            (0..10)
+                .map(|j| Vec3::splat(0.3 + 0.001 * (i + j) as f32))
+                .collect()
+        })
+        .collect();
+    let path = export_apng("identical_frames", &[frame.clone(), frame.clone()]).unwrap();
+
+    let decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    assert_eq!(reader.info().animation_control().unwrap().num_frames, 2);
+    let mut frames = vec![vec![0; reader.output_buffer_size()]; 2];
+    for data in &mut frames {
+        reader.next_frame(data).unwrap();
+    }
+    assert_eq!(frames[0], frames[1]);
+    assert_eq!(frames[0], ppm::to_rgb8(&frame, &ExportSettings::default()));
+}
+
+// A mid gray exported at the given white balance, as linear 16-bit values
+// scaled to one.
+fn balanced_gray(kelvin: f32) -> Vec3 {
+    let settings = ExportSettings {
+        white_balance: kelvin,
+        color_space: ColorSpace::Linear,
+        ..ExportSettings::default()
+    };
+    let rgb = ppm::to_rgb16(&[vec![Vec3::splat(0.5)]], &settings);
+    Vec3::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32) / 65535.0
+}
+
+#[test]
+fn white_balance_is_neutral_at_6500_kelvin() {
+    assert_eq!(balanced_gray(6500.0), Vec3::splat(balanced_gray(6500.0).x));
+    assert!((balanced_gray(6500.0).x - 0.5).abs() < 1e-4);
+    // Nearby temperatures barely tint the image.
+    let nearby = balanced_gray(6400.0);
+    assert!(
+        nearby.max_element() - nearby.min_element() < 0.02,
+        "{}",
+        nearby
+    );
+}
+
+#[test]
+fn white_balance_warms_below_and_cools_above_6500_kelvin() {
+    let warm = balanced_gray(3200.0);
+    assert!(warm.x > warm.y && warm.y > warm.z, "{}", warm);
+    assert!(warm.z < 0.5 * warm.x, "{}", warm);
+
+    let cool = balanced_gray(10000.0);
+    assert!(cool.z > cool.x, "{}", cool);
+}
+
+#[test]
+fn auto_exposure_brings_dim_and_bright_images_to_middle_gray() {
+    let dim = vec![vec![Vec3::splat(0.01); 4]; 3];
+    let bright = vec![vec![Vec3::splat(5.0); 4]; 3];
+    assert!(ppm::auto_exposure(&dim) > 1.0);
+    assert!(ppm::auto_exposure(&bright) < 1.0);
+    for image in [&dim, &bright] {
+        let exposed = ppm::auto_exposure(image) * image[0][0].x;
+        assert!((exposed - 0.18).abs() < 0.002, "{}", exposed);
+    }
+
+    // Both export the same once exposed.
+    let settings = ExportSettings {
+        auto_exposure: true,
+        color_space: ColorSpace::Linear,
+        ..ExportSettings::default()
+    };
+    let (dim, bright) = (
+        ppm::to_rgb16(&dim, &settings),
+        ppm::to_rgb16(&bright, &settings),
+    );
+    assert!(dim.iter().zip(&bright).all(|(a, b)| a.abs_diff(*b) <= 150));
+    assert!((dim[0] as f32 / 65535.0 - 0.18).abs() < 0.002);
+}
+
+#[test]
+fn auto_exposure_ignores_pixels_that_are_not_finite() {
+    let mut image = vec![vec![Vec3::splat(0.5); 4]; 3];
+    let exposure = ppm::auto_exposure(&image);
+    image[0][0] = Vec3::NAN;
+    image[1][2] = Vec3::splat(f32::INFINITY);
+    image[2][3] = vec3(0.5, f32::NEG_INFINITY, 0.5);
+    assert_eq!(ppm::auto_exposure(&image), exposure);
+}
+
+// A pixel exported linearly with the given clamping, as 16-bit values scaled
+// to one.
+fn clamped(pixel: Vec3, clamp_mode: ClampMode) -> Vec3 {
+    let settings = ExportSettings {
+        clamp_mode,
+        color_space: ColorSpace::Linear,
+        ..ExportSettings::default()
+    };
+    let rgb = ppm::to_rgb16(&[vec![pixel]], &settings);
+    Vec3::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32) / 65535.0
+}
+
+#[test]
+fn luminance_clamping_desaturates_instead_of_clipping() {
+    let orange = vec3(2.0, 0.5, 0.0);
+    let close = |a: Vec3, b: Vec3| (a - b).abs().max_element() < 1e-4;
+    assert!(close(
+        clamped(orange, ClampMode::PerChannel),
+        vec3(1.0, 0.5, 0.0)
+    ));
+
+    // The brightest channel just fits, the luminance is kept, and the hue
+    // stays where it was, with red as far above green as three times green
+    // above blue.
+    let luminance = |pixel: Vec3| pixel.dot(vec3(0.2126, 0.7152, 0.0722));
+    let pixel = clamped(orange, ClampMode::Luminance);
+    assert!((pixel.x - 1.0).abs() < 1e-4, "{}", pixel);
+    assert!(
+        (luminance(pixel) - luminance(orange)).abs() < 1e-4,
+        "{}",
+        pixel
+    );
+    assert!(pixel.z > 0.5, "{}", pixel);
+    let hue = (pixel.x - pixel.y) / (pixel.y - pixel.z);
+    assert!((hue - 3.0).abs() < 0.01, "{}", pixel);
+
+    // Colors that fit are left alone.
+    let fits = vec3(0.9, 0.5, 0.1);
+    assert!(close(clamped(fits, ClampMode::Luminance), fits));
+}
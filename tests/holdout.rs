@@ -0,0 +1,112 @@
+use glam::{const_vec3, vec3, Vec3};
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::renderer::{debug_pixel, render, render_layer, RenderSettings, Scene};
+use raymarching_pathtracer::sdf::*;
+
+const SIZE: i32 = 48;
+const BACKGROUND: Vec3 = const_vec3!([0.2, 0.2, 0.2]);
+
+fn settings() -> RenderSettings {
+    RenderSettings {
+        width: SIZE,
+        height: SIZE,
+        sample_count: 16,
+        ..Default::default()
+    }
+}
+
+// A mirror facing the camera, with a light out of view whose reflection the
+// camera sees. The holdout, if any, sits on the way from the mirror to the
+// light, out of the way of the camera rays to the mirror.
+fn mirror_scene(holdout: bool) -> Scene {
+    let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 1.0, 1.0, 0.0);
+    let mirror = plane(-Vec3::Y)
+        .position(2.0 * Vec3::Y)
+        .material(Material::Specular {
+            color: Vec3::ONE,
+            specularity: 1.0,
+            fuzziness: 0.0,
+        });
+    let light = sphere(0.3)
+        .position(vec3(1.5, -3.5, 0.0))
+        .material(Material::Emissive {
+            color: Vec3::ONE,
+            intensity: 10.0,
+        });
+    let radius = if holdout { 0.35 } else { 0.0 };
+    let holdout = sphere(radius)
+        .position(vec3(1.3, -1.85, 0.0))
+        .material(Material::Holdout);
+
+    let mut scene = Scene::new(camera, mirror.merge(light).merge(holdout));
+    scene.background_color = Box::new(|_| BACKGROUND);
+    scene
+}
+
+fn brightest_pixel(image: &[Vec<Vec3>]) -> (usize, usize) {
+    let mut brightest = (0, 0);
+    for (i, row) in image.iter().enumerate() {
+        for (j, pixel) in row.iter().enumerate() {
+            if pixel.max_element() > image[brightest.0][brightest.1].max_element() {
+                brightest = (i, j);
+            }
+        }
+    }
+    brightest
+}
+
+#[test]
+fn holdouts_block_reflections_but_show_the_background() {
+    let open = render(&settings(), &mirror_scene(false)).unwrap();
+    let (i, j) = brightest_pixel(&open);
+    assert!(open[i][j].min_element() > 1.0, "{}", open[i][j]);
+
+    let scene = mirror_scene(true);
+    let image = render(&settings(), &scene).unwrap();
+    let layer = render_layer(&settings(), &scene).unwrap();
+
+    // The reflection of the light is blocked, and the mirror shows the
+    // background around it.
+    assert!(image[i][j].max_element() <= BACKGROUND.x, "{}", image[i][j]);
+
+    // Where the camera sees the holdout, the image shows the background and
+    // the layer is transparent.
+    let mut holdout_pixels = 0;
+    for (image_row, layer_row) in image.iter().zip(&layer) {
+        for (pixel, layered) in image_row.iter().zip(layer_row) {
+            if layered.w == 0.0 {
+                assert!(
+                    (*pixel - BACKGROUND).abs().max_element() < 1e-6,
+                    "{}",
+                    pixel
+                );
+                assert_eq!(layered.truncate(), Vec3::ZERO);
+                holdout_pixels += 1;
+            }
+        }
+    }
+    assert!(holdout_pixels > 10);
+}
+
+#[test]
+fn camera_rays_hitting_a_holdout_are_traced_and_kept_finite() {
+    let scene = mirror_scene(true);
+    let layer = render_layer(&settings(), &scene).unwrap();
+    let (i, j) = (0..SIZE as usize)
+        .flat_map(|i| (0..SIZE as usize).map(move |j| (i, j)))
+        .find(|&(i, j)| layer[i][j].w == 0.0)
+        .unwrap();
+
+    let (color, trace) = debug_pixel(&scene, &settings(), j as i32, i as i32).unwrap();
+    assert!((color - BACKGROUND).abs().max_element() < 1e-6, "{}", color);
+    assert_eq!(trace.len(), settings().sample_count as usize);
+    assert!(trace
+        .iter()
+        .all(|bounce| bounce.depth == 0 && matches!(bounce.material, Material::Holdout)));
+
+    // A background that comes out NaN there is dropped like any other.
+    let mut scene = scene;
+    scene.background_color = Box::new(|_| Vec3::NAN);
+    let layer = render_layer(&settings(), &scene).unwrap();
+    assert!(layer[i][j].is_finite(), "{}", layer[i][j]);
+}
@@ -0,0 +1,527 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::background;
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::error::RenderError;
+use raymarching_pathtracer::light::Light;
+use raymarching_pathtracer::renderer::{
+    debug_pixel, render, render_preview, RenderSettings, Scene,
+};
+use raymarching_pathtracer::sampling::ThreadRngSampler;
+use raymarching_pathtracer::sdf::*;
+use std::f32::consts::PI;
+
+const WHITE: Material = Material::Lambertian { color: Vec3::ONE };
+
+fn looking_down() -> Camera {
+    Camera::new(vec3(0.0, 0.0, 3.0), Vec3::ZERO, Vec3::Y, 0.5, 1.0, 0.0)
+}
+
+fn total_light(settings: &RenderSettings, scene: &Scene) -> f32 {
+    let image = render(settings, scene).unwrap();
+    image.iter().flatten().sum::<Vec3>().dot(Vec3::ONE)
+}
+
+#[test]
+fn doubling_env_intensity_doubles_sky_lit_diffuse_light() {
+    let mut scene = Scene::new(looking_down(), plane(Vec3::Z).material(WHITE));
+    scene.background_color = Box::new(|_| vec3(0.4, 0.5, 0.6));
+    let settings = RenderSettings {
+        width: 8,
+        height: 8,
+        sample_count: 16,
+        ..Default::default()
+    };
+
+    let single = total_light(&settings, &scene);
+    scene.env_intensity = 2.0;
+    let double = total_light(&settings, &scene);
+
+    assert!(single > 0.0);
+    assert!((double / single - 2.0).abs() < 0.2, "{} {}", single, double);
+}
+
+// The mean and the variance of the brightness of the pixels.
+fn pixel_statistics(settings: &RenderSettings, scene: &Scene) -> (f32, f32) {
+    let image = render(settings, scene).unwrap();
+    let values: Vec<f32> = image
+        .iter()
+        .flatten()
+        .map(|pixel| pixel.dot(Vec3::ONE))
+        .collect();
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32;
+    (mean, variance)
+}
+
+// A small, bright patch of sky that isn't a light is found far more reliably
+// by sampling the background towards it, and combining that with the diffuse
+// bounce by MIS doesn't change what the floor converges to.
+#[test]
+fn background_sampling_keeps_the_mean_and_cuts_the_noise() {
+    let sun = vec3(1.0, 0.0, 1.0).normalize();
+    let mut scene = Scene::new(looking_down(), plane(Vec3::Z).material(WHITE));
+    scene.background_color = background::directional(move |d: Vec3| {
+        if d.dot(sun) > 0.99 {
+            Vec3::splat(50.0)
+        } else {
+            Vec3::splat(0.2)
+        }
+    });
+    let settings = RenderSettings {
+        width: 16,
+        height: 16,
+        sample_count: 64,
+        max_bounces: 1,
+        ..Default::default()
+    };
+
+    // The patch is small enough for its cosine to be taken as constant.
+    let patch = 2.0 * PI * (1.0 - 0.99) * sun.z / PI;
+    let expected = 3.0 * (50.0 * patch + 0.2 * (1.0 - patch));
+
+    let (mean, variance) = pixel_statistics(&settings, &scene);
+    scene.background_sampler = Some(background::cone_sampler(sun, 0.99, 0.5));
+    let (sampled_mean, sampled_variance) = pixel_statistics(&settings, &scene);
+
+    assert!(
+        (mean / expected - 1.0).abs() < 0.15,
+        "{} {}",
+        mean,
+        expected
+    );
+    assert!(
+        (sampled_mean / expected - 1.0).abs() < 0.05,
+        "{} {}",
+        sampled_mean,
+        expected
+    );
+    assert!(
+        sampled_variance < 0.1 * variance,
+        "{} {}",
+        variance,
+        sampled_variance
+    );
+}
+
+// The light seen from a spotlight pointing along -y, viewed from the given
+// angle away from its direction, in the rendered and in the preview image.
+fn spotlight_seen(angle_deg: f32, cos_radius: f32) -> (f32, f32) {
+    let angle = angle_deg.to_radians();
+    let eye = 5.0 * vec3(angle.sin(), -angle.cos(), 0.0);
+    let camera = Camera::new(eye, Vec3::ZERO, Vec3::Z, 0.05, 1.0, 0.0);
+    let spotlight = Material::Spotlight {
+        color: Vec3::ONE,
+        intensity: 2.0,
+        direction: -Vec3::Y,
+        cos_radius,
+    };
+    let scene = Scene::new(camera, sphere(0.2).material(spotlight));
+    let settings = RenderSettings {
+        width: 3,
+        height: 3,
+        sample_count: 64,
+        ..Default::default()
+    };
+
+    let mean = |pixel: Vec3| pixel.dot(Vec3::ONE) / 3.0;
+    let rendered = mean(render(&settings, &scene).unwrap()[1][1]);
+    let preview = mean(render_preview(3, 3, Vec3::ZERO, &scene).unwrap()[1][1]);
+    (rendered, preview)
+}
+
+#[test]
+fn spotlight_falls_off_across_its_cone() {
+    let cos_radius = 40.0f32.to_radians().cos();
+    let seen: Vec<f32> = (0..=10)
+        .map(|i| spotlight_seen(5.0 * i as f32, cos_radius).0)
+        .collect();
+
+    assert!((seen[0] - 2.0).abs() < 0.05, "{:?}", seen);
+    assert!(seen.windows(2).all(|pair| pair[1] <= pair[0]), "{:?}", seen);
+    assert!(seen[4] > 0.2 && seen[4] < 1.8, "{:?}", seen);
+    // The edge of the cone is at 40 degrees, where the pixel only just
+    // reaches inside it.
+    assert!(
+        seen[8] < 0.01 && seen[9..].iter().all(|&light| light == 0.0),
+        "{:?}",
+        seen
+    );
+}
+
+#[test]
+fn spotlight_without_a_cone_is_dark() {
+    for cos_radius in [1.0, 1.5] {
+        for angle in [0.0, 20.0] {
+            assert_eq!(spotlight_seen(angle, cos_radius), (0.0, 0.0));
+        }
+    }
+}
+
+// The light reflected straight up from the white floor below a point light
+// of unit intensity at the given position, with nothing else lighting it.
+fn lit_by_point_light(light_position: Vec3) -> (f32, f32) {
+    let camera = Camera::new(vec3(0.0, 0.0, 3.0), Vec3::ZERO, Vec3::Y, 0.05, 1.0, 0.0);
+    let mut scene = Scene::new(camera, plane(Vec3::Z).material(WHITE));
+    scene.background_color = Box::new(|_| Vec3::ZERO);
+    scene.lights.push(Light::Point {
+        position: light_position,
+        intensity: Vec3::ONE,
+    });
+    let settings = RenderSettings {
+        width: 3,
+        height: 3,
+        sample_count: 4,
+        max_bounces: 0,
+        ..Default::default()
+    };
+
+    let mean = |pixel: Vec3| pixel.dot(Vec3::ONE) / 3.0;
+    let rendered = mean(render(&settings, &scene).unwrap()[1][1]);
+    let preview = mean(render_preview(3, 3, Vec3::ZERO, &scene).unwrap()[1][1]);
+    (rendered, preview)
+}
+
+#[test]
+fn point_light_falls_off_with_the_square_of_the_distance() {
+    let (near, _) = lit_by_point_light(vec3(0.0, 0.0, 1.0));
+    let (far, _) = lit_by_point_light(vec3(0.0, 0.0, 2.0));
+    assert!((near / far - 4.0).abs() < 0.04, "{} {}", near, far);
+
+    // Light arriving at 45 degrees comes from twice as far away squared, and
+    // is spread over a larger area by the cosine.
+    let (slanted, _) = lit_by_point_light(vec3(1.0, 0.0, 1.0));
+    let expected = 2.0 * 2.0f32.sqrt();
+    assert!(
+        (near / slanted - expected).abs() < 0.01 * expected,
+        "{}",
+        slanted
+    );
+
+    // A white floor reflects the irradiance over pi. In spectral mode the
+    // white light is only matched on average over many more samples, but
+    // the pixels see the same wavelengths in each render.
+    if !cfg!(feature = "spectral") {
+        assert!((near - 1.0 / PI).abs() < 0.01 / PI, "{}", near);
+    }
+}
+
+#[test]
+fn point_light_at_the_surface_gives_no_light_and_no_nan() {
+    let light = Light::Point {
+        position: Vec3::ONE,
+        intensity: Vec3::ONE,
+    };
+    let mut sampler = ThreadRngSampler::default();
+    let sample = light.sample(Vec3::ONE, &mut sampler);
+    assert!(sample.direction.is_normalized());
+    assert_eq!(sample.weight, Vec3::ZERO);
+
+    // Close to the light, the distance is clamped instead.
+    let sample = light.sample(Vec3::ONE - 0.001 * Vec3::Z, &mut sampler);
+    assert!((sample.direction - Vec3::Z).length() < 1e-3);
+    assert!(
+        sample.weight.is_finite() && sample.weight.x <= 1e4,
+        "{}",
+        sample.weight
+    );
+
+    // A light lying on the floor leaves it dark in the preview.
+    let (rendered, preview) = lit_by_point_light(Vec3::ZERO);
+    assert!(
+        rendered.is_finite() && preview.is_finite(),
+        "{} {}",
+        rendered,
+        preview
+    );
+}
+
+#[test]
+fn quality_presets_cost_more_for_less_noise() {
+    // A floor lit by a small bright patch of sky, which every pixel sees the
+    // same of, so that all the differences between pixels are noise.
+    let sun = vec3(1.0, 0.0, 1.0).normalize();
+    let mut scene = Scene::new(looking_down(), plane(Vec3::Z).material(WHITE));
+    scene.background_color = background::directional(move |d: Vec3| {
+        if d.dot(sun) > 0.95 {
+            Vec3::splat(10.0)
+        } else {
+            Vec3::splat(0.2)
+        }
+    });
+
+    let presets = [
+        RenderSettings::preview(),
+        RenderSettings::medium(),
+        RenderSettings::high(),
+    ];
+    let noise: Vec<f32> = presets
+        .iter()
+        .map(|preset| {
+            let settings = RenderSettings {
+                width: 8,
+                height: 8,
+                ..*preset
+            };
+            pixel_statistics(&settings, &scene).1
+        })
+        .collect();
+
+    for (better, worse) in presets.iter().skip(1).zip(&presets) {
+        assert!(better.sample_count > worse.sample_count);
+        assert!(better.max_bounces >= worse.max_bounces);
+        assert!(better.step_scale <= worse.step_scale);
+    }
+    assert!(noise[1] < 0.5 * noise[0], "{:?}", noise);
+    assert!(noise[2] < 0.5 * noise[1], "{:?}", noise);
+}
+
+// The inside of a hollow ball of radius 1 with walls of the given albedo,
+// lit by a point light at its center, like an integrating sphere. Every
+// point of the wall is lit alike, directly and by the other walls.
+fn integrating_sphere(albedo: f32) -> Scene {
+    let camera = Camera::new(0.5 * Vec3::X, Vec3::ZERO, Vec3::Z, 1.0, 1.0, 0.0);
+    let walls = sphere(1.5)
+        .subtract(sphere(1.0))
+        .material(Material::Lambertian {
+            color: Vec3::splat(albedo),
+        });
+    let mut scene = Scene::new(camera, walls);
+    scene.lights.push(Light::Point {
+        position: Vec3::ZERO,
+        intensity: Vec3::ONE,
+    });
+    scene
+}
+
+#[test]
+fn dim_paths_end_early_without_darkening_the_image() {
+    let albedo = 0.8;
+    let scene = integrating_sphere(albedo);
+    let settings = RenderSettings {
+        width: 16,
+        height: 16,
+        sample_count: 64,
+        max_bounces: 40,
+        ..Default::default()
+    };
+
+    // The direct light on the wall is reflected over pi, and each bounce
+    // between the walls adds the albedo times the previous one.
+    let direct = albedo / PI;
+    let expected = direct * (1.0 - albedo.powi(settings.max_bounces + 1)) / (1.0 - albedo);
+    let image = render(&settings, &scene).unwrap();
+    let mean = image.iter().flatten().sum::<Vec3>().dot(Vec3::ONE) / (3.0 * 256.0);
+    assert!(
+        (mean / expected - 1.0).abs() < 0.03,
+        "{} {}",
+        mean,
+        expected
+    );
+
+    // Nothing escapes the closed ball, so without roulette every path would
+    // take all 41 hits. With it they take 1 / (1 - albedo) = 5 on average.
+    let (_, trace) = debug_pixel(&scene, &settings, 8, 8).unwrap();
+    let bounces = trace.len() as f32 / settings.sample_count as f32;
+    assert!(bounces < 8.0, "{}", bounces);
+}
+
+#[test]
+fn debugged_pixels_must_be_inside_a_valid_image() {
+    let scene = Scene::new(looking_down(), plane(Vec3::Z).material(WHITE));
+    let settings = RenderSettings {
+        width: 4,
+        height: 2,
+        sample_count: 1,
+        ..Default::default()
+    };
+    assert!(debug_pixel(&scene, &settings, 3, 1).is_ok());
+    for (x, y) in [(4, 0), (0, 2), (-1, 0), (0, -1)] {
+        let result = debug_pixel(&scene, &settings, x, y);
+        assert!(
+            matches!(result, Err(RenderError::InvalidSettings(_))),
+            "({}, {})",
+            x,
+            y
+        );
+    }
+
+    let empty = RenderSettings {
+        sample_count: 0,
+        ..settings
+    };
+    let result = debug_pixel(&scene, &empty, 0, 0);
+    assert!(matches!(result, Err(RenderError::InvalidSettings(_))));
+}
+
+// A white floor lit by a point light off to one side and a sun at an angle,
+// with either light left out.
+fn two_lights_scene(point: bool, sun: bool) -> Scene {
+    let mut scene = Scene::new(looking_down(), plane(Vec3::Z).material(WHITE));
+    scene.background_color = Box::new(|_| Vec3::ZERO);
+    if point {
+        scene.lights.push(Light::Point {
+            position: vec3(0.5, 0.0, 0.3),
+            intensity: Vec3::ONE,
+        });
+    }
+    if sun {
+        scene.lights.push(Light::Directional {
+            direction: vec3(-1.0, 0.0, 1.0).normalize(),
+            cos_radius: 0.999,
+            radiance: Vec3::splat(1000.0),
+        });
+    }
+    scene
+}
+
+#[test]
+fn lights_chosen_by_power_add_up_to_each_light_alone() {
+    let settings = RenderSettings {
+        width: 16,
+        height: 16,
+        sample_count: 128,
+        max_bounces: 0,
+        ..Default::default()
+    };
+    let both = render(&settings, &two_lights_scene(true, true)).unwrap();
+    let point = render(&settings, &two_lights_scene(true, false)).unwrap();
+    let sun = render(&settings, &two_lights_scene(false, true)).unwrap();
+
+    // Each light alone is sampled every time, which is what choosing among
+    // them uniformly or by power must converge to. Compared over the two
+    // halves of the floor, the one nearer the point light and the other.
+    let half = |image: &[Vec<Vec3>], left: bool| -> f32 {
+        image
+            .iter()
+            .flat_map(|row| if left { &row[..8] } else { &row[8..] })
+            .map(|pixel| pixel.dot(Vec3::ONE))
+            .sum()
+    };
+    for left in [true, false] {
+        let alone = half(&point, left) + half(&sun, left);
+        let together = half(&both, left);
+        assert!(
+            (together / alone - 1.0).abs() < 0.02,
+            "{} against {}",
+            together,
+            alone
+        );
+    }
+}
+
+// In spectral mode the light of a single wavelength can come out negative in
+// some color channel, so bounces may take away from a channel.
+#[cfg(not(feature = "spectral"))]
+#[test]
+fn bounced_light_only_adds_to_the_direct_light() {
+    let camera = Camera::new(vec3(0.0, -5.0, 1.0), Vec3::ZERO, Vec3::Z, 0.6, 1.0, 0.0);
+    let floor = plane(Vec3::Z).position(-Vec3::Z).material(WHITE);
+    let ball = sphere(1.0).material(Material::Lambertian {
+        color: vec3(0.8, 0.3, 0.3),
+    });
+    let mut scene = Scene::new(camera, floor.merge(ball));
+    scene.background_color = Box::new(|_| Vec3::splat(0.2));
+    scene.lights = vec![Light::Directional {
+        direction: vec3(-1.0, 0.5, 0.5).normalize(),
+        cos_radius: 0.999,
+        radiance: Vec3::splat(1000.0),
+    }];
+    let full = RenderSettings {
+        width: 32,
+        height: 32,
+        sample_count: 8,
+        ..Default::default()
+    };
+    let direct = RenderSettings {
+        max_bounces: 0,
+        ..full
+    };
+
+    // Paths take the same samples up to their first hit, so each pixel of
+    // the full render is its direct light plus what the bounces bring.
+    let full = render(&full, &scene).unwrap();
+    let direct = render(&direct, &scene).unwrap();
+    let bounced: Vec<Vec3> = full
+        .iter()
+        .flatten()
+        .zip(direct.iter().flatten())
+        .map(|(full, direct)| *full - *direct)
+        .collect();
+    assert!(bounced.iter().all(|light| light.min_element() >= 0.0));
+    let total = bounced.iter().sum::<Vec3>().dot(Vec3::ONE);
+    assert!(total > 0.0);
+}
+
+#[test]
+fn preview_ambient_lifts_every_surface_alike() {
+    let ball = sphere(0.5).position(vec3(0.0, 0.0, 1.0)).material(WHITE);
+    let mut scene = Scene::new(looking_down(), plane(Vec3::Z).material(WHITE).merge(ball));
+    scene.lights = vec![Light::Directional {
+        direction: vec3(1.0, 0.0, 1.0).normalize(),
+        cos_radius: 0.999,
+        radiance: Vec3::ONE,
+    }];
+    let dark = render_preview(32, 32, Vec3::ZERO, &scene).unwrap();
+    let ambient = Vec3::splat(0.2);
+    let lifted = render_preview(32, 32, ambient, &scene).unwrap();
+
+    // On white surfaces the ambient light adds its own radiance, in the
+    // shadow of the ball as much as in full light, so the differences
+    // between lit areas stay as they were.
+    let pixels = || dark.iter().flatten().zip(lifted.iter().flatten());
+    for (dark, lifted) in pixels() {
+        assert!(
+            (*lifted - *dark - ambient).abs().max_element() < 1e-5,
+            "{} {}",
+            dark,
+            lifted
+        );
+    }
+
+    // The view takes in both the shadow and the fully lit floor.
+    let darkest = pixels().map(|(dark, _)| dark.x).fold(f32::MAX, f32::min);
+    let brightest = pixels().map(|(dark, _)| dark.x).fold(0.0, f32::max);
+    assert!(brightest > 3.0 * darkest, "{} {}", darkest, brightest);
+}
+
+#[test]
+fn doubling_emissive_intensity_doubles_the_light() {
+    let glowing = |intensity| {
+        let ball = sphere(0.5)
+            .position(vec3(0.3, 0.0, 0.8))
+            .material(Material::Emissive {
+                color: vec3(1.0, 0.7, 0.4),
+                intensity,
+            });
+        let mut scene = Scene::new(looking_down(), plane(Vec3::Z).material(WHITE).merge(ball));
+        scene.background_color = Box::new(|_| Vec3::ZERO);
+        scene
+    };
+    let settings = RenderSettings {
+        width: 16,
+        height: 16,
+        sample_count: 16,
+        ..Default::default()
+    };
+
+    // With the same samples, both the ball and the floor it lights come out
+    // twice as bright.
+    let single = render(&settings, &glowing(1.5)).unwrap();
+    let double = render(&settings, &glowing(3.0)).unwrap();
+    for (single, double) in single.iter().flatten().zip(double.iter().flatten()) {
+        assert!(
+            (*double - 2.0 * *single).abs().max_element() <= 1e-5 * double.max_element(),
+            "{} {}",
+            single,
+            double
+        );
+    }
+    assert!(
+        single
+            .iter()
+            .flatten()
+            .filter(|pixel| pixel.x > 0.0)
+            .count()
+            > 200
+    );
+}
@@ -0,0 +1,272 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::renderer::{render, RenderSettings, Scene};
+use raymarching_pathtracer::sdf::*;
+use raymarching_pathtracer::union_all;
+
+const BLACK: Material = Material::Lambertian { color: Vec3::ZERO };
+
+// A closed box, 4 units across with walls 0.1 units thick.
+fn closed_box() -> SdfObject<Difference<Cuboid, Cuboid>> {
+    cuboid(Vec3::splat(2.0))
+        .subtract(cuboid(Vec3::splat(1.9)))
+        .material(BLACK)
+}
+
+fn corners() -> impl Iterator<Item = Vec3> {
+    (0..8).map(|i| {
+        let sign = |bit: i32| if i & bit == 0 { -1.0 } else { 1.0 };
+        vec3(sign(1), sign(2), sign(4)).normalize()
+    })
+}
+
+#[test]
+fn rays_into_the_corners_of_a_closed_box_never_escape() {
+    // The floor plane makes the map unbounded, as in most scenes.
+    let bounded = closed_box();
+    let unbounded = closed_box().merge(plane(Vec3::Z).position(-1.9 * Vec3::Z).material(BLACK));
+    let maps: [&dyn SdfMap; 2] = [&bounded, &unbounded];
+
+    for map in maps {
+        for direction in corners() {
+            let march = map.march(vec3(0.1, -0.2, 0.3), direction, RayMask::CAMERA, 1.0);
+            assert!(!matches!(march, March::Escaped));
+        }
+    }
+}
+
+#[test]
+fn rays_into_the_sky_escape() {
+    let ground = plane(Vec3::Z).material(BLACK);
+    for direction in [Vec3::Z, vec3(1.0, 0.0, 0.2), vec3(0.0, 1.0, 0.01)] {
+        let march = ground.march(Vec3::Z, direction.normalize(), RayMask::CAMERA, 1.0);
+        assert!(matches!(march, March::Escaped));
+    }
+}
+
+#[test]
+fn sky_does_not_leak_into_a_closed_box() {
+    let camera = Camera::new(Vec3::ZERO, Vec3::ONE, Vec3::Z, 1.0, 1.0, 0.0);
+    let mut scene = Scene::new(camera, closed_box());
+    scene.background_color = Box::new(|_| Vec3::ONE);
+    let settings = RenderSettings {
+        width: 16,
+        height: 16,
+        sample_count: 4,
+        show_march_failures: true,
+        ..Default::default()
+    };
+
+    let image = render(&settings, &scene).unwrap();
+    assert!(image.iter().flatten().all(|pixel| *pixel == Vec3::ZERO));
+}
+
+// Plain sphere tracing, stepping by the signed distance as the marcher did
+// before it took the absolute value for rays inside objects.
+fn signed_march(map: &dyn SdfMap, origin: Vec3, direction: Vec3) -> Option<Vec3> {
+    let mut acc = 0.0;
+    for _ in 0..1000 {
+        let dist = map.dist(origin + acc * direction, RayMask::CAMERA);
+        acc += dist;
+        if dist < SURFACE_DIST {
+            return Some(origin + acc * direction);
+        } else if acc > 30.0 {
+            return None;
+        }
+    }
+    None
+}
+
+#[test]
+fn rays_outside_objects_march_as_with_signed_distances() {
+    let map = sphere(0.5)
+        .position(vec3(1.0, 0.0, 0.5))
+        .material(BLACK)
+        .merge(
+            torus(0.6, 0.2)
+                .position(vec3(-1.0, 0.5, 0.3))
+                .material(BLACK),
+        )
+        .merge(plane(Vec3::Z).material(BLACK));
+    let origin = vec3(0.2, -4.0, 1.5);
+
+    for i in 0..32 {
+        for j in 0..32 {
+            let target = vec3(-2.0 + 4.0 * i as f32 / 31.0, 0.0, 2.0 * j as f32 / 31.0);
+            let direction = (target - origin).normalize();
+            let hit = map
+                .ray_intersection(origin, direction, RayMask::CAMERA, 1.0)
+                .map(|hit| hit.position);
+            assert_eq!(hit, signed_march(&map, origin, direction));
+        }
+    }
+}
+
+// A fan of rays from the point through a square of the plane y = 0, some of
+// which miss everything around the origin.
+fn fan(origin: Vec3, n: i32) -> impl Iterator<Item = Vec3> {
+    (0..n * n).map(move |k| {
+        let target = vec3(
+            -3.0 + 6.0 * (k % n) as f32 / (n - 1) as f32,
+            0.0,
+            -3.0 + 6.0 * (k / n) as f32 / (n - 1) as f32,
+        );
+        (target - origin).normalize()
+    })
+}
+
+// Eight small objects spread apart, with nothing between them.
+fn sparse_objects() -> impl SdfMap {
+    union_all![
+        sphere(0.3).position(vec3(-2.0, 0.0, -2.0)).material(BLACK),
+        sphere(0.2).position(vec3(1.5, 0.5, -2.0)).material(BLACK),
+        cuboid(Vec3::splat(0.3))
+            .position(vec3(2.0, 0.0, 2.0))
+            .material(BLACK),
+        cuboid(vec3(0.5, 0.2, 0.1))
+            .position(vec3(-1.5, -0.5, 1.5))
+            .material(BLACK),
+        torus(0.4, 0.1)
+            .position(vec3(0.0, 0.0, 2.2))
+            .material(BLACK),
+        torus(0.3, 0.1)
+            .position(vec3(0.0, 0.5, -2.2))
+            .material(BLACK),
+        sphere(0.25).position(vec3(-2.3, 0.0, 0.3)).material(BLACK),
+        sphere(0.4).material(BLACK)
+    ]
+}
+
+#[test]
+fn bounded_marching_hits_where_brute_force_does() {
+    // The same rays march from where they enter the bounds, so they take
+    // other steps, and stop within SURFACE_DIST of where brute force does.
+    let map = sparse_objects();
+    assert!(map.bounds().is_some());
+    let origin = vec3(0.3, -6.0, 0.4);
+    let mut hits = 0;
+    for direction in fan(origin, 48) {
+        let hit = map.ray_intersection(origin, direction, RayMask::CAMERA, 1.0);
+        match (hit, signed_march(&map, origin, direction)) {
+            (Some(hit), Some(expected)) => {
+                assert!(
+                    hit.position.distance(expected) < SURFACE_DIST,
+                    "{}",
+                    hit.position
+                );
+                hits += 1;
+            }
+            (None, None) => (),
+            (hit, expected) => panic!(
+                "{:?} against {:?} along {}",
+                hit.map(|hit| hit.position),
+                expected,
+                direction
+            ),
+        }
+    }
+    assert!(hits > 100);
+}
+
+// The map without the test of a ray against the bounds of each object, which
+// marches every ray through the bounds of the whole map.
+struct Unculled<M>(M);
+
+impl<M: SdfMap> SdfMap for Unculled<M> {
+    fn dist(&self, p: Vec3, mask: RayMask) -> f32 {
+        self.0.dist(p, mask)
+    }
+
+    fn dist4(&self, p: Vec3x4, mask: RayMask) -> glam::Vec4 {
+        self.0.dist4(p, mask)
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.0.bounds()
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        self.0.distinfo(p, mask)
+    }
+}
+
+#[test]
+fn rays_missing_every_object_are_skipped_without_changing_hits() {
+    // Rays that can hit an object take exactly the same steps as without
+    // culling the others.
+    let map = sparse_objects();
+    let unculled = Unculled(sparse_objects());
+    let origin = vec3(0.3, -6.0, 0.4);
+    let directions: Vec<Vec3> = fan(origin, 48).collect();
+    let position = |hit: Option<HitInfo>| hit.map(|hit| hit.position);
+
+    let mut skipped = 0;
+    for &direction in &directions {
+        let hit = map.ray_intersection(origin, direction, RayMask::CAMERA, 1.0);
+        let expected = unculled.ray_intersection(origin, direction, RayMask::CAMERA, 1.0);
+        assert_eq!(position(hit), position(expected));
+        if !map.may_hit(origin, direction) {
+            skipped += 1;
+        }
+    }
+    assert!(skipped > directions.len() / 2, "{}", skipped);
+
+    for chunk in directions.chunks_exact(4) {
+        let directions = [chunk[0], chunk[1], chunk[2], chunk[3]];
+        let hits = map.ray_intersection4([origin; 4], directions, RayMask::CAMERA);
+        let expected = unculled.ray_intersection4([origin; 4], directions, RayMask::CAMERA);
+        assert_eq!(hits.map(position), expected.map(position));
+    }
+}
+
+// A box twisted about the z axis by `rate` radians per unit of height. Its
+// field overestimates the distance, the more the faster it twists.
+#[derive(Clone)]
+struct TwistedBox {
+    half_extent: Vec3,
+    rate: f32,
+}
+
+impl Sdf for TwistedBox {
+    fn dist(&self, p: Vec3) -> f32 {
+        let (sin, cos) = (self.rate * p.z).sin_cos();
+        let p = vec3(cos * p.x - sin * p.y, sin * p.x + cos * p.y, p.z);
+        let q = p.abs() - self.half_extent;
+        q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+    }
+}
+
+// Rows of the column of pixels through the axis of the box, which the box
+// covers all the way down, where the background shows through.
+fn holes_in_twisted_box(step_scale: f32) -> usize {
+    let size = 32;
+    let camera = Camera::new(vec3(0.0, -6.0, 0.0), Vec3::ZERO, Vec3::Z, 0.6, 1.0, 0.0);
+    let twisted = TwistedBox {
+        half_extent: vec3(1.0, 0.15, 2.5),
+        rate: 4.0,
+    };
+    let light = Material::Emissive {
+        color: Vec3::ONE,
+        intensity: 1.0,
+    };
+    let mut scene = Scene::new(camera, twisted.material(light));
+    scene.background_color = Box::new(|_| Vec3::ZERO);
+    let settings = RenderSettings {
+        width: size,
+        height: size,
+        sample_count: 1,
+        step_scale,
+        ..Default::default()
+    };
+
+    let image = render(&settings, &scene).unwrap();
+    let column = size as usize / 2;
+    image.iter().filter(|row| row[column] == Vec3::ZERO).count()
+}
+
+#[test]
+fn smaller_steps_close_holes_in_a_twisted_box() {
+    let holes = holes_in_twisted_box(1.0);
+    assert!(holes > 0);
+    assert_eq!(holes_in_twisted_box(0.5), 0);
+}
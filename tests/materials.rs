@@ -0,0 +1,471 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::background;
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::light::Light;
+use raymarching_pathtracer::renderer::{
+    debug_pixel, render, render_preview, RenderSettings, Scene,
+};
+use raymarching_pathtracer::sdf::*;
+use raymarching_pathtracer::texture::{Image, Projection};
+use std::sync::Arc;
+
+const SIZE: i32 = 64;
+
+fn settings(sample_count: i32) -> RenderSettings {
+    RenderSettings {
+        width: SIZE,
+        height: SIZE,
+        sample_count,
+        ..Default::default()
+    }
+}
+
+fn phong(shininess: f32) -> Material {
+    Material::Phong {
+        diffuse: Vec3::ZERO,
+        specular: Vec3::ONE,
+        shininess,
+    }
+}
+
+// A unit sphere at the origin, seen from the given position under a narrow
+// light from the given direction.
+fn highlight_scene(eye: Vec3, light: Vec3, shininess: f32) -> Scene {
+    let camera = Camera::new(eye, Vec3::ZERO, Vec3::Z, 0.5, 1.0, 0.0);
+    let mut scene = Scene::new(camera, sphere(1.0).material(phong(shininess)));
+    scene.lights = vec![Light::Directional {
+        direction: light.normalize(),
+        cos_radius: 0.9999,
+        radiance: Vec3::splat(1000.0),
+    }];
+    scene
+}
+
+fn brightness(scene: &Scene, sample_count: i32) -> Vec<Vec<f32>> {
+    let image = render(&settings(sample_count), scene).unwrap();
+    image
+        .iter()
+        .map(|row| row.iter().map(|pixel| pixel.dot(Vec3::ONE)).collect())
+        .collect()
+}
+
+fn brightest_pixel(image: &[Vec<f32>]) -> (f32, f32) {
+    let mut brightest = (0, 0);
+    for (i, row) in image.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            if *value > image[brightest.0][brightest.1] {
+                brightest = (i, j);
+            }
+        }
+    }
+    (brightest.0 as f32, brightest.1 as f32)
+}
+
+// Where the highlight should peak: the point of the sphere whose normal is
+// the half vector between the light and the eye, projected to the image.
+fn expected_highlight(scene: &Scene, eye: Vec3, light: Vec3) -> (f32, f32) {
+    let mut normal = light.normalize();
+    for _ in 0..20 {
+        normal = (light.normalize() + (eye - normal).normalize()).normalize();
+    }
+    let (x, y) = scene.camera.project(normal).unwrap();
+    let pixel = |t: f32| t * SIZE as f32 - 0.5;
+    (pixel(0.5 - y), pixel(x + 0.5))
+}
+
+#[test]
+fn phong_highlight_follows_the_light_and_the_eye() {
+    let views = [
+        (vec3(0.0, -5.0, 0.0), vec3(0.0, -1.0, 0.0)),
+        (vec3(0.0, -5.0, 0.0), vec3(0.6, -1.0, 0.4)),
+        (vec3(1.5, -5.0, 1.0), vec3(0.6, -1.0, 0.4)),
+    ];
+
+    for (eye, light) in views {
+        let scene = highlight_scene(eye, light, 200.0);
+        let (i, j) = brightest_pixel(&brightness(&scene, 4));
+        let (expected_i, expected_j) = expected_highlight(&scene, eye, light);
+        assert!(
+            (i - expected_i).abs() <= 1.5 && (j - expected_j).abs() <= 1.5,
+            "({}, {}) against ({}, {})",
+            i,
+            j,
+            expected_i,
+            expected_j
+        );
+    }
+}
+
+#[test]
+fn phong_highlight_tightens_with_shininess() {
+    let eye = vec3(0.0, -5.0, 0.0);
+    let light = vec3(0.6, -1.0, 0.4);
+    let areas: Vec<usize> = [10.0, 50.0, 200.0]
+        .iter()
+        .map(|&shininess| {
+            let image = brightness(&highlight_scene(eye, light, shininess), 4);
+            let peak = image.iter().flatten().fold(0.0f32, |a, &b| a.max(b));
+            image.iter().flatten().filter(|&&v| v > 0.5 * peak).count()
+        })
+        .collect();
+
+    assert!(
+        areas[0] > 2 * areas[1] && areas[1] > 2 * areas[2],
+        "{:?}",
+        areas
+    );
+}
+
+// The highlight is only lit by direct samples, so light from a sampled patch
+// of background must reach it as fully as from a light covering the patch.
+#[test]
+fn phong_highlight_is_lit_fully_by_the_sampled_background() {
+    let eye = vec3(0.0, -5.0, 0.0);
+    let direction = vec3(0.6, -1.0, 0.4).normalize();
+    let total = |scene: &Scene| brightness(scene, 64).iter().flatten().sum::<f32>();
+
+    let mut lit = highlight_scene(eye, direction, 10.0);
+    lit.lights = vec![Light::Directional {
+        direction,
+        cos_radius: 0.8,
+        radiance: Vec3::ONE,
+    }];
+
+    let mut sampled = highlight_scene(eye, direction, 10.0);
+    sampled.lights = vec![];
+    sampled.background_color = background::directional(move |d: Vec3| {
+        if d.dot(direction) > 0.8 {
+            Vec3::ONE
+        } else {
+            Vec3::ZERO
+        }
+    });
+    sampled.background_sampler = Some(background::cone_sampler(direction, 0.8, 0.5));
+
+    let (lit, sampled) = (total(&lit), total(&sampled));
+    assert!((sampled / lit - 1.0).abs() < 0.05, "{} {}", lit, sampled);
+}
+
+#[test]
+fn bump_height_can_capture_its_parameters() {
+    for slope in [0.0, 0.5, 2.0] {
+        let height = Height::new(move |p: Vec3| slope * p.x);
+        let normal = bumped_normal(Vec3::Z, vec3(0.3, -0.2, 0.0), &height);
+        let expected = vec3(-slope, 0.0, 1.0).normalize();
+        assert!(
+            (normal - expected).length() < 1e-3,
+            "slope {}: {} instead of {}",
+            slope,
+            normal,
+            expected
+        );
+    }
+}
+
+#[test]
+fn textured_materials_release_their_image_with_the_scene() {
+    let image = Arc::new(Image::from_fn(2, 2, |_, _| vec3(0.8, 0.4, 0.2)));
+    let textured = Material::ImageTexture {
+        image: image.clone(),
+        projection: Projection::Triplanar { scale: 1.0 },
+    };
+    let bumped = Material::Bumped {
+        base: Arc::new(textured),
+        height: Height::new(|_| 0.0),
+    };
+
+    let camera = Camera::new(vec3(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y, 0.5, 1.0, 0.0);
+    let scene = Scene::new(camera, sphere(1.0).material(bumped));
+    let center = render_preview(8, 8, Vec3::ONE, &scene).unwrap()[4][4];
+    assert!((center.x / center.y - 2.0).abs() < 0.01, "{}", center);
+    assert!((center.y / center.z - 2.0).abs() < 0.01, "{}", center);
+    assert!(Arc::strong_count(&image) > 1);
+
+    drop(scene);
+    assert_eq!(Arc::strong_count(&image), 1);
+}
+
+#[test]
+fn procedural_materials_resolve_to_plain_surfaces() {
+    let checker = Material::Checker {
+        color1: Vec3::ZERO,
+        color2: Vec3::ONE,
+        scale: 1.0,
+    };
+    let bumped = Material::Bumped {
+        base: Arc::new(checker),
+        height: Height::new(|p| 0.1 * p.x.sin()),
+    };
+
+    // Far from the cell boundaries the footprint covers a single color,
+    // and a wide footprint averages the two.
+    for (position, footprint, expected) in [
+        (vec3(0.2, 0.2, 0.2), 0.01, 0.0),
+        (vec3(1.2, 0.2, 0.2), 0.01, 1.0),
+        (vec3(0.2, 0.2, 0.2), 100.0, 0.5),
+    ] {
+        match bumped.resolve(position, Vec3::Z, footprint) {
+            Surface::Lambertian { color } => {
+                assert!(
+                    (color - Vec3::splat(expected)).abs().max_element() < 0.02,
+                    "{}",
+                    color
+                )
+            }
+            surface => panic!("{:?} instead of a Lambertian surface", surface),
+        }
+    }
+}
+
+// Refracts the unit direction through a surface with the unit normal facing
+// it, where eta is the ratio of the indices of refraction before and after.
+// None when the light is totally reflected.
+fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let cos = -direction.dot(normal);
+    let k = 1.0 - eta * eta * (1.0 - cos * cos);
+    (k >= 0.0).then(|| eta * direction + (eta * cos - k.sqrt()) * normal)
+}
+
+// Where the ray leaves a sphere about the origin that it starts in, or
+// enters it from outside.
+fn sphere_exit(origin: Vec3, direction: Vec3, radius: f32) -> Vec3 {
+    let b = origin.dot(direction);
+    let c = origin.length_squared() - radius * radius;
+    origin + (-b + (b * b - c).sqrt()) * direction
+}
+
+fn sphere_entry(origin: Vec3, direction: Vec3, radius: f32) -> Option<Vec3> {
+    let b = origin.dot(direction);
+    let c = origin.length_squared() - radius * radius;
+    let t = -b - (b * b - c).sqrt();
+    (t > 0.0).then(|| origin + t * direction)
+}
+
+#[test]
+fn light_bends_through_a_bubble_in_glass_as_snell_says() {
+    let (radius, bubble_radius, ior) = (1.0, 0.5, 1.5);
+    let glass = Material::Dielectric {
+        color: Vec3::ONE,
+        ior,
+        dispersion: 0.0,
+    };
+    let eye = vec3(0.0, -4.0, 0.0);
+    let camera = Camera::new(eye, Vec3::ZERO, Vec3::Z, 0.5, 1.0, 0.0);
+    let ball = sphere(radius)
+        .subtract(sphere(bubble_radius))
+        .material(glass);
+    let scene = Scene::new(camera, ball);
+    let settings = RenderSettings {
+        width: 16,
+        height: 16,
+        sample_count: 64,
+        max_bounces: 8,
+        ..Default::default()
+    };
+
+    // The paths that refract into the glass, into the bubble, out of it and
+    // out of the glass, as told by the spheres their first hits are on.
+    let (_, trace) = debug_pixel(&scene, &settings, 10, 8).unwrap();
+    let paths: Vec<_> = trace
+        .split(|bounce| bounce.depth == 0)
+        .skip(1)
+        .zip(trace.iter().filter(|bounce| bounce.depth == 0))
+        .map(|(rest, first)| {
+            let mut path = vec![first.position];
+            path.extend(rest.iter().map(|bounce| bounce.position));
+            path
+        })
+        .filter(|path| path.len() >= 4)
+        .filter(|path| {
+            let radii = [radius, bubble_radius, bubble_radius, radius];
+            path.iter()
+                .zip(radii)
+                .all(|(p, r)| (p.length() - r).abs() < 0.01)
+        })
+        .collect();
+    assert!(paths.len() > 16, "{}", paths.len());
+
+    for path in paths {
+        let hit = path[0];
+        let into_glass = refract((hit - eye).normalize(), hit / radius, 1.0 / ior).unwrap();
+        let at_bubble = sphere_entry(hit, into_glass, bubble_radius).unwrap();
+        let into_bubble = refract(into_glass, at_bubble / bubble_radius, ior).unwrap();
+        let out_of_bubble = sphere_exit(at_bubble, into_bubble, bubble_radius);
+        let back_in_glass =
+            refract(into_bubble, -out_of_bubble / bubble_radius, 1.0 / ior).unwrap();
+        let out_of_glass = sphere_exit(out_of_bubble, back_in_glass, radius);
+
+        for (traced, expected) in path[1..]
+            .iter()
+            .zip([at_bubble, out_of_bubble, out_of_glass])
+        {
+            assert!(
+                traced.distance(expected) < 0.01,
+                "{} instead of {}",
+                traced,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn plastic_reflects_more_at_grazing_angles() {
+    // Black plastic in a white environment shows only its coat.
+    let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 0.5, 1.0, 0.0);
+    let plastic = Material::Plastic {
+        color: Vec3::ZERO,
+        ior: 1.5,
+    };
+    let mut scene = Scene::new(camera, sphere(1.0).material(plastic));
+    scene.background_color = Box::new(|_| Vec3::ONE);
+    let image = render(&settings(256), &scene).unwrap();
+    // The coat reflects the white environment, so the brightness is the
+    // share reflected, averaged here over rings of the disk the sphere covers.
+    let half = SIZE as f32 / 2.0;
+    let radius = image[SIZE as usize / 2]
+        .iter()
+        .filter(|pixel| pixel.x < 0.5)
+        .count() as f32
+        / 2.0;
+    let ring_mean = |from: f32, to: f32| {
+        let mut sum = 0.0;
+        let mut count = 0;
+        for (i, row) in image.iter().enumerate() {
+            for (j, pixel) in row.iter().enumerate() {
+                let (x, y) = (j as f32 + 0.5 - half, i as f32 + 0.5 - half);
+                let r = (x * x + y * y).sqrt() / radius;
+                if r >= from && r < to {
+                    sum += pixel.x;
+                    count += 1;
+                }
+            }
+        }
+        sum / count as f32
+    };
+
+    // Head-on the coat reflects ((ior - 1) / (ior + 1))^2 = 0.04.
+    let head_on = ring_mean(0.0, 0.3);
+    let grazing = ring_mean(0.85, 0.95);
+    assert!((head_on - 0.04).abs() < 0.01, "{}", head_on);
+    assert!(grazing > 2.0 * head_on, "{} against {}", grazing, head_on);
+}
+
+// A ball on a floor in the given materials, under a low sun and a dim sky.
+fn ball_on_floor(ball: Material, floor: Material) -> Scene {
+    let camera = Camera::new(vec3(0.0, -5.0, 1.0), Vec3::ZERO, Vec3::Z, 0.6, 1.0, 0.0);
+    let floor = plane(Vec3::Z).position(-Vec3::Z).material(floor);
+    let ball = sphere(1.0).material(ball);
+    let mut scene = Scene::new(camera, floor.merge(ball));
+    scene.background_color = Box::new(|_| Vec3::splat(0.2));
+    scene.lights = vec![Light::Directional {
+        direction: vec3(-1.0, 0.5, 0.5).normalize(),
+        cos_radius: 0.999,
+        radiance: Vec3::splat(1000.0),
+    }];
+    scene
+}
+
+#[test]
+fn smooth_oren_nayar_is_lambertian() {
+    let color = vec3(0.8, 0.6, 0.4);
+    let oren_nayar = |roughness| {
+        let material = Material::OrenNayar { color, roughness };
+        let scene = ball_on_floor(material.clone(), material);
+        render(&settings(4), &scene).unwrap()
+    };
+    let lambertian = Material::Lambertian { color };
+    let lambertian = render(&settings(4), &ball_on_floor(lambertian.clone(), lambertian)).unwrap();
+    let difference = |image: &[Vec<Vec3>]| {
+        image
+            .iter()
+            .flatten()
+            .zip(lambertian.iter().flatten())
+            .map(|(a, b)| (*a - *b).abs().max_element() / b.max_element().max(0.1))
+            .fold(0.0, f32::max)
+    };
+
+    // With the same samples, the smooth surface matches pixel for pixel,
+    // while a rough one reflects visibly less of the sun towards the camera.
+    let smooth = difference(&oren_nayar(0.0));
+    assert!(smooth < 1e-4, "{}", smooth);
+    let total = |image: &[Vec<Vec3>]| image.iter().flatten().sum::<Vec3>().dot(Vec3::ONE);
+    let rough = total(&oren_nayar(0.5)) / total(&lambertian);
+    assert!(rough < 0.95, "{}", rough);
+}
+
+#[test]
+fn clay_renders_differently_colored_scenes_alike() {
+    let clay = RenderSettings {
+        clay: true,
+        ..settings(4)
+    };
+    let red_on_blue = ball_on_floor(
+        Material::Lambertian {
+            color: vec3(0.9, 0.1, 0.1),
+        },
+        Material::OrenNayar {
+            color: vec3(0.1, 0.1, 0.9),
+            roughness: 0.3,
+        },
+    );
+    let mirror_on_green = ball_on_floor(
+        Material::Specular {
+            color: Vec3::ONE,
+            specularity: 1.0,
+            fuzziness: 0.0,
+        },
+        Material::Lambertian {
+            color: vec3(0.2, 0.8, 0.2),
+        },
+    );
+
+    let image = render(&clay, &red_on_blue).unwrap();
+    assert_eq!(render(&clay, &mirror_on_green).unwrap(), image);
+    assert!(image
+        .iter()
+        .flatten()
+        .any(|pixel| pixel.min_element() > 0.0));
+    // In color they differ.
+    assert_ne!(
+        render(&settings(4), &red_on_blue).unwrap(),
+        render(&settings(4), &mirror_on_green).unwrap()
+    );
+}
+
+#[test]
+fn plain_white_emissive_textures_glow_like_emissive_materials() {
+    let white = Arc::new(Image::from_fn(1, 1, |_, _| Vec3::ONE));
+    let scene = |glow: Material| {
+        let camera = Camera::new(vec3(0.0, -5.0, 1.0), Vec3::ZERO, Vec3::Z, 0.6, 1.0, 0.0);
+        let floor = plane(Vec3::Z)
+            .position(-Vec3::Z)
+            .material(Material::Lambertian {
+                color: Vec3::splat(0.8),
+            });
+        Scene::new(camera, floor.merge(sphere(0.7).material(glow)))
+    };
+    let textured = scene(Material::EmissiveTexture {
+        image: white,
+        projection: Projection::Spherical { center: Vec3::ZERO },
+        intensity: 3.0,
+    });
+    let plain = scene(Material::Emissive {
+        color: Vec3::ONE,
+        intensity: 3.0,
+    });
+
+    // Both the ball and the floor it lights come out the same.
+    let image = render(&settings(8), &plain).unwrap();
+    assert_eq!(render(&settings(8), &textured).unwrap(), image);
+    assert!(image[SIZE as usize - 1].iter().any(|pixel| pixel.x > 0.0));
+    // In spectral mode the white is only matched on average.
+    if !cfg!(feature = "spectral") {
+        let center = image[SIZE as usize / 2][SIZE as usize / 2];
+        assert!(
+            (center - Vec3::splat(3.0)).abs().max_element() < 1e-4,
+            "{}",
+            center
+        );
+    }
+}
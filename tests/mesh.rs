@@ -0,0 +1,150 @@
+use glam::Vec3;
+use raymarching_pathtracer::error::RenderError;
+use raymarching_pathtracer::mesh;
+use raymarching_pathtracer::sdf::*;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+const WHITE: Material = Material::Lambertian { color: Vec3::ONE };
+const BOX: (Vec3, Vec3) = (
+    glam::const_vec3!([-1.5, -1.5, -1.5]),
+    glam::const_vec3!([1.5, 1.5, 1.5]),
+);
+
+// Checks that every edge of the mesh is shared by exactly two triangles
+// that run along it in opposite directions, so the mesh is closed and
+// consistently wound. Returns the number of edges.
+fn assert_watertight(triangles: &[[u32; 3]]) -> usize {
+    let mut edges: HashMap<(u32, u32), i32> = HashMap::new();
+    for &[a, b, c] in triangles {
+        for (from, to) in [(a, b), (b, c), (c, a)] {
+            assert_ne!(from, to);
+            *edges.entry((from, to)).or_default() += 1;
+        }
+    }
+    for (&(from, to), &count) in &edges {
+        assert_eq!(count, 1, "edge {}-{} is used {} times", from, to, count);
+        assert_eq!(
+            edges.get(&(to, from)),
+            Some(&1),
+            "edge {}-{} is open",
+            from,
+            to
+        );
+    }
+    edges.len() / 2
+}
+
+fn area(vertices: &[Vec3], triangles: &[[u32; 3]]) -> f32 {
+    triangles
+        .iter()
+        .map(|t| t.map(|i| vertices[i as usize]))
+        .map(|[a, b, c]| 0.5 * (b - a).cross(c - a).length())
+        .sum()
+}
+
+// Volume enclosed by the mesh, positive when its triangles wind
+// counterclockwise as seen from outside.
+fn volume(vertices: &[Vec3], triangles: &[[u32; 3]]) -> f32 {
+    triangles
+        .iter()
+        .map(|t| t.map(|i| vertices[i as usize]))
+        .map(|[a, b, c]| a.dot(b.cross(c)) / 6.0)
+        .sum()
+}
+
+#[test]
+fn sphere_mesh_is_closed_with_the_area_of_the_sphere() {
+    let map = sphere(1.0).material(WHITE);
+    let (vertices, triangles) = mesh::extract(&map, BOX, [40, 40, 40]);
+
+    let edges = assert_watertight(&triangles);
+    // A surface with no holes through it, like a sphere, has Euler
+    // characteristic two.
+    assert_eq!(
+        vertices.len() as i64 - edges as i64 + triangles.len() as i64,
+        2
+    );
+
+    let area = area(&vertices, &triangles);
+    assert!((area / (4.0 * PI) - 1.0).abs() < 0.01, "{}", area);
+    let volume = volume(&vertices, &triangles);
+    assert!((volume / (4.0 / 3.0 * PI) - 1.0).abs() < 0.01, "{}", volume);
+    for v in &vertices {
+        assert!((v.length() - 1.0).abs() < 0.01, "{}", v);
+    }
+}
+
+#[test]
+fn torus_mesh_is_closed_with_one_hole() {
+    let (radius1, radius2) = (1.0, 0.3);
+    let map = torus(radius1, radius2).material(WHITE);
+    let (vertices, triangles) = mesh::extract(&map, BOX, [50, 50, 50]);
+
+    let edges = assert_watertight(&triangles);
+    assert_eq!(
+        vertices.len() as i64 - edges as i64 + triangles.len() as i64,
+        0
+    );
+
+    let expected = 4.0 * PI * PI * radius1 * radius2;
+    let area = area(&vertices, &triangles);
+    assert!((area / expected - 1.0).abs() < 0.02, "{}", area);
+}
+
+// Reads back the vertices, normals and faces of an OBJ file as written by
+// export_obj, with the indices of the faces starting from zero.
+fn read_obj(path: &str) -> (Vec<Vec3>, Vec<Vec3>, Vec<[u32; 3]>) {
+    let text = std::fs::read_to_string(path).unwrap();
+    let (mut vertices, mut normals, mut faces) = (Vec::new(), Vec::new(), Vec::new());
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let kind = words.next().unwrap();
+        let words: Vec<&str> = words.collect();
+        let vector = || {
+            let [x, y, z] = [0, 1, 2].map(|i| words[i].parse::<f32>().unwrap());
+            Vec3::new(x, y, z)
+        };
+        match kind {
+            "v" => vertices.push(vector()),
+            "vn" => normals.push(vector()),
+            "f" => faces.push([0, 1, 2].map(|i| {
+                // Faces with normals refer to the normal of the same index.
+                let (vertex, normal) = words[i].split_once("//").unwrap_or((words[i], words[i]));
+                assert_eq!(vertex, normal);
+                vertex.parse::<u32>().unwrap() - 1
+            })),
+            _ => panic!("unexpected line {:?}", line),
+        }
+    }
+    (vertices, normals, faces)
+}
+
+#[test]
+fn obj_files_read_back_as_written() {
+    let map = torus(1.0, 0.3).material(WHITE);
+    let (vertices, triangles) = mesh::extract(&map, BOX, [20, 20, 20]);
+    let normals = mesh::vertex_normals(&map, &vertices);
+    let path = format!("{}/torus.obj", env!("CARGO_TARGET_TMPDIR"));
+
+    mesh::export_obj(&path, &vertices, &triangles, Some(&normals)).unwrap();
+    assert_eq!(
+        read_obj(&path),
+        (vertices.clone(), normals, triangles.clone())
+    );
+
+    let path = format!("{}/torus_flat.obj", env!("CARGO_TARGET_TMPDIR"));
+    mesh::export_obj(&path, &vertices, &triangles, None).unwrap();
+    assert_eq!(read_obj(&path), (vertices, Vec::new(), triangles));
+}
+
+#[test]
+fn obj_export_needs_a_normal_for_each_vertex() {
+    let map = sphere(1.0).material(WHITE);
+    let (vertices, triangles) = mesh::extract(&map, BOX, [8, 8, 8]);
+    let normals = mesh::vertex_normals(&map, &vertices[1..]);
+    let path = format!("{}/sphere.obj", env!("CARGO_TARGET_TMPDIR"));
+
+    let result = mesh::export_obj(&path, &vertices, &triangles, Some(&normals));
+    assert!(matches!(result, Err(RenderError::InvalidSettings(_))));
+}
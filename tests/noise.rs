@@ -0,0 +1,87 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::noise::{fbm, fbm_lipschitz, value_noise};
+use raymarching_pathtracer::sdf::*;
+
+// Points spread irregularly through a cube four units across.
+fn points(n: usize) -> impl Iterator<Item = Vec3> {
+    let fraction = |x: f32| x - x.floor();
+    (0..n).map(move |i| {
+        let i = i as f32;
+        4.0 * vec3(
+            fraction(0.618_034 * i),
+            fraction(0.754_877_7 * i),
+            fraction(0.569_840_3 * i),
+        ) - Vec3::splat(2.0)
+    })
+}
+
+#[test]
+fn noise_is_deterministic_and_seeded() {
+    for p in points(1000) {
+        let value = value_noise(p, 7);
+        assert!((-1.0..=1.0).contains(&value));
+        assert_eq!(value, value_noise(p, 7));
+        let fractal = fbm(p, 5, 7);
+        assert!((-1.0..=1.0).contains(&fractal));
+        assert_eq!(fractal, fbm(p, 5, 7));
+    }
+
+    let differing = points(1000)
+        .filter(|&p| fbm(p, 5, 7) != fbm(p, 5, 8))
+        .count();
+    assert!(differing > 990, "{}", differing);
+}
+
+#[test]
+fn fbm_changes_no_faster_than_its_bound() {
+    let h = 1e-3;
+    for octaves in [1, 3, 5] {
+        let bound = fbm_lipschitz(octaves);
+        for p in points(2000) {
+            for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+                let slope = (fbm(p + h * axis, octaves, 3) - fbm(p, octaves, 3)).abs() / h;
+                assert!(slope <= bound, "{} > {} at {}", slope, bound, p);
+            }
+        }
+    }
+}
+
+#[test]
+fn asteroid_is_a_roughened_sphere_that_marches_safely() {
+    let (radius, amplitude) = (1.0, 0.3);
+    let asteroid = sphere(radius)
+        .displace_noise(amplitude, 1.2, 5, 1)
+        .material(Material::Lambertian { color: Vec3::ONE });
+
+    // Rays from all around hit within the amplitude of the sphere, at
+    // different heights.
+    let hits: Vec<f32> = points(200)
+        .filter_map(|p| p.try_normalize())
+        .map(|direction| {
+            let hit = asteroid
+                .ray_intersection(5.0 * direction, -direction, RayMask::CAMERA, 1.0)
+                .unwrap();
+            hit.position.length()
+        })
+        .collect();
+    let (lowest, highest) = hits.iter().fold((f32::INFINITY, 0.0f32), |(lo, hi), &r| {
+        (lo.min(r), hi.max(r))
+    });
+    assert!(lowest >= radius - amplitude - 0.01, "{}", lowest);
+    assert!(highest <= radius + amplitude + 0.01, "{}", highest);
+    assert!(highest - lowest > 0.2, "{} {}", lowest, highest);
+
+    // The scaled field never claims more room than there is, so steps by it
+    // don't pass through the surface.
+    let points: Vec<Vec3> = points(2000).collect();
+    for pair in points.windows(2) {
+        let (p, q) = (0.5 * pair[0], 0.5 * pair[0] + 0.05 * (pair[1] - pair[0]));
+        let change = (asteroid.dist(p, RayMask::CAMERA) - asteroid.dist(q, RayMask::CAMERA)).abs();
+        assert!(
+            change <= p.distance(q) * 1.0001,
+            "{} over {}",
+            change,
+            p.distance(q)
+        );
+    }
+}
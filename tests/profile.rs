@@ -0,0 +1,103 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::profile::Report;
+use raymarching_pathtracer::sdf::*;
+
+const WHITE: Material = Material::Lambertian { color: Vec3::ONE };
+
+// A profiled ball resting on a profiled floor, with the ball moved and
+// rotated so that the names have to be found through the transforms.
+fn ball_on_floor() -> impl SdfMap {
+    let floor = plane(Vec3::Z).material(WHITE).profile("floor");
+    let ball = sphere(1.0)
+        .material(WHITE)
+        .profile("ball")
+        .rotate(Vec3::X, 0.3)
+        .position(vec3(0.0, 0.0, 1.0));
+    floor.merge(ball)
+}
+
+#[test]
+fn profiling_takes_the_same_steps() {
+    let map = ball_on_floor();
+    for direction in [
+        vec3(0.0, 1.0, -0.2),
+        vec3(0.3, 1.0, -0.6),
+        vec3(0.0, 1.0, 0.5),
+    ] {
+        let origin = vec3(0.0, -5.0, 1.5);
+        let direction = direction.normalize();
+        let hit = map.ray_intersection(origin, direction, RayMask::CAMERA, 1.0);
+        let (profiled, _) = map.ray_intersection_profiled(origin, direction, RayMask::CAMERA, 1.0);
+        assert_eq!(
+            hit.map(|hit| hit.position),
+            profiled.map(|hit| hit.position)
+        );
+    }
+}
+
+#[test]
+fn steps_go_to_the_nearest_profiled_map() {
+    let map = ball_on_floor();
+
+    // Straight down onto the floor, far from the ball.
+    let (_, profile) =
+        map.ray_intersection_profiled(vec3(8.0, 0.0, 2.0), -Vec3::Z, RayMask::CAMERA, 1.0);
+    assert_eq!(profile.objects, vec![("floor", profile.steps)]);
+
+    // Straight at the ball from nearer to it than to the floor, and then
+    // grazing past it onto the floor behind.
+    let (_, profile) =
+        map.ray_intersection_profiled(vec3(0.0, -1.8, 1.0), Vec3::Y, RayMask::CAMERA, 1.0);
+    assert_eq!(profile.objects, vec![("ball", profile.steps)]);
+
+    let direction = vec3(0.0, 1.0, -0.3).normalize();
+    let (hit, profile) =
+        map.ray_intersection_profiled(vec3(1.05, -5.0, 2.5), direction, RayMask::CAMERA, 1.0);
+    assert!(hit.unwrap().position.y > 0.0);
+    let mut names: Vec<_> = profile.objects.iter().map(|&(name, _)| name).collect();
+    names.sort_unstable();
+    assert_eq!(names, vec!["ball", "floor"]);
+    let attributed: u32 = profile.objects.iter().map(|&(_, steps)| steps).sum();
+    assert_eq!(attributed, profile.steps);
+}
+
+#[test]
+fn the_innermost_profiled_map_takes_the_steps() {
+    let map = sphere(1.0)
+        .material(WHITE)
+        .profile("inner")
+        .profile("outer");
+    let (_, profile) = map.ray_intersection_profiled(-4.0 * Vec3::X, Vec3::X, RayMask::CAMERA, 1.0);
+    assert_eq!(profile.objects, vec![("inner", profile.steps)]);
+
+    // Maps that aren't profiled take steps without taking them for anyone.
+    let map = sphere(1.0).material(WHITE);
+    let (_, profile) = map.ray_intersection_profiled(-4.0 * Vec3::X, Vec3::X, RayMask::CAMERA, 1.0);
+    assert!(profile.steps > 0);
+    assert!(profile.objects.is_empty());
+}
+
+#[test]
+fn reports_add_up_in_any_grouping() {
+    let map = ball_on_floor();
+    let profiles: Vec<_> = (0..8)
+        .map(|k| {
+            let direction = vec3(0.1 * k as f32 - 0.4, 1.0, -0.3).normalize();
+            map.ray_intersection_profiled(vec3(0.0, -5.0, 2.0), direction, RayMask::CAMERA, 1.0)
+                .1
+        })
+        .collect();
+
+    let mut whole = Report::default();
+    profiles.iter().for_each(|profile| whole.add(profile));
+
+    let (mut first, mut second) = (Report::default(), Report::default());
+    profiles[..3].iter().for_each(|profile| first.add(profile));
+    profiles[3..].iter().for_each(|profile| second.add(profile));
+
+    assert_eq!(first.merge(second), whole);
+    assert_eq!(whole.rays, 8);
+    let attributed: u64 = whole.objects.iter().map(|&(_, steps)| steps).sum();
+    assert_eq!(attributed, whole.steps);
+    assert!(whole.objects[0].1 >= whole.objects[1].1);
+}
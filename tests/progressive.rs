@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::renderer::{render, render_timed, Accumulator, RenderSettings, Scene};
+use raymarching_pathtracer::sdf::*;
+
+// A lit ball on a floor, slow enough per pass for the clock to matter.
+fn ball_scene() -> Scene {
+    let camera = Camera::new(vec3(0.0, -5.0, 1.0), Vec3::ZERO, Vec3::Z, 0.8, 1.0, 0.0);
+    let floor = plane(Vec3::Z)
+        .position(-Vec3::Z)
+        .material(Material::Lambertian { color: Vec3::ONE });
+    let ball = sphere(1.0).material(Material::Lambertian {
+        color: vec3(0.8, 0.4, 0.2),
+    });
+    let mut scene = Scene::new(camera, floor.merge(ball));
+    scene.background_color = Box::new(|_| Vec3::ONE);
+    scene
+}
+
+fn settings(sample_count: i32) -> RenderSettings {
+    RenderSettings {
+        width: 48,
+        height: 48,
+        sample_count,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn timed_renders_take_one_pass_at_least_and_sample_count_at_most() {
+    let scene = ball_scene();
+    let (image, passes) = render_timed(&settings(64), &scene, Duration::ZERO).unwrap();
+    assert_eq!(passes, 1);
+    assert!(image.iter().flatten().all(|pixel| pixel.is_finite()));
+
+    let (_, passes) = render_timed(&settings(4), &scene, Duration::from_secs(60)).unwrap();
+    assert_eq!(passes, 4);
+}
+
+#[test]
+fn timed_renders_stop_near_their_budget() {
+    let scene = ball_scene();
+    // Times a few passes, to set the budget to about twenty of them.
+    let start = Instant::now();
+    let (_, passes) = render_timed(&settings(4), &scene, Duration::from_secs(60)).unwrap();
+    let pass = start.elapsed() / passes as u32;
+    let budget = 20 * pass;
+
+    let sample_count = 1000;
+    let start = Instant::now();
+    let (_, passes) = render_timed(&settings(sample_count), &scene, budget).unwrap();
+    let elapsed = start.elapsed();
+    assert!(
+        passes > 1 && passes < sample_count as usize,
+        "{} passes in {:?}",
+        passes,
+        budget
+    );
+    // Passes vary in length, so a pass more than expected may fit in.
+    assert!(
+        elapsed < budget * 3 / 2 + Duration::from_millis(20),
+        "{:?} for a budget of {:?}",
+        elapsed,
+        budget
+    );
+}
+
+// An emissive ball against a colored sky, which renders the same every time
+// as nothing in it is sampled at random.
+fn glowing_ball_scene() -> Scene {
+    let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 0.8, 1.0, 0.0);
+    let ball = sphere(1.0).material(Material::Emissive {
+        color: vec3(1.0, 0.5, 0.2),
+        intensity: 2.0,
+    });
+    let mut scene = Scene::new(camera, ball);
+    scene.background_color = Box::new(|ray| 0.5 * (ray.direction + Vec3::ONE));
+    scene
+}
+
+fn max_difference(a: &[Vec<Vec3>], b: &[Vec<Vec3>]) -> f32 {
+    a.iter()
+        .flatten()
+        .zip(b.iter().flatten())
+        .map(|(u, v)| (*u - *v).abs().max_element())
+        .fold(0.0, f32::max)
+}
+
+#[test]
+fn accumulation_starts_over_for_a_moved_camera() {
+    let settings = settings(8);
+    let mut scene = glowing_ball_scene();
+    let mut accumulator = Accumulator::new(&settings);
+    accumulator.add_samples(&settings, &scene, 4);
+    let before = render(&settings, &scene).unwrap();
+
+    scene.camera.translate(0.7, -0.3, 0.5);
+    assert!(!accumulator.is_done(&settings, &scene));
+    while !accumulator.is_done(&settings, &scene) {
+        accumulator.add_samples(&settings, &scene, 3);
+    }
+    assert_eq!(accumulator.samples(), 8);
+
+    // The image is the new view alone, with nothing left of the old one.
+    let after = render(&settings, &scene).unwrap();
+    let mean = accumulator.mean();
+    assert!(max_difference(&mean, &after) < 1e-5);
+    assert!(max_difference(&before, &after) > 0.5);
+}
@@ -0,0 +1,45 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::sampling::*;
+
+fn assert_unit_upper(direction: Vec3, normal: Vec3) {
+    assert!(direction.is_finite(), "{}", direction);
+    assert!((direction.length() - 1.0).abs() < 1e-3, "{}", direction);
+    // Directions on the horizon may fall below it by a rounding error.
+    assert!(direction.dot(normal) > -1e-6, "{}", direction);
+}
+
+#[test]
+fn points_on_and_just_outside_the_rim_lift_to_the_horizon() {
+    let normal = vec3(0.3, -0.5, 0.8).normalize();
+    for k in 0..360 {
+        let angle = (k as f32).to_radians();
+        // Exactly on the rim, as far as rounding allows, and past it by a
+        // rounding error and by more.
+        for scale in [1.0, 1.0 + f32::EPSILON, 1.0001] {
+            let point = (scale * angle.cos(), scale * angle.sin());
+            let direction = disk_to_hemisphere(normal, point);
+            assert_unit_upper(direction, normal);
+            assert!(direction.dot(normal) < 0.02, "{}", direction);
+        }
+    }
+
+    // Points whose squared distance from the center is exactly one.
+    for point in [(1.0, 0.0), (0.0, -1.0), (0.6, 0.8), (-0.8, 0.6)] {
+        let direction = disk_to_hemisphere(Vec3::Z, point);
+        assert_eq!(direction.z, 0.0);
+        assert_unit_upper(direction, Vec3::Z);
+    }
+}
+
+#[test]
+fn edges_of_the_square_give_finite_directions() {
+    let edge = [0.0, 1e-7, 0.5, 1.0 - f32::EPSILON, 1.0];
+    for u in edge {
+        for v in edge {
+            let (direction, pdf) = cos_weighted_hemisphere_pdf(Vec3::Z, (u, v));
+            assert_unit_upper(direction, Vec3::Z);
+            assert!(pdf.is_finite() && pdf >= 0.0, "{}", pdf);
+            assert_eq!(direction, cos_weighted_hemisphere(Vec3::Z, (u, v)));
+        }
+    }
+}
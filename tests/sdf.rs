@@ -0,0 +1,470 @@
+use glam::{vec2, vec3, Vec3};
+use raymarching_pathtracer::sdf::*;
+use raymarching_pathtracer::union_all;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const WHITE: Material = Material::Lambertian { color: Vec3::ONE };
+
+// A unit sphere that counts how many of its copies have been dropped.
+#[derive(Clone)]
+struct Counted(&'static AtomicUsize);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl SdfMap for Counted {
+    fn dist(&self, p: Vec3, _mask: RayMask) -> f32 {
+        p.length() - 1.0
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        DistInfo {
+            distance: self.dist(p, mask),
+            material: WHITE,
+        }
+    }
+}
+
+// A unit sphere with bounds and a gradient that counts its evaluations.
+#[derive(Clone)]
+struct Ball(Vec3, &'static AtomicUsize);
+
+impl SdfMap for Ball {
+    fn dist(&self, p: Vec3, _mask: RayMask) -> f32 {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        (p - self.0).length() - 1.0
+    }
+
+    fn distinfo(&self, p: Vec3, mask: RayMask) -> DistInfo {
+        DistInfo {
+            distance: self.dist(p, mask),
+            material: WHITE,
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        Some((self.0 - Vec3::ONE, self.0 + Vec3::ONE))
+    }
+
+    fn grad(&self, p: Vec3, _mask: RayMask) -> Option<Vec3> {
+        Some((p - self.0).normalize())
+    }
+}
+
+#[test]
+fn shared_maps_are_freed_with_their_last_reference() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    let shared = Counted(&DROPS).share();
+    // The original is dropped at once, the shared copy only with the last
+    // placement of it.
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+    let placed = shared
+        .position(vec3(-2.0, 0.0, 0.0))
+        .merge(shared.position(vec3(2.0, 0.0, 0.0)));
+    drop(shared);
+    assert!(placed.dist(vec3(2.0, 0.0, 0.0), RayMask::ALL) < 0.0);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+    drop(placed);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn poly_capsules_own_their_points() {
+    // The points are gone by the time the tube is used.
+    let tube = {
+        let points: Vec<Vec3> = (0..5)
+            .map(|i| vec3(i as f32, (i % 2) as f32, 0.0))
+            .collect();
+        poly_capsule(points, 0.1, 0.0)
+    };
+    let copy = tube.clone();
+
+    for i in 0..=40 {
+        let x = i as f32 / 10.0;
+        let y = if x.floor() as i32 % 2 == 0 {
+            x.fract()
+        } else {
+            1.0 - x.fract()
+        };
+        assert!(tube.dist(vec3(x, y, 0.0)) < 0.0);
+        assert_eq!(copy.dist(vec3(x, y, 0.3)), tube.dist(vec3(x, y, 0.3)));
+    }
+}
+
+#[test]
+fn analytic_normals_match_finite_differences() {
+    let map = sphere(1.0)
+        .material(WHITE)
+        .merge(
+            torus(1.0, 0.3)
+                .rotate(vec3(1.0, 1.0, 0.0).normalize(), 0.7)
+                .scale(0.8)
+                .position(vec3(3.0, 0.0, 0.5))
+                .material(WHITE),
+        )
+        .merge(plane(Vec3::Z).position(-1.0 * Vec3::Z).material(WHITE));
+
+    // Central differences of the field, which agree with the gradient up to
+    // the curvature over the stencil.
+    let differences = |p: Vec3| {
+        let h = 1e-3;
+        let d = |dp: Vec3| map.dist(p + dp, RayMask::ALL) - map.dist(p - dp, RayMask::ALL);
+        vec3(d(h * Vec3::X), d(h * Vec3::Y), d(h * Vec3::Z)).normalize()
+    };
+
+    let origin = vec3(1.0, -6.0, 3.0);
+    let mut hits = 0;
+    for i in 0..20 {
+        for j in 0..20 {
+            let target = vec3(-2.0 + 0.35 * i as f32, 0.0, -1.5 + 0.25 * j as f32);
+            let direction = (target - origin).normalize();
+            if let Some(hit) = map.ray_intersection(origin, direction, RayMask::CAMERA, 1.0) {
+                let analytic = map.grad(hit.position, RayMask::CAMERA).unwrap().normalize();
+                let numeric = differences(hit.position);
+                assert!(
+                    analytic.dot(numeric) > 0.9999,
+                    "{} against {} at {}",
+                    analytic,
+                    numeric,
+                    hit.position
+                );
+                hits += 1;
+            }
+        }
+    }
+    assert!(hits > 200);
+}
+
+#[test]
+fn union_gradients_cost_no_more_than_distances() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let ball = |x: f32| Ball(vec3(x, 0.0, 0.0), &CALLS);
+    let map = union_all![
+        ball(0.0),
+        ball(3.0),
+        ball(6.0),
+        ball(9.0),
+        ball(12.0),
+        ball(15.0),
+        ball(18.0),
+        ball(21.0),
+    ];
+
+    for x in [-1.0, 6.0, 10.0, 22.0] {
+        let p = vec3(x, 0.5, 0.0);
+        CALLS.store(0, Ordering::SeqCst);
+        map.dist(p, RayMask::ALL);
+        let dist_calls = CALLS.swap(0, Ordering::SeqCst);
+
+        let grad = map.grad(p, RayMask::ALL).unwrap();
+        let grad_calls = CALLS.load(Ordering::SeqCst);
+        let nearest = vec3(3.0 * ((x / 3.0).round().clamp(0.0, 7.0)), 0.0, 0.0);
+        assert!((grad - (p - nearest).normalize()).length() < 1e-5);
+        assert!(
+            grad_calls <= dist_calls,
+            "{} evaluations for the gradient at x = {}, {} for the distance",
+            grad_calls,
+            x,
+            dist_calls
+        );
+    }
+}
+
+#[test]
+fn sphere_grid_is_negative_inside_and_positive_at_the_corners() {
+    // A sphere off center, so that a mixed-up order of the axes shows.
+    let center = vec3(0.5, 0.0, -1.0);
+    let ball = sphere(1.0).position(center);
+    let (min, max) = (Vec3::splat(-2.0), Vec3::splat(2.0));
+    let [nx, ny, nz] = [9, 7, 5];
+    let grid = ball.sample_grid((min, max), [nx, ny, nz]);
+    assert_eq!(grid.len(), nx * ny * nz);
+
+    let spacing = (max - min) / vec3(nx as f32 - 1.0, ny as f32 - 1.0, nz as f32 - 1.0);
+    let index = |i: usize, j: usize, k: usize| i + nx * (j + ny * k);
+    for k in 0..nz {
+        for j in 0..ny {
+            for i in 0..nx {
+                let p = min + spacing * vec3(i as f32, j as f32, k as f32);
+                let value = grid[index(i, j, k)];
+                assert!((value - (p.distance(center) - 1.0)).abs() < 1e-5);
+            }
+        }
+    }
+
+    // The grid point at the center of the sphere is the deepest inside, and
+    // the corners of the box are all outside.
+    assert_eq!(grid[index(5, 3, 1)], -1.0);
+    for corner in [0, nx - 1, nx * (ny - 1), nx * ny * nz - 1] {
+        assert!(grid[corner] > 0.0);
+    }
+    // Inside are the center, its neighbors along x and y and the four points
+    // diagonally between those. The neighbors along z are on the surface.
+    let inside = grid.iter().filter(|&&d| d < 0.0).count();
+    assert_eq!(inside, 9);
+}
+
+#[test]
+fn bounded_unions_match_the_exhaustive_union_with_fewer_evaluations() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    // Twenty balls on a 5 by 4 grid, three units apart.
+    let centers: Vec<Vec3> = (0..20)
+        .map(|i| vec3(3.0 * (i % 5) as f32, 3.0 * (i / 5) as f32, 0.0))
+        .collect();
+    let ball = |i: usize| Ball(centers[i], &CALLS);
+    let map = union_all![
+        ball(0),
+        ball(1),
+        ball(2),
+        ball(3),
+        ball(4),
+        ball(5),
+        ball(6),
+        ball(7),
+        ball(8),
+        ball(9),
+        ball(10),
+        ball(11),
+        ball(12),
+        ball(13),
+        ball(14),
+        ball(15),
+        ball(16),
+        ball(17),
+        ball(18),
+        ball(19),
+    ];
+
+    // Points spread irregularly over the grid and around it.
+    let fraction = |x: f32| x - x.floor();
+    let queries = 10_000;
+    CALLS.store(0, Ordering::SeqCst);
+    for i in 0..queries {
+        let i = i as f32;
+        let p = vec3(
+            -2.0 + 16.0 * fraction(0.618_034 * i),
+            -2.0 + 13.0 * fraction(0.754_877_7 * i),
+            -2.0 + 4.0 * fraction(0.569_840_3 * i),
+        );
+        let exhaustive = centers
+            .iter()
+            .map(|&center| (p - center).length() - 1.0)
+            .fold(f32::INFINITY, f32::min);
+        assert_eq!(map.dist(p, RayMask::ALL), exhaustive);
+    }
+
+    let per_query = CALLS.load(Ordering::SeqCst) as f32 / queries as f32;
+    assert!(per_query < 5.0, "{} evaluations per query", per_query);
+}
+
+// A field that counts its evaluations.
+#[derive(Clone)]
+struct CountedSdf<S>(S, &'static AtomicUsize);
+
+impl<S: Sdf> Sdf for CountedSdf<S> {
+    fn dist(&self, p: Vec3) -> f32 {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.dist(p)
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.0.bounds()
+    }
+}
+
+#[test]
+fn differences_skip_cuts_that_cannot_reach_the_point() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    // A cube with a ball cut out of the middle of each face.
+    let cube = cuboid(Vec3::ONE);
+    let faces = [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z];
+    let cut = |i: usize| CountedSdf(sphere(0.5).position(faces[i]), &CALLS);
+    let carved = CountedSdf(cube, &CALLS)
+        .subtract(cut(0))
+        .subtract(cut(1))
+        .subtract(cut(2))
+        .subtract(cut(3))
+        .subtract(cut(4))
+        .subtract(cut(5));
+
+    let fraction = |x: f32| x - x.floor();
+    let queries = 10_000;
+    CALLS.store(0, Ordering::SeqCst);
+    for i in 0..queries {
+        let i = i as f32;
+        let p =
+            4.0 * vec3(
+                fraction(0.618_034 * i),
+                fraction(0.754_877_7 * i),
+                fraction(0.569_840_3 * i),
+            ) - Vec3::splat(2.0);
+        let full = faces
+            .iter()
+            .map(|&face| -((p - face).length() - 0.5))
+            .fold(cube.dist(p), f32::max);
+        assert_eq!(carved.dist(p), full);
+    }
+
+    let per_query = CALLS.load(Ordering::SeqCst) as f32 / queries as f32;
+    assert!(per_query < 3.0, "{} evaluations per query", per_query);
+}
+
+#[test]
+fn disks_and_rectangles_measure_past_their_edges() {
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+
+    // Over the disk the distance is the height, and past its rim the
+    // distance to the rim, straight out or at a slant.
+    let disk = disk(1.0);
+    assert!(close(disk.dist(vec3(0.3, 0.2, 0.7)), 0.7));
+    assert!(close(disk.dist(vec3(0.3, 0.2, -0.7)), 0.7));
+    assert!(close(disk.dist(vec3(3.0, 0.0, 0.0)), 2.0));
+    assert!(close(disk.dist(vec3(0.0, -2.0, 0.5)), 1.0f32.hypot(0.5)));
+    assert!(close(disk.dist(vec3(0.6, 0.8, 0.0)), 0.0));
+
+    // Past an edge of the rectangle the distance is to that edge, and past
+    // a corner to the corner.
+    let rectangle = rectangle(vec2(1.0, 0.5));
+    assert!(close(rectangle.dist(vec3(0.5, 0.2, -0.3)), 0.3));
+    assert!(close(rectangle.dist(vec3(3.0, 0.0, 0.0)), 2.0));
+    assert!(close(rectangle.dist(vec3(0.0, -1.5, 0.0)), 1.0));
+    assert!(close(
+        rectangle.dist(vec3(0.2, 1.5, 2.0)),
+        1.0f32.hypot(2.0)
+    ));
+    assert!(close(rectangle.dist(vec3(-2.0, 1.5, 0.0)), 2.0f32.sqrt()));
+}
+
+#[test]
+fn euler_rotations_yaw_then_pitch_then_roll() {
+    use std::f32::consts::FRAC_PI_2;
+
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+    let points = [
+        vec3(0.3, -1.2, 0.7),
+        vec3(2.0, 0.1, -0.4),
+        vec3(-0.5, 0.9, 1.6),
+    ];
+
+    // No angles leave the object as it is.
+    let object = cuboid(vec3(0.2, 0.5, 1.0)).position(vec3(0.4, 0.0, 0.1));
+    let same = object.rotate_euler(0.0, 0.0, 0.0);
+    for p in points {
+        assert!(close(same.dist(p), object.dist(p)), "{}", p);
+    }
+
+    // Each angle on its own turns a ball off its axis about the right axis.
+    let ball = |p: Vec3| sphere(0.1).position(p);
+    let yawed = ball(Vec3::X).rotate_euler(FRAC_PI_2, 0.0, 0.0);
+    assert!(close(yawed.dist(Vec3::Y), -0.1));
+    let pitched = ball(Vec3::X).rotate_euler(0.0, FRAC_PI_2, 0.0);
+    assert!(close(pitched.dist(-Vec3::Z), -0.1));
+    let rolled = ball(Vec3::Y).rotate_euler(0.0, 0.0, FRAC_PI_2);
+    assert!(close(rolled.dist(Vec3::Z), -0.1));
+
+    // Together, yaw turns the axes that pitch and roll then turn about,
+    // which is rolling, pitching and yawing about the fixed axes in turn.
+    let (yaw, pitch, roll) = (0.7, -0.4, 1.1);
+    let euler = object.rotate_euler(yaw, pitch, roll);
+    let in_turn = object
+        .rotate(Vec3::X, roll)
+        .rotate(Vec3::Y, pitch)
+        .rotate(Vec3::Z, yaw);
+    for p in points {
+        assert!(close(euler.dist(p), in_turn.dist(p)), "{}", p);
+    }
+}
+
+#[test]
+fn clipped_fields_match_inside_and_underestimate_outside() {
+    let bounds = (Vec3::splat(-1.2), Vec3::splat(1.2));
+    let inside = |p: Vec3| p.abs().max_element() <= 1.2;
+    // Slightly off the axes, where the Mandelbulb divides by zero.
+    let grid = (0..13 * 13 * 13).map(|n| {
+        let (i, j, k) = (n % 13, n / 13 % 13, n / 169);
+        vec3(i as f32, j as f32, k as f32) * 0.5 - 3.0 + vec3(0.013, 0.021, 0.017)
+    });
+
+    // Inside the box the field is the original one.
+    let clipped = Mandelbulb.clip(bounds);
+    for p in grid.clone().filter(|&p| inside(p)) {
+        assert_eq!(clipped.dist(p), Mandelbulb.dist(p), "{}", p);
+    }
+
+    // Outside it, the distance to the box is no more than the distance to
+    // the surface within, so marching by it can't step past the surface.
+    let ball = sphere(1.0);
+    let clipped = ball.clip(bounds);
+    let mut outside = 0;
+    for p in grid.filter(|&p| !inside(p)) {
+        assert!(clipped.dist(p) <= ball.dist(p) + 1e-6, "{}", p);
+        assert!(clipped.dist(p) > 0.0, "{}", p);
+        outside += 1;
+    }
+    assert!(outside > 1000);
+}
+
+#[test]
+fn half_period_offsets_shift_the_copies_by_half_a_cell() {
+    let period = vec3(2.0, 3.0, 4.0);
+    let ball = sphere(0.3);
+    let plain = ball.repeat(period);
+    let shifted = ball.repeat_offset(period, 0.5 * period);
+
+    // The copies move from the corners of the cells to their centers.
+    for cell in [vec3(0.0, 0.0, 0.0), vec3(1.0, -2.0, 3.0)] {
+        let corner = cell * period;
+        let center = corner + 0.5 * period;
+        assert!((plain.dist(corner) + 0.3).abs() < 1e-5);
+        assert!((shifted.dist(center) + 0.3).abs() < 1e-5);
+        assert!(plain.dist(center) > 0.5 && shifted.dist(corner) > 0.5);
+    }
+
+    // Everywhere else the field is the same, moved along.
+    for p in [
+        vec3(0.4, -0.7, 1.9),
+        vec3(-3.1, 2.2, 0.3),
+        vec3(5.0, 5.0, -5.0),
+    ] {
+        let moved = shifted.dist(p + 0.5 * period);
+        assert!((moved - plain.dist(p)).abs() < 1e-5, "{}", p);
+    }
+}
+
+#[test]
+fn bores_show_the_interior_material_and_the_outside_keeps_its_own() {
+    let lambertian = |color: Vec3| Material::Lambertian { color };
+    let color = |material: Material| match material {
+        Material::Lambertian { color } => color,
+        other => panic!("{:?}", other),
+    };
+    let (outside, drill, interior) = (Vec3::X, Vec3::Y, Vec3::Z);
+
+    // A block with a round hole drilled through it from top to bottom.
+    let block = cuboid(Vec3::ONE).material(lambertian(outside));
+    let hole = poly_capsule(&[vec3(0.0, 0.0, -2.0), vec3(0.0, 0.0, 2.0)][..], 0.4, 0.0)
+        .material(lambertian(drill));
+    let bored = block.subtract_with_interior(hole, lambertian(interior));
+
+    for wall in [
+        vec3(0.4, 0.0, 0.0),
+        vec3(0.0, -0.4, 0.5),
+        vec3(-0.28, 0.28, -0.9),
+    ] {
+        assert!(bored.dist(wall, RayMask::ALL).abs() < 1e-2, "{}", wall);
+        assert_eq!(color(bored.material_at(wall)), interior, "{}", wall);
+    }
+    for face in [
+        vec3(1.0, 0.0, 0.0),
+        vec3(0.5, 1.0, 0.3),
+        vec3(0.6, 0.0, 1.0),
+    ] {
+        assert!(bored.dist(face, RayMask::ALL).abs() < 1e-6, "{}", face);
+        assert_eq!(color(bored.material_at(face)), outside, "{}", face);
+    }
+}
@@ -0,0 +1,120 @@
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::light::Light;
+use raymarching_pathtracer::renderer::{render_shadow_catcher, RenderSettings, Scene};
+use raymarching_pathtracer::sdf::*;
+
+const SIZE: i32 = 61;
+const CENTER: Vec3 = glam::const_vec3!([0.0, 0.0, 1.0]);
+const RADIUS: f32 = 0.5;
+
+// A ball floating over the ground under a sun 45 degrees up, seen from
+// straight above, with a dim sky.
+fn ball_scene() -> Scene {
+    let camera = Camera::new(
+        vec3(-0.5, 0.0, 6.0),
+        vec3(-0.5, 0.0, 0.0),
+        Vec3::Y,
+        0.5,
+        1.0,
+        0.0,
+    );
+    let ball = sphere(RADIUS)
+        .position(CENTER)
+        .material(Material::Lambertian { color: Vec3::ONE });
+    let mut scene = Scene::new(camera, ball);
+    scene.background_color = Box::new(|_| Vec3::splat(0.1));
+    scene.lights = vec![Light::Directional {
+        direction: vec3(1.0, 0.0, 1.0).normalize(),
+        cos_radius: 0.9999,
+        radiance: Vec3::splat(1e4),
+    }];
+    scene
+}
+
+#[test]
+fn shadow_density_follows_the_shadow_of_a_ball() {
+    let scene = ball_scene();
+    let settings = RenderSettings {
+        width: SIZE,
+        height: SIZE,
+        sample_count: 64,
+        ..Default::default()
+    };
+    let ground = plane(Vec3::Z).material(Material::Lambertian { color: Vec3::ONE });
+    let layer = render_shadow_catcher(&settings, &scene, &ground).unwrap();
+
+    let (mut shadowed, mut lit, mut covered) = (0, 0, 0);
+    for (i, row) in layer.iter().enumerate() {
+        for (j, pixel) in row.iter().enumerate() {
+            assert_eq!(pixel.truncate(), Vec3::ZERO);
+            let x = (j as f32 + 0.5) / SIZE as f32 - 0.5;
+            let y = 0.5 - (i as f32 + 0.5) / SIZE as f32;
+            let ray = scene.camera.get_ray(x, y, (0.0, 0.0));
+
+            // Where the camera sees the ball, the ball's own layer shows.
+            let to_center = CENTER - ray.origin;
+            let along = to_center.dot(ray.direction);
+            let miss = (to_center - along * ray.direction).length() - RADIUS;
+            if miss < -0.05 {
+                assert_eq!(pixel.w, 0.0, "({}, {})", i, j);
+                covered += 1;
+                continue;
+            }
+
+            // The shadow of the ball on the ground is an ellipse around
+            // (-1, 0), stretched to 0.5 / cos 45 degrees along x.
+            let ground = ray.origin - ray.origin.z / ray.direction.z * ray.direction;
+            let r = ((ground.x + 1.0) / (RADIUS * 2f32.sqrt())).hypot(ground.y / RADIUS);
+            if miss < 0.05 || (r - 1.0).abs() < 0.1 {
+                continue;
+            }
+            let expected = if r < 1.0 { 1.0 } else { 0.0 };
+            assert!(
+                (pixel.w - expected).abs() < 0.1,
+                "({}, {}): {} where {} was expected",
+                i,
+                j,
+                pixel.w,
+                expected
+            );
+            if r < 1.0 {
+                shadowed += 1;
+            } else {
+                lit += 1;
+            }
+        }
+    }
+    assert!(shadowed > 50 && lit > 50 && covered > 50);
+
+    // Open ground far from the ball is barely darkened, only by the little
+    // of the sky that the ball hides.
+    for corner in [layer[0][0], layer[SIZE as usize - 1][SIZE as usize - 1]] {
+        assert!(corner.w < 0.01, "{}", corner.w);
+    }
+}
+
+#[test]
+fn supersampled_shadows_average_the_sub_pixels() {
+    let scene = ball_scene();
+    let ground = plane(Vec3::Z).material(Material::Lambertian { color: Vec3::ONE });
+    let settings = |ssaa, sample_count| RenderSettings {
+        width: 21,
+        height: 21,
+        sample_count,
+        ssaa,
+        ..Default::default()
+    };
+    let plain = render_shadow_catcher(&settings(1, 64), &scene, &ground).unwrap();
+    let supersampled = render_shadow_catcher(&settings(2, 16), &scene, &ground).unwrap();
+
+    assert_eq!((supersampled.len(), supersampled[0].len()), (21, 21));
+    let total =
+        |layer: &[Vec<glam::Vec4>]| layer.iter().flatten().map(|pixel| pixel.w).sum::<f32>();
+    assert!(
+        (total(&supersampled) / total(&plain) - 1.0).abs() < 0.05,
+        "{} against {}",
+        total(&supersampled),
+        total(&plain)
+    );
+}
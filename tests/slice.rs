@@ -0,0 +1,84 @@
+use glam::{vec2, Vec3};
+use raymarching_pathtracer::error::RenderError;
+use raymarching_pathtracer::renderer::{render_slice, SlicePlane};
+use raymarching_pathtracer::sdf::*;
+
+const WHITE: Material = Material::Lambertian { color: Vec3::ONE };
+const SIZE: i32 = 96;
+const HALF_EXTENT: f32 = 1.2;
+
+// The in-plane coordinates of the pixels drawn as the surface, in white.
+fn contour(map: &dyn SdfMap, plane: SlicePlane) -> Vec<(f32, f32)> {
+    let bounds = ((-HALF_EXTENT, -HALF_EXTENT), (HALF_EXTENT, HALF_EXTENT));
+    let image = render_slice(map, plane, bounds, SIZE, SIZE).unwrap();
+    let pixel = |k: usize| -HALF_EXTENT + 2.0 * HALF_EXTENT * (k as f32 + 0.5) / SIZE as f32;
+
+    let mut points = Vec::new();
+    for (i, row) in image.iter().enumerate() {
+        for (j, color) in row.iter().enumerate() {
+            if color.min_element() > 0.9 {
+                points.push((pixel(j), -pixel(i)));
+            }
+        }
+    }
+    points
+}
+
+#[test]
+fn sphere_slices_to_a_circle_of_the_right_radius() {
+    // A unit sphere cut 0.6 off its center leaves a circle of radius 0.8.
+    let points = contour(&sphere(1.0).material(WHITE), SlicePlane::Xy { z: 0.6 });
+    let pixel_size = 2.0 * HALF_EXTENT / SIZE as f32;
+
+    assert!(!points.is_empty());
+    for &(u, v) in &points {
+        assert!((vec2(u, v).length() - 0.8).abs() < 1.5 * pixel_size);
+    }
+    // The contour goes all the way round.
+    let mut octants = [false; 8];
+    for &(u, v) in &points {
+        let angle = v.atan2(u) + std::f32::consts::PI;
+        octants[((angle / std::f32::consts::FRAC_PI_4) as usize).min(7)] = true;
+    }
+    assert!(octants.iter().all(|&seen| seen));
+}
+
+#[test]
+fn objects_hidden_from_rays_are_sliced_too() {
+    let hidden = sphere(1.0).material(WHITE).ray_mask(RayMask::SHADOW);
+    let visible = sphere(1.0).material(WHITE);
+    let plane = SlicePlane::Xz { y: 0.0 };
+    assert_eq!(contour(&hidden, plane), contour(&visible, plane));
+}
+
+#[test]
+fn single_pixel_slices_are_finite() {
+    let map = sphere(1.0).material(WHITE);
+    for (width, height) in [(1, 1), (1, 8), (8, 1)] {
+        let image = render_slice(
+            &map,
+            SlicePlane::Yz { x: 0.0 },
+            ((-1.0, -1.0), (1.0, 1.0)),
+            width,
+            height,
+        )
+        .unwrap();
+        assert!(image.iter().flatten().all(|pixel| pixel.is_finite()));
+    }
+}
+
+#[test]
+fn empty_slices_are_rejected() {
+    let map = sphere(1.0).material(WHITE);
+    let slice = |bounds, width, height| {
+        let result = render_slice(&map, SlicePlane::Xy { z: 0.0 }, bounds, width, height);
+        matches!(result, Err(RenderError::InvalidSettings(_)))
+    };
+    let square = ((-1.0, -1.0), (1.0, 1.0));
+    assert!(slice(square, 0, 8));
+    assert!(slice(square, 8, 0));
+    assert!(slice(((1.0, -1.0), (-1.0, 1.0)), 8, 8));
+    assert!(slice(((-1.0, 1.0), (1.0, 1.0)), 8, 8));
+    assert!(slice(((-1.0, -1.0), (f32::INFINITY, 1.0)), 8, 8));
+    assert!(!slice(square, 8, 8));
+}
@@ -0,0 +1,31 @@
+#![cfg(feature = "spectral")]
+
+use glam::{vec3, Vec3};
+use raymarching_pathtracer::camera::Camera;
+use raymarching_pathtracer::renderer::{Accumulator, RenderSettings, Scene};
+use raymarching_pathtracer::sdf::*;
+
+// A progressive render stopped after its first sample per pixel still sees
+// the whole spectrum, so a white sky comes out roughly white, not violet.
+#[test]
+fn first_pass_of_a_spectral_render_is_not_hue_shifted() {
+    let camera = Camera::new(vec3(0.0, -5.0, 0.0), Vec3::ZERO, Vec3::Z, 0.5, 1.0, 0.0);
+    let white = Material::Lambertian { color: Vec3::ONE };
+    let mut scene = Scene::new(
+        camera,
+        sphere(0.1).position(-10.0 * Vec3::Y).material(white),
+    );
+    scene.background_color = Box::new(|_| Vec3::ONE);
+    let settings = RenderSettings {
+        width: 32,
+        height: 32,
+        sample_count: 64,
+        ..Default::default()
+    };
+
+    let mut accumulator = Accumulator::new(&settings);
+    accumulator.add_samples(&settings, &scene, 1);
+    let mean = accumulator.mean().iter().flatten().sum::<Vec3>() / (32.0 * 32.0);
+
+    assert!((mean - Vec3::ONE).abs().max_element() < 0.3, "{}", mean);
+}
@@ -0,0 +1,56 @@
+use glam::{vec2, Vec2, Vec3};
+use raymarching_pathtracer::texture::{Image, Wrap};
+
+// Two texels side by side, black on the left and white on the right, or
+// stacked with black on top and white below.
+fn halves(wrap: Wrap, across: bool) -> Image {
+    let (width, height) = if across { (2, 1) } else { (1, 2) };
+    Image::from_fn(width, height, |x, y| Vec3::splat((x + y) as f32)).wrap(wrap)
+}
+
+fn gray(image: &Image, uv: Vec2) -> f32 {
+    let color = image.sample(uv);
+    assert_eq!(color, Vec3::splat(color.x));
+    color.x
+}
+
+#[test]
+fn repeated_textures_blend_across_their_edges() {
+    let image = halves(Wrap::Repeat, true);
+    // At the left and right edges the texel centers on either side are the
+    // two halves, and past an edge the image starts over.
+    assert_eq!(gray(&image, vec2(0.0, 0.5)), 0.5);
+    assert_eq!(gray(&image, vec2(1.0, 0.5)), 0.5);
+    assert_eq!(gray(&image, vec2(1.25, 0.5)), gray(&image, vec2(0.25, 0.5)));
+    assert_eq!(
+        gray(&image, vec2(-0.25, 0.5)),
+        gray(&image, vec2(0.75, 0.5))
+    );
+    assert_eq!(gray(&image, vec2(0.25, 0.5)), 0.0);
+    assert_eq!(gray(&image, vec2(0.75, 0.5)), 1.0);
+
+    // The same up and down, with v running up from the bottom.
+    let image = halves(Wrap::Repeat, false);
+    assert_eq!(gray(&image, vec2(0.5, 0.0)), 0.5);
+    assert_eq!(gray(&image, vec2(0.5, 1.0)), 0.5);
+    assert_eq!(gray(&image, vec2(0.5, 0.25)), 1.0);
+    assert_eq!(gray(&image, vec2(0.5, 1.25)), 1.0);
+}
+
+#[test]
+fn clamped_textures_extend_their_edge_texels() {
+    let image = halves(Wrap::Clamp, true);
+    for u in [-3.0, -0.25, 0.0, 0.25] {
+        assert_eq!(gray(&image, vec2(u, 0.5)), 0.0, "{}", u);
+    }
+    for u in [0.75, 1.0, 1.25, 4.0] {
+        assert_eq!(gray(&image, vec2(u, 0.5)), 1.0, "{}", u);
+    }
+    assert_eq!(gray(&image, vec2(0.5, 0.5)), 0.5);
+
+    let image = halves(Wrap::Clamp, false);
+    assert_eq!(gray(&image, vec2(0.5, 1.0)), 0.0);
+    assert_eq!(gray(&image, vec2(0.5, 2.0)), 0.0);
+    assert_eq!(gray(&image, vec2(0.5, 0.0)), 1.0);
+    assert_eq!(gray(&image, vec2(0.5, -1.0)), 1.0);
+}
@@ -0,0 +1,59 @@
+use glam::{const_vec3, vec3, Vec3};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use raymarching_pathtracer::sampling::RngSampler;
+use raymarching_pathtracer::sdf::*;
+use raymarching_pathtracer::validation::validate_sdf;
+
+// A unit sphere with space squashed along z but the distance left as it is,
+// the mistake of scaling a field without scaling the distance back.
+#[derive(Clone)]
+struct Squashed(f32);
+
+impl Sdf for Squashed {
+    fn dist(&self, p: Vec3) -> f32 {
+        vec3(p.x, p.y, self.0 * p.z).length() - 1.0
+    }
+}
+
+const BOUNDS: (Vec3, Vec3) = (
+    const_vec3!([-2.0, -2.0, -2.0]),
+    const_vec3!([2.0, 2.0, 2.0]),
+);
+
+fn seeded(seed: u64) -> RngSampler<StdRng> {
+    RngSampler(StdRng::seed_from_u64(seed))
+}
+
+#[test]
+fn spheres_pass_and_squashed_ones_fail() {
+    for sphere in [sphere(1.0), sphere(0.5)] {
+        let report = validate_sdf(&sphere, BOUNDS, 10_000, &mut seeded(1));
+        assert!(report.is_lipschitz(), "{:?}", report);
+    }
+    let report = validate_sdf(&sphere(1.0).scale(3.0), BOUNDS, 10_000, &mut seeded(1));
+    assert!(report.is_lipschitz(), "{:?}", report);
+
+    // Away from the z axis the field changes four times as fast as the
+    // distance along z.
+    let report = validate_sdf(&Squashed(4.0), BOUNDS, 10_000, &mut seeded(1));
+    assert!(!report.is_lipschitz());
+    assert!(
+        report.worst_ratio > 3.5 && report.worst_ratio <= 4.0 + 1e-3,
+        "{:?}",
+        report
+    );
+    let ratio = (Squashed(4.0).dist(report.a) - Squashed(4.0).dist(report.b)).abs()
+        / (report.a - report.b).length();
+    assert_eq!(ratio, report.worst_ratio);
+}
+
+#[test]
+fn seeded_validation_gives_the_same_report() {
+    let first = validate_sdf(&Squashed(4.0), BOUNDS, 1000, &mut seeded(7));
+    let second = validate_sdf(&Squashed(4.0), BOUNDS, 1000, &mut seeded(7));
+    assert_eq!(
+        (first.worst_ratio, first.a, first.b),
+        (second.worst_ratio, second.a, second.b)
+    );
+}